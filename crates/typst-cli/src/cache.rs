@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use ecow::eco_format;
+use typst::diag::{bail, StrResult};
+use typst::syntax::{PackageSpec, VirtualPath};
+
+use crate::args::{CacheCommand, CacheSubcommand};
+use crate::package::{
+    contents_checksum, package_cache_dir, subdirs, PackageManifest, CHECKSUM_FILE,
+};
+use crate::set_failed;
+
+/// Execute a cache management command.
+pub fn cache(command: &CacheCommand) -> StrResult<()> {
+    let Some(root) = package_cache_dir() else {
+        return Err("failed to locate the cache directory".into());
+    };
+
+    match &command.command {
+        CacheSubcommand::List => list(&root),
+        CacheSubcommand::Clean => clean(&root),
+        CacheSubcommand::Prune { older_than } => prune(&root, *older_than),
+        CacheSubcommand::Verify { fix } => verify(&root, *fix),
+    }
+}
+
+/// Print all cached packages along with their size.
+fn list(root: &Path) -> StrResult<()> {
+    let mut total = 0;
+    for entry in entries(root)? {
+        let size = dir_size(&entry.path);
+        total += size;
+        println!("{} ({})", entry.name, format_size(size));
+    }
+    println!("total: {} in {}", format_size(total), root.display());
+    Ok(())
+}
+
+/// Remove all cached packages.
+///
+/// Only the `<namespace>/<name>/<version>` directories of packages are
+/// removed, along with the directories that become empty through this. Other
+/// files in the cache directory are kept, as the directory may be configured
+/// to one that is shared with other data.
+fn clean(root: &Path) -> StrResult<()> {
+    for entry in entries(root)? {
+        fs::remove_dir_all(&entry.path).map_err(|err| {
+            eco_format!("failed to remove {} ({err})", entry.path.display())
+        })?;
+
+        // Fails if the directories aren't empty yet, which is fine.
+        for dir in entry.path.ancestors().skip(1).take(2) {
+            fs::remove_dir(dir).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Remove cached packages that were last modified before `age` ago.
+fn prune(root: &Path, age: Duration) -> StrResult<()> {
+    let cutoff = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+    for entry in entries(root)? {
+        if entry.modified.map_or(true, |modified| modified >= cutoff) {
+            continue;
+        }
+
+        fs::remove_dir_all(&entry.path).map_err(|err| {
+            eco_format!("failed to remove {} ({err})", entry.path.display())
+        })?;
+        println!("removed {}", entry.name);
+    }
+    Ok(())
+}
+
+/// Check all cached packages and report (or remove) the broken ones.
+fn verify(root: &Path, fix: bool) -> StrResult<()> {
+    let mut broken = 0;
+    for entry in entries(root)? {
+        let Err(problem) = check(&entry) else { continue };
+        broken += 1;
+        if fix {
+            fs::remove_dir_all(&entry.path).map_err(|err| {
+                eco_format!("failed to remove {} ({err})", entry.path.display())
+            })?;
+            println!("removed {} ({problem})", entry.name);
+        } else {
+            println!("{}: {problem}", entry.name);
+        }
+    }
+
+    if broken > 0 && !fix {
+        set_failed();
+        println!("{broken} broken package(s), run with `--fix` to remove them");
+    }
+
+    Ok(())
+}
+
+/// Check that a cached package has a manifest matching its location, an
+/// existing entrypoint, and the contents it was downloaded with.
+fn check(entry: &Entry) -> StrResult<()> {
+    let spec: PackageSpec = entry.name.parse()?;
+    let bytes = fs::read(entry.path.join("typst.toml"))
+        .map_err(|err| eco_format!("failed to read package manifest ({err})"))?;
+    let manifest = PackageManifest::parse(&bytes)?;
+    manifest.validate(&spec)?;
+
+    let entrypoint = &manifest.package.entrypoint;
+    match VirtualPath::new(entrypoint.as_str()).resolve(&entry.path) {
+        Some(path) if path.is_file() => {}
+        _ => bail!("package entrypoint `{entrypoint}` is missing"),
+    }
+
+    // Packages downloaded before checksums were recorded can't be checked.
+    let Ok(expected) = fs::read_to_string(entry.path.join(CHECKSUM_FILE)) else {
+        return Ok(());
+    };
+    let actual = contents_checksum(&entry.path)
+        .map_err(|err| eco_format!("failed to read package contents ({err})"))?;
+    if expected.trim() != actual {
+        bail!("package contents were modified after downloading");
+    }
+
+    Ok(())
+}
+
+/// A package version in the cache.
+struct Entry {
+    /// The package specification in `@namespace/name:version` form.
+    name: String,
+    /// The directory the package is unpacked in.
+    path: PathBuf,
+    /// When the package directory was last modified.
+    modified: Option<SystemTime>,
+}
+
+/// Collect all package versions in the cache, sorted by name.
+fn entries(root: &Path) -> StrResult<Vec<Entry>> {
+    let mut entries = vec![];
+    if !root.exists() {
+        return Ok(entries);
+    }
+
+    for namespace in subdirs(root) {
+        for name in subdirs(&namespace) {
+            for version in subdirs(&name) {
+                let modified = fs::metadata(&version).and_then(|m| m.modified()).ok();
+                entries.push(Entry {
+                    name: format!(
+                        "@{}/{}:{}",
+                        file_name(&namespace),
+                        file_name(&name),
+                        file_name(&version),
+                    ),
+                    path: version,
+                    modified,
+                });
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// The last component of a path as a string.
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into()
+}
+
+/// The total size of all files in a directory, in bytes.
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(read) = fs::read_dir(dir) else { return 0 };
+    read.filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Format a size in bytes with a binary unit.
+fn format_size(size: u64) -> String {
+    const KI: f64 = 1024.0;
+    const MI: f64 = KI * KI;
+    const GI: f64 = KI * KI * KI;
+
+    let size = size as f64;
+    if size >= GI {
+        format!("{:.1} GiB", size / GI)
+    } else if size >= MI {
+        format!("{:.1} MiB", size / MI)
+    } else if size >= KI {
+        format!("{:.1} KiB", size / KI)
+    } else {
+        format!("{size} B")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_keeps_other_files() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let package = root.join("preview/example/0.1.0");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("lib.typ"), "").unwrap();
+        fs::create_dir_all(root.join("preview/other/0.2.0")).unwrap();
+        fs::write(root.join("notes.txt"), "keep").unwrap();
+        fs::create_dir_all(root.join("unrelated")).unwrap();
+        fs::write(root.join("unrelated/data"), "keep").unwrap();
+
+        clean(root).unwrap();
+        assert!(!root.join("preview").exists());
+        assert!(root.join("notes.txt").exists());
+        assert!(root.join("unrelated/data").exists());
+    }
+}
@@ -1,80 +1,227 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
-use chrono::{Datelike, Timelike};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use codespan_reporting::term::{self, termcolor};
 use ecow::{eco_format, EcoString};
-use parking_lot::RwLock;
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use termcolor::{ColorChoice, StandardStream};
-use typst::diag::{bail, At, Severity, SourceDiagnostic, StrResult};
+use typst::diag::{
+    bail, At, Severity, SourceDiagnostic, SourceResult, StrResult, WarningCategory,
+};
 use typst::eval::Tracer;
-use typst::foundations::Datetime;
+use typst::foundations::Timezone;
 use typst::layout::Frame;
 use typst::model::Document;
 use typst::syntax::{FileId, Source, Span};
 use typst::visualize::Color;
 use typst::{World, WorldExt};
 
-use crate::args::{CompileCommand, DiagnosticFormat, OutputFormat};
+use crate::args::{
+    CompileCommand, DeniedWarnings, DiagnosticFormat, OutputFormat, PdfStandard, Rgba,
+    SharedArgs, SvgText,
+};
+use crate::limits::enforce;
+use crate::package::file_url;
+use crate::prefetch::prefetch_packages;
+use crate::text::{self, TextFormat};
 use crate::timings::Timer;
 use crate::watch::Status;
 use crate::world::SystemWorld;
-use crate::{color_stream, set_failed};
+use crate::{color_stream, set_denied, set_failed, set_warned};
 
 type CodespanResult<T> = Result<T, CodespanError>;
 type CodespanError = codespan_reporting::files::Error;
 
+/// The diagnostics collected for the SARIF log, if diagnostics were printed
+/// as SARIF since the log was last written.
+static SARIF: Lazy<Mutex<Option<Vec<JsonDiagnostic>>>> = Lazy::new(|| Mutex::new(None));
+
 impl CompileCommand {
-    /// The output path.
-    pub fn output(&self) -> PathBuf {
-        self.output.clone().unwrap_or_else(|| {
-            self.common.input.with_extension(
-                match self.output_format().unwrap_or(OutputFormat::Pdf) {
-                    OutputFormat::Pdf => "pdf",
-                    OutputFormat::Png => "png",
-                    OutputFormat::Svg => "svg",
-                },
-            )
+    /// The indices of the document's pages that should be exported.
+    pub fn selected_pages(&self, document: &Document) -> StrResult<Vec<usize>> {
+        let selected: Vec<usize> = (0..document.pages.len())
+            .filter(|i| self.pages.as_ref().map_or(true, |pages| pages.includes(i + 1)))
+            .collect();
+        if selected.is_empty() {
+            bail!("none of the selected pages exist in the document");
+        }
+        Ok(selected)
+    }
+
+    /// How exported PDFs are encrypted, if at all.
+    ///
+    /// Without an owner password, the user password grants full access.
+    pub fn pdf_encryption(&self) -> Option<typst_pdf::PdfEncryption> {
+        let user = self.pdf_user_password.clone();
+        let owner = self.pdf_owner_password.clone().or_else(|| user.clone())?;
+        Some(typst_pdf::PdfEncryption {
+            owner_password: owner,
+            user_password: user.unwrap_or_default(),
+            printing: !self.pdf_no_printing,
+            copying: !self.pdf_no_copying,
         })
     }
 
-    /// The format to use for generated output, either specified by the user or inferred from the extension.
+    /// Whether a warning makes the compilation fail.
+    pub fn denies(&self, category: Option<WarningCategory>) -> bool {
+        self.deny.iter().any(|denied| match denied {
+            DeniedWarnings::Warnings => true,
+            DeniedWarnings::Markup => category == Some(WarningCategory::Markup),
+            DeniedWarnings::Imports => category == Some(WarningCategory::Imports),
+            DeniedWarnings::Layout => category == Some(WarningCategory::Layout),
+            DeniedWarnings::Deprecated => category == Some(WarningCategory::Deprecated),
+        })
+    }
+
+    /// The output paths of all formats.
+    ///
+    /// When exporting multiple pages as images, these are the templates that
+    /// the page numbers are filled into.
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        self.output_formats()
+            .unwrap_or_else(|_| vec![OutputFormat::Pdf])
+            .into_iter()
+            .map(|format| self.output_for(format))
+            .collect()
+    }
+
+    /// The output path for a format.
+    ///
+    /// When exporting to multiple formats, the output path (or by default, the
+    /// input path) only provides the name and the extension is replaced.
+    pub fn output_for(&self, format: OutputFormat) -> PathBuf {
+        match &self.output {
+            Some(output) if self.format.len() <= 1 => output.clone(),
+            output => output
+                .as_ref()
+                .unwrap_or(&self.common.input)
+                .with_extension(format.extension()),
+        }
+    }
+
+    /// Whether output paths are derived from the input or from a name rather
+    /// than given exactly by the user.
+    fn output_derived(&self) -> bool {
+        self.output.is_none() || self.format.len() > 1
+    }
+
+    /// The formats to use for generated output, either specified by the user or
+    /// inferred from the extension.
     ///
     /// Will return `Err` if the format was not specified and could not be inferred.
-    pub fn output_format(&self) -> StrResult<OutputFormat> {
-        Ok(if let Some(specified) = self.format {
-            specified
+    pub fn output_formats(&self) -> StrResult<Vec<OutputFormat>> {
+        Ok(if !self.format.is_empty() {
+            let mut formats = self.format.clone();
+            formats.sort();
+            formats.dedup();
+            formats
         } else if let Some(output) = &self.output {
-            match output.extension() {
+            vec![match output.extension() {
                 Some(ext) if ext.eq_ignore_ascii_case("pdf") => OutputFormat::Pdf,
                 Some(ext) if ext.eq_ignore_ascii_case("png") => OutputFormat::Png,
                 Some(ext) if ext.eq_ignore_ascii_case("svg") => OutputFormat::Svg,
+                Some(ext) if ext.eq_ignore_ascii_case("txt") => OutputFormat::Txt,
+                Some(ext) if ext.eq_ignore_ascii_case("md") => OutputFormat::Md,
                 _ => bail!("could not infer output format for path {}.\nconsider providing the format manually with `--format/-f`", output.display()),
-            }
+            }]
         } else {
-            OutputFormat::Pdf
+            vec![OutputFormat::Pdf]
         })
     }
 }
 
 /// Execute a compilation command.
 pub fn compile(mut timer: Timer, mut command: CompileCommand) -> StrResult<()> {
-    let mut world = SystemWorld::new(&command.common)?;
-    timer.record(&mut world, |world| compile_once(world, &mut command, false))??;
+    if command.common.reads_stdin() && command.output.is_none() {
+        bail!("an output path must be specified when reading from stdin");
+    }
+
+    if is_pattern(&command.common.input) {
+        return compile_batch(timer, command);
+    }
+
+    let mut world = SystemWorld::new_mapped(&command.common, crate::ARGS.mmap_threshold)?;
+    timer.record(&mut world, |world| {
+        prefetch_packages(world);
+        compile_once(world, &mut command, false)
+    })??;
+    Ok(())
+}
+
+/// Whether the input is a glob pattern rather than a path.
+fn is_pattern(input: &Path) -> bool {
+    !input.exists() && input.to_str().is_some_and(|input| input.contains(['*', '?', '[']))
+}
+
+/// Compile all input files matching a glob pattern.
+///
+/// The files share one world, so that fonts are only searched once and
+/// packages and files they have in common are only loaded once.
+fn compile_batch(mut timer: Timer, command: CompileCommand) -> StrResult<()> {
+    let pattern = command.common.input.to_string_lossy();
+    let inputs: Vec<PathBuf> = glob::glob(&pattern)
+        .map_err(|err| eco_format!("input pattern is invalid ({err})"))?
+        .filter_map(Result::ok)
+        .collect();
+
+    if inputs.is_empty() {
+        bail!("no input files match {pattern}");
+    }
+
+    let named = |path: &PathBuf| path.to_string_lossy().contains("{name}");
+    if inputs.len() > 1 && command.output.as_ref().is_some_and(|path| !named(path)) {
+        bail!("cannot compile multiple files without `{{name}}` in output path");
+    }
+
+    let mut shared: Option<SystemWorld> = None;
+    for input in inputs {
+        let mut command = command.clone();
+        let name = input.file_stem().unwrap_or_default().to_string_lossy();
+        let fill = |path: &Path| path.to_string_lossy().replace("{name}", &name).into();
+        command.output = command.output.as_deref().map(fill);
+        command.make_deps = command.make_deps.as_deref().map(fill);
+        command.deps_json = command.deps_json.as_deref().map(fill);
+        command.common.input = input;
+
+        let world = match &mut shared {
+            Some(world) => {
+                world.set_input(&command.common)?;
+                world
+            }
+            None => shared.insert(SystemWorld::new_mapped(
+                &command.common,
+                crate::ARGS.mmap_threshold,
+            )?),
+        };
+
+        timer.record(world, |world| {
+            prefetch_packages(world);
+            compile_once(world, &mut command, false)
+        })??;
+    }
+
     Ok(())
 }
 
 /// Compile a single time.
 ///
-/// Returns whether it compiled without errors.
+/// Returns the output files that were written, which are none if the
+/// compilation failed.
 #[typst_macros::time(name = "compile once")]
 pub fn compile_once(
     world: &mut SystemWorld,
     command: &mut CompileCommand,
     watching: bool,
-) -> StrResult<()> {
+) -> StrResult<Vec<PathBuf>> {
     let start = std::time::Instant::now();
     if watching {
         Status::Compiling.print(command).unwrap();
@@ -90,19 +237,28 @@ pub fn compile_once(
         print_diagnostics(world, &errors, &[], command.common.diagnostic_format)
             .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
 
-        return Ok(());
+        return Ok(vec![]);
     }
 
     let mut tracer = Tracer::new();
-    let result = typst::compile(world, &mut tracer);
-    let warnings = tracer.warnings();
+    let result = enforce(|| typst::compile(world, &mut tracer));
+    let warnings = categorized_warnings(world, tracer, result.is_ok());
+    let (result, warnings, denied) = deny_warnings(command, result, warnings);
+    if denied {
+        set_denied();
+    }
+
+    // Export the PDF / PNG.
+    let result = result.and_then(|document| export(world, &document, command, watching));
 
     match result {
-        // Export the PDF / PNG.
-        Ok(document) => {
-            export(world, &document, command, watching)?;
+        Ok(outputs) => {
             let duration = start.elapsed();
 
+            if !command.deny.is_empty() && !warnings.is_empty() {
+                set_warned();
+            }
+
             if watching {
                 if warnings.is_empty() {
                     Status::Success(duration).print(command).unwrap();
@@ -115,13 +271,19 @@ pub fn compile_once(
                 .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
 
             if let Some(open) = command.open.take() {
-                open_file(open.as_deref(), &command.output())?;
+                if let Some(output) = outputs.first() {
+                    open_file(open.as_deref(), output)?;
+                }
             }
+
+            Ok(outputs)
         }
 
         // Print diagnostics.
         Err(errors) => {
-            set_failed();
+            if !denied {
+                set_failed();
+            }
 
             if watching {
                 Status::Error.print(command).unwrap();
@@ -134,55 +296,293 @@ pub fn compile_once(
                 command.common.diagnostic_format,
             )
             .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
+
+            Ok(vec![])
         }
     }
+}
 
-    Ok(())
+/// Collect the warnings of a compilation along with their categories.
+///
+/// After a successful compilation, the packages the document used are
+/// recorded in the lockfile, so that a warning about that can be denied like
+/// the others.
+pub fn categorized_warnings(
+    world: &mut SystemWorld,
+    tracer: Tracer,
+    succeeded: bool,
+) -> Vec<(SourceDiagnostic, Option<WarningCategory>)> {
+    let mut warnings: Vec<_> = tracer.categorized_warnings().collect();
+    warnings.extend(
+        world
+            .package_warnings()
+            .into_iter()
+            .map(|warning| (warning, Some(WarningCategory::Deprecated))),
+    );
+    if succeeded {
+        warnings.extend(world.record_packages().map(|warning| (warning, None)));
+    }
+    warnings
+}
+
+/// Promote the warnings that the command denies to errors if they are all
+/// that went wrong.
+///
+/// Returns the result, the remaining warnings, and whether any warnings were
+/// denied.
+pub fn deny_warnings<T>(
+    command: &CompileCommand,
+    result: SourceResult<T>,
+    warnings: Vec<(SourceDiagnostic, Option<WarningCategory>)>,
+) -> (SourceResult<T>, Vec<SourceDiagnostic>, bool) {
+    let denied =
+        result.is_ok() && warnings.iter().any(|&(_, category)| command.denies(category));
+    if !denied {
+        let warnings = warnings.into_iter().map(|(warning, _)| warning).collect();
+        return (result, warnings, false);
+    }
+
+    let (denied, allowed): (Vec<_>, Vec<_>) = warnings
+        .into_iter()
+        .partition(|&(_, category)| command.denies(category));
+    let errors = denied
+        .into_iter()
+        .map(|(warning, _)| SourceDiagnostic { severity: Severity::Error, ..warning })
+        .collect();
+    let warnings = allowed.into_iter().map(|(warning, _)| warning).collect();
+    (Err(errors), warnings, true)
 }
 
-/// Export into the target format.
-fn export(
+/// Export into the target formats and write the requested dependency files.
+///
+/// Returns the output files, with one file per page for images.
+///
+/// Fails with errors pointing into the document if it can't be exported in
+/// the requested way, for instance because it violates a PDF standard.
+#[typst_macros::time(name = "export")]
+pub fn export(
     world: &mut SystemWorld,
     document: &Document,
     command: &CompileCommand,
     watching: bool,
-) -> StrResult<()> {
-    match command.output_format()? {
-        OutputFormat::Png => {
-            export_image(world, document, command, watching, ImageExportFormat::Png)
+) -> SourceResult<Vec<PathBuf>> {
+    let document = &*with_metadata(document, command);
+
+    // All formats are exported from the same layout.
+    let mut outputs = vec![];
+    for format in command.output_formats().at(Span::detached())? {
+        let output = command.output_for(format);
+        match format {
+            OutputFormat::Png => outputs.extend(
+                export_image(
+                    world,
+                    document,
+                    command,
+                    &output,
+                    watching,
+                    ImageExportFormat::Png,
+                )
+                .at(Span::detached())?,
+            ),
+            OutputFormat::Svg => outputs.extend(
+                export_image(
+                    world,
+                    document,
+                    command,
+                    &output,
+                    watching,
+                    ImageExportFormat::Svg,
+                )
+                .at(Span::detached())?,
+            ),
+            OutputFormat::Pdf => {
+                export_pdf(document, command, &output, world)?;
+                outputs.push(output);
+            }
+            OutputFormat::Txt => {
+                export_text(document, command, &output, TextFormat::Plain)
+                    .at(Span::detached())?;
+                outputs.push(output);
+            }
+            OutputFormat::Md => {
+                export_text(document, command, &output, TextFormat::Markdown)
+                    .at(Span::detached())?;
+                outputs.push(output);
+            }
         }
-        OutputFormat::Svg => {
-            export_image(world, document, command, watching, ImageExportFormat::Svg)
+    }
+
+    write_make_deps(world, command, &outputs).at(Span::detached())?;
+    write_deps_json(world, command, &outputs).at(Span::detached())?;
+    Ok(outputs)
+}
+
+/// Apply the metadata given on the command line to a document.
+fn with_metadata<'a>(
+    document: &'a Document,
+    command: &CompileCommand,
+) -> Cow<'a, Document> {
+    if command.title.is_none() && command.author.is_empty() && command.keywords.is_empty()
+    {
+        return Cow::Borrowed(document);
+    }
+
+    let mut document = document.clone();
+    if let Some(title) = &command.title {
+        document.title = Some(title.into());
+    }
+    if !command.author.is_empty() {
+        document.author = command.author.iter().map(Into::into).collect();
+    }
+    if !command.keywords.is_empty() {
+        document.keywords = command.keywords.iter().map(Into::into).collect();
+    }
+
+    Cow::Owned(document)
+}
+
+/// Write a Makefile rule with the outputs as the targets and all files the
+/// last compilation depended on as prerequisites, if requested.
+fn write_make_deps(
+    world: &mut SystemWorld,
+    command: &CompileCommand,
+    outputs: &[PathBuf],
+) -> StrResult<()> {
+    let Some(path) = &command.make_deps else { return Ok(()) };
+
+    let mut deps: Vec<PathBuf> =
+        world.dependencies().filter(|path| path.exists()).collect();
+    deps.sort();
+
+    let mut rule = outputs
+        .iter()
+        .map(|output| escape_make_path(output))
+        .collect::<Vec<_>>()
+        .join(" ");
+    rule.push(':');
+    for dep in &deps {
+        rule.push(' ');
+        rule.push_str(&escape_make_path(dep));
+    }
+    rule.push('\n');
+
+    fs::write(path, rule)
+        .map_err(|err| eco_format!("failed to write make dependencies ({err})"))
+}
+
+/// Write a JSON manifest describing all files the last compilation depended
+/// on, if requested.
+fn write_deps_json(
+    world: &mut SystemWorld,
+    command: &CompileCommand,
+    outputs: &[PathBuf],
+) -> StrResult<()> {
+    let Some(path) = &command.deps_json else { return Ok(()) };
+
+    let ids: Vec<FileId> = world.dependency_ids().collect();
+    let mut dependencies = vec![];
+    for id in ids {
+        // Skip files that could not be loaded.
+        let Ok(data) = world.file(id) else { continue };
+        let vpath = id.vpath().as_rooted_path().display();
+        dependencies.push(Dependency {
+            id: match id.package() {
+                Some(package) => eco_format!("{package}{vpath}"),
+                None => eco_format!("{vpath}"),
+            },
+            kind: if id.package().is_some() { "package" } else { "project" },
+            path: world.path(id).ok(),
+            sha256: Sha256::digest(&data)
+                .iter()
+                .map(|byte| eco_format!("{byte:02x}"))
+                .collect(),
+        });
+    }
+    dependencies.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let manifest = DepsManifest { outputs: outputs.to_vec(), dependencies };
+    let json = serde_json::to_string_pretty(&manifest)
+        .map_err(|err| eco_format!("failed to serialize dependencies ({err})"))?;
+    fs::write(path, json)
+        .map_err(|err| eco_format!("failed to write dependency manifest ({err})"))
+}
+
+/// The files the outputs depend on, as written by `--deps-json`.
+#[derive(Serialize)]
+struct DepsManifest {
+    /// The output files.
+    outputs: Vec<PathBuf>,
+    /// The files the compilation read.
+    dependencies: Vec<Dependency>,
+}
+
+/// A file that a compilation depended on.
+#[derive(Serialize)]
+struct Dependency {
+    /// The file's path within its project or package.
+    id: EcoString,
+    /// Whether the file belongs to the project or to a package.
+    kind: &'static str,
+    /// The file's path on the system.
+    path: Option<PathBuf>,
+    /// The hex-encoded SHA-256 hash of the file's contents.
+    sha256: EcoString,
+}
+
+/// Escape a path for use in a Makefile rule.
+fn escape_make_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' | ':' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {}
         }
-        OutputFormat::Pdf => export_pdf(document, command, world),
+        escaped.push(c);
     }
+    escaped
 }
 
 /// Export to a PDF.
 fn export_pdf(
     document: &Document,
     command: &CompileCommand,
+    output: &Path,
     world: &SystemWorld,
-) -> StrResult<()> {
-    let ident = world.input().to_string_lossy();
-    let buffer = typst_pdf::pdf(document, Some(&ident), now());
-    let output = command.output();
+) -> SourceResult<()> {
+    let pages = command.selected_pages(document).at(Span::detached())?;
+    let ident = world.input().map(|path| path.to_string_lossy());
+    let timestamp = world.now(Some(Timezone::Offset(0)));
+    let standard = match command.pdf_standard {
+        PdfStandard::V1_7 => typst_pdf::PdfStandard::Pdf17,
+        PdfStandard::A2b => typst_pdf::PdfStandard::A2b,
+    };
+    let encryption = command.pdf_encryption();
+    let buffer = typst_pdf::pdf_pages(
+        document,
+        ident.as_deref(),
+        timestamp,
+        &pages,
+        standard,
+        command.pdf_tags,
+        encryption.as_ref(),
+    )?;
     fs::write(output, buffer)
-        .map_err(|err| eco_format!("failed to write PDF file ({err})"))?;
+        .map_err(|err| eco_format!("failed to write PDF file ({err})"))
+        .at(Span::detached())?;
     Ok(())
 }
 
-/// Get the current date and time in UTC.
-fn now() -> Option<Datetime> {
-    let now = chrono::Local::now().naive_utc();
-    Datetime::from_ymd_hms(
-        now.year(),
-        now.month().try_into().ok()?,
-        now.day().try_into().ok()?,
-        now.hour().try_into().ok()?,
-        now.minute().try_into().ok()?,
-        now.second().try_into().ok()?,
-    )
+/// Export to a plain text or Markdown file.
+fn export_text(
+    document: &Document,
+    command: &CompileCommand,
+    output: &Path,
+    format: TextFormat,
+) -> StrResult<()> {
+    let pages = command.selected_pages(document)?;
+    fs::write(output, text::text(document, &pages, format))
+        .map_err(|err| eco_format!("failed to write text file ({err})"))
 }
 
 /// An image format to export in.
@@ -191,20 +591,40 @@ enum ImageExportFormat {
     Svg,
 }
 
-/// Export to one or multiple PNGs.
+impl ImageExportFormat {
+    /// The file extension of the format.
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+/// Export to one or multiple PNGs or SVGs.
+///
+/// Returns the paths of the images, also of those that were up to date.
 fn export_image(
     world: &mut SystemWorld,
     document: &Document,
     command: &CompileCommand,
+    output: &Path,
     watching: bool,
     fmt: ImageExportFormat,
-) -> StrResult<()> {
-    // Determine whether we have a `{n}` numbering.
-    let output = command.output();
-    let string = output.to_str().unwrap_or_default();
-    let numbered = string.contains("{n}");
-    if !numbered && document.pages.len() > 1 {
-        bail!("cannot export multiple images without `{{n}}` in output path");
+) -> StrResult<Vec<PathBuf>> {
+    // Determine whether we have a `{page}` or `{n}` numbering. Derived output
+    // paths are numbered automatically, like `doc-{page}.png`.
+    let pages = command.selected_pages(document)?;
+    let mut string = output.to_string_lossy().into_owned();
+    let mut numbered = string.contains("{page}") || string.contains("{n}");
+    if !numbered && pages.len() > 1 {
+        if !command.output_derived() {
+            bail!("cannot export multiple images without `{{page}}` in output path");
+        }
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = format!("{stem}-{{page}}.{}", fmt.extension());
+        string = output.with_file_name(name).to_string_lossy().into_owned();
+        numbered = true;
     }
 
     // Find a number width that accommodates all pages. For instance, the
@@ -214,79 +634,126 @@ fn export_image(
 
     let cache = world.export_cache();
 
-    // The results are collected in a `Vec<()>` which does not allocate.
-    document
-        .pages
+    pages
         .par_iter()
-        .enumerate()
-        .map(|(i, frame)| {
+        .map(|&i| {
+            let frame = &document.pages[i];
             let storage;
             let path = if numbered {
-                storage = string.replace("{n}", &format!("{:0width$}", i + 1));
+                let number = format!("{:0width$}", i + 1);
+                storage = string.replace("{page}", &number).replace("{n}", &number);
                 Path::new(&storage)
             } else {
-                output.as_path()
+                output
             };
 
             // If we are not watching, don't use the cache.
             // If the frame is in the cache, skip it.
             // If the file does not exist, always create it.
-            if watching && cache.is_cached(i, frame) && path.exists() {
-                return Ok(());
+            if watching && cache.is_cached(path, document, frame) && path.exists() {
+                return Ok(path.to_owned());
             }
 
             match fmt {
                 ImageExportFormat::Png => {
+                    let background = match command.png_background {
+                        Some(Rgba([r, g, b, a])) => Color::from_u8(r, g, b, a),
+                        None => Color::WHITE,
+                    };
                     let pixmap =
-                        typst_render::render(frame, command.ppi / 72.0, Color::WHITE);
-                    pixmap
-                        .save_png(path)
+                        typst_render::render(frame, command.ppi / 72.0, background);
+                    let png = pixmap.encode_png().map_err(|err| {
+                        eco_format!("failed to encode PNG file ({err})")
+                    })?;
+                    fs::write(path, png_with_metadata(png, document))
                         .map_err(|err| eco_format!("failed to write PNG file ({err})"))?;
                 }
                 ImageExportFormat::Svg => {
-                    let svg = typst_svg::svg(frame);
+                    let mode = match command.svg_text {
+                        SvgText::Paths => typst_svg::TextMode::Paths,
+                        SvgText::EmbedFonts => typst_svg::TextMode::EmbedFonts,
+                        SvgText::Plain => typst_svg::TextMode::Plain,
+                    };
+                    let svg = typst_svg::svg_page(document, i, mode);
                     fs::write(path, svg.as_bytes())
                         .map_err(|err| eco_format!("failed to write SVG file ({err})"))?;
                 }
             }
 
-            Ok(())
+            Ok(path.to_owned())
         })
-        .collect::<Result<Vec<()>, EcoString>>()?;
+        .collect()
+}
 
-    Ok(())
+/// Add the document's title, authors and keywords to an encoded PNG file as
+/// international text chunks.
+fn png_with_metadata(png: Vec<u8>, document: &Document) -> Vec<u8> {
+    let mut entries = vec![];
+    if let Some(title) = &document.title {
+        entries.push(("Title", title.to_string()));
+    }
+    if !document.author.is_empty() {
+        entries.push(("Author", document.author.join(", ")));
+    }
+    if !document.keywords.is_empty() {
+        entries.push(("Keywords", document.keywords.join(", ")));
+    }
+
+    // The chunks go right after the signature and the header chunk, which
+    // always has 13 bytes of data.
+    const HEADER_END: usize = 8 + 4 + 4 + 13 + 4;
+    if entries.is_empty() || png.len() < HEADER_END {
+        return png;
+    }
+
+    let mut out = Vec::with_capacity(png.len() + 256);
+    out.extend_from_slice(&png[..HEADER_END]);
+    for (keyword, text) in entries {
+        // Keyword, no compression, empty language tag and translated keyword.
+        let mut data = Vec::with_capacity(keyword.len() + 5 + text.len());
+        data.extend_from_slice(keyword.as_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+
+        let mut crc = flate2::Crc::new();
+        crc.update(b"iTXt");
+        crc.update(&data);
+
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(b"iTXt");
+        out.extend_from_slice(&data);
+        out.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    out.extend_from_slice(&png[HEADER_END..]);
+    out
 }
 
 /// Caches exported files so that we can avoid re-exporting them if they haven't
 /// changed.
 ///
-/// This is done by having a list of size `files.len()` that contains the hashes
-/// of the last rendered frame in each file. If a new frame is inserted, this
-/// will invalidate the rest of the cache, this is deliberate as to decrease the
-/// complexity and memory usage of such a cache.
+/// This is done by remembering the hash of the frame that was last exported to
+/// each image file.
 pub struct ExportCache {
-    /// The hashes of last compilation's frames.
-    pub cache: RwLock<Vec<u128>>,
+    /// The hashes of the frames last exported to each file.
+    pub cache: Mutex<HashMap<PathBuf, u128>>,
 }
 
 impl ExportCache {
     /// Creates a new export cache.
     pub fn new() -> Self {
-        Self { cache: RwLock::new(Vec::with_capacity(32)) }
+        Self { cache: Mutex::new(HashMap::new()) }
     }
 
-    /// Returns true if the entry is cached and appends the new hash to the
-    /// cache (for the next compilation).
-    pub fn is_cached(&self, i: usize, frame: &Frame) -> bool {
-        let hash = typst::util::hash128(frame);
-
-        let mut cache = self.cache.upgradable_read();
-        if i >= cache.len() {
-            cache.with_upgraded(|cache| cache.push(hash));
-            return false;
-        }
-
-        cache.with_upgraded(|cache| std::mem::replace(&mut cache[i], hash) == hash)
+    /// Returns true if the frame was last exported to the same file with the
+    /// same document metadata and remembers it for the next compilation.
+    pub fn is_cached(&self, path: &Path, document: &Document, frame: &Frame) -> bool {
+        let hash = typst::util::hash128(&(
+            frame,
+            &document.title,
+            &document.author,
+            &document.keywords,
+        ));
+        self.cache.lock().insert(path.to_owned(), hash) == Some(hash)
     }
 }
 
@@ -313,6 +780,21 @@ pub fn print_diagnostics(
     let mut w = match diagnostic_format {
         DiagnosticFormat::Human => color_stream(),
         DiagnosticFormat::Short => StandardStream::stderr(ColorChoice::Never),
+        DiagnosticFormat::Json => {
+            let mut w = std::io::stderr().lock();
+            for diagnostic in json_diagnostics(world, warnings.iter().chain(errors)) {
+                serde_json::to_writer(&mut w, &diagnostic)
+                    .map_err(std::io::Error::from)?;
+                writeln!(w)?;
+            }
+            return Ok(());
+        }
+        DiagnosticFormat::Sarif => {
+            // Collected into one log, see `write_sarif`.
+            let diagnostics = json_diagnostics(world, warnings.iter().chain(errors));
+            SARIF.lock().get_or_insert_with(Vec::new).extend(diagnostics);
+            return Ok(());
+        }
     };
 
     let mut config = term::Config { tab_width: 2, ..Default::default() };
@@ -351,6 +833,156 @@ pub fn print_diagnostics(
     Ok(())
 }
 
+/// A diagnostic in machine-readable form.
+#[derive(Serialize)]
+pub struct JsonDiagnostic {
+    /// Either `error` or `warning`.
+    severity: &'static str,
+    /// The diagnostic's message.
+    message: EcoString,
+    /// The file the diagnostic points into, if any. Files in packages are
+    /// prefixed with the package, like `@preview/example:0.1.0/lib.typ`.
+    file: Option<String>,
+    /// Where the file can be viewed online, if it is part of a package from
+    /// the default registry.
+    url: Option<EcoString>,
+    /// The byte range in the file that the diagnostic points to.
+    range: Option<Range<usize>>,
+    /// The 1-based line and column at which the diagnostic starts.
+    start: Option<(usize, usize)>,
+    /// The 1-based line and column at which the diagnostic ends.
+    end: Option<(usize, usize)>,
+    /// Additional hints for resolving the diagnostic.
+    hints: Vec<EcoString>,
+}
+
+/// Convert diagnostics into machine-readable form.
+pub fn json_diagnostics<'a>(
+    world: &SystemWorld,
+    diagnostics: impl IntoIterator<Item = &'a SourceDiagnostic>,
+) -> Vec<JsonDiagnostic> {
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| {
+            let id = diagnostic.span.id();
+            let range = world.range(diagnostic.span);
+            let position = |byte| {
+                let source = world.source(id?).ok()?;
+                let line = source.byte_to_line(byte)?;
+                let column = source.byte_to_column(byte)?;
+                Some((line + 1, column + 1))
+            };
+
+            JsonDiagnostic {
+                severity: match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                message: diagnostic.message.clone(),
+                file: id.and_then(|id| {
+                    codespan_reporting::files::Files::name(world, id).ok()
+                }),
+                url: id.and_then(|id| file_url(id.package()?, id.vpath())),
+                start: range.as_ref().and_then(|range| position(range.start)),
+                end: range.as_ref().and_then(|range| position(range.end)),
+                range,
+                hints: diagnostic.hints.to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Ensure that the SARIF log goes to a file if a command can't write it to
+/// stdout, because it prints its results there or never finishes.
+pub fn require_sarif_output(args: &SharedArgs, command: &str) -> StrResult<()> {
+    if args.diagnostic_format == DiagnosticFormat::Sarif && args.sarif_output.is_none() {
+        bail!("`typst {command}` requires `--sarif-output` for SARIF diagnostics");
+    }
+    Ok(())
+}
+
+/// Write the diagnostics collected since the last call as one SARIF log to
+/// the given file or stdout.
+///
+/// Does nothing unless diagnostics were printed as SARIF. This way, a command
+/// that compiles several files emits a single log with a single run.
+pub fn write_sarif(output: Option<&Path>) -> StrResult<()> {
+    let Some(diagnostics) = SARIF.lock().take() else { return Ok(()) };
+    let log = sarif(&diagnostics);
+    let result = match output {
+        Some(path) => serde_json::to_vec_pretty(&log)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(path, json)),
+        None => {
+            let mut w = io::stdout().lock();
+            serde_json::to_writer_pretty(&mut w, &log)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(w))
+        }
+    };
+    result.map_err(|err| eco_format!("failed to write SARIF log ({err})"))
+}
+
+/// Build a SARIF log from diagnostics.
+///
+/// Files in packages are referred to by their URL if it is known.
+fn sarif(diagnostics: &[JsonDiagnostic]) -> serde_json::Value {
+    let results: Vec<_> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut text = diagnostic.message.to_string();
+            for hint in &diagnostic.hints {
+                text.push_str("\nhint: ");
+                text.push_str(hint);
+            }
+
+            let uri =
+                diagnostic.url.as_ref().map(ToString::to_string).or_else(|| {
+                    diagnostic.file.as_ref().map(|file| file.replace('\\', "/"))
+                });
+            let locations: Vec<_> = uri
+                .into_iter()
+                .map(|uri| {
+                    let mut location = serde_json::json!({
+                        "physicalLocation": { "artifactLocation": { "uri": uri } }
+                    });
+                    if let (Some(start), Some(end)) = (diagnostic.start, diagnostic.end) {
+                        location["physicalLocation"]["region"] = serde_json::json!({
+                            "startLine": start.0,
+                            "startColumn": start.1,
+                            "endLine": end.0,
+                            "endColumn": end.1,
+                        });
+                    }
+                    location
+                })
+                .collect();
+
+            serde_json::json!({
+                "level": diagnostic.severity,
+                "message": { "text": text },
+                "locations": locations,
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "typst",
+                    "version": crate::typst_version(),
+                    "informationUri": "https://typst.app",
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
 /// Create a label for a span.
 fn label(world: &SystemWorld, span: Span) -> Option<Label<FileId>> {
     Some(Label::primary(span.id()?, world.range(span)?))
@@ -419,3 +1051,89 @@ impl<'a> codespan_reporting::files::Files<'a> for SystemWorld {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_deny_warnings() {
+        let command =
+            CompileCommand::try_parse_from(["compile", "main.typ", "--deny", "markup"])
+                .unwrap();
+        let warning = |message| SourceDiagnostic::warning(Span::detached(), message);
+        let warnings = || {
+            vec![
+                (warning("empty emphasis"), Some(WarningCategory::Markup)),
+                (warning("failed to update typst.lock"), None),
+            ]
+        };
+
+        let (result, rest, denied) = deny_warnings(&command, Ok(()), warnings());
+        let errors = result.unwrap_err();
+        assert!(denied);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity, Severity::Error);
+        assert_eq!(errors[0].message, "empty emphasis");
+        assert_eq!(rest.len(), 1);
+
+        // Warnings don't replace the errors of a failed compilation.
+        let failed: SourceResult<()> = Err(vec![warning("other")].into());
+        let (result, rest, denied) = deny_warnings(&command, failed, warnings());
+        assert!(!denied);
+        assert_eq!(result.unwrap_err()[0].message, "other");
+        assert_eq!(rest.len(), 2);
+    }
+
+    #[test]
+    fn test_write_sarif() {
+        let diagnostic = |severity, message: &str, file: Option<&str>| JsonDiagnostic {
+            severity,
+            message: message.into(),
+            file: file.map(Into::into),
+            url: None,
+            range: Some(0..4),
+            start: Some((1, 1)),
+            end: Some((1, 5)),
+            hints: vec!["try this".into()],
+        };
+
+        // Diagnostics of several compilations end up in one log.
+        SARIF.lock().get_or_insert_with(Vec::new).extend([
+            diagnostic("warning", "unused", Some("chapters\\a.typ")),
+            diagnostic("error", "unknown variable", None),
+        ]);
+        SARIF.lock().get_or_insert_with(Vec::new).push(diagnostic(
+            "error",
+            "failed",
+            Some("b.typ"),
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sarif");
+        write_sarif(Some(&path)).unwrap();
+        let log: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(log["version"], "2.1.0");
+        let runs = log["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0]["tool"]["driver"]["name"], "typst");
+        let results = runs[0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["level"], "warning");
+        assert_eq!(results[0]["message"]["text"], "unused\nhint: try this");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "chapters/a.typ");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["endColumn"], 5);
+        assert_eq!(results[1]["locations"].as_array().unwrap().len(), 0);
+
+        // Once written, the log starts over.
+        fs::remove_file(&path).unwrap();
+        write_sarif(Some(&path)).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,13 @@
+use std::io;
+
+use clap::CommandFactory;
+use typst::diag::StrResult;
+
+use crate::args::{CliArguments, CompletionsCommand};
+
+/// Execute a completions command.
+pub fn completions(command: &CompletionsCommand) -> StrResult<()> {
+    let mut cmd = CliArguments::command();
+    clap_complete::generate(command.shell, &mut cmd, "typst", &mut io::stdout());
+    Ok(())
+}
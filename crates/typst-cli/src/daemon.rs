@@ -0,0 +1,273 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use clap::Parser;
+use ecow::{eco_format, EcoString};
+use serde::{Deserialize, Serialize};
+use typst::diag::{bail, At, SourceDiagnostic, StrResult};
+use typst::eval::Tracer;
+use typst::syntax::Span;
+use typst::World;
+
+use crate::args::{CompileCommand, DaemonCommand, QueryCommand, SharedArgs};
+use crate::compile::{
+    categorized_warnings, deny_warnings, export, json_diagnostics, JsonDiagnostic,
+};
+use crate::limits::enforce;
+use crate::print_error;
+use crate::query::{format, retrieve};
+use crate::world::SystemWorld;
+
+/// Execute a daemon command.
+///
+/// Listens on a Unix socket for requests, one JSON object per line, and
+/// answers each with one JSON object per line:
+///
+/// ```json
+/// {"command": "compile", "args": ["main.typ", "main.pdf"]}
+/// {"command": "query", "args": ["main.typ", "<intro>", "--field", "value"]}
+/// {"command": "shutdown"}
+/// ```
+///
+/// The `args` are the same as for `typst compile` and `typst query`, with
+/// paths relative to the daemon's working directory.
+pub fn daemon(command: &DaemonCommand) -> StrResult<()> {
+    let path = &command.socket;
+
+    // Clean up a socket left behind by a daemon that didn't shut down
+    // properly, but don't interfere with a running one.
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} already exists and is not a socket", path.display());
+        }
+        if UnixStream::connect(path).is_ok() {
+            bail!("a daemon is already listening on {}", path.display());
+        }
+        fs::remove_file(path)
+            .map_err(|err| eco_format!("failed to remove stale socket ({err})"))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .map_err(|err| eco_format!("failed to listen on {} ({err})", path.display()))?;
+    eprintln!("listening on {}", path.display());
+
+    let mut daemon = Daemon { world: None };
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| daemon.serve(stream));
+        match result {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) => {
+                print_error(&eco_format!("failed to serve connection ({err})")).ok();
+            }
+        }
+    }
+
+    fs::remove_file(path).ok();
+    Ok(())
+}
+
+/// A request to the daemon.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    /// Compile a document, taking the same arguments as `typst compile`.
+    Compile { args: Vec<String> },
+    /// Query a document, taking the same arguments as `typst query`.
+    Query { args: Vec<String> },
+    /// Stop the daemon.
+    Shutdown,
+}
+
+/// The daemon's answer to a request.
+#[derive(Default, Serialize)]
+struct Response {
+    /// Whether the request was handled without errors.
+    ok: bool,
+    /// The paths of the written outputs, one per line, or the serialized query
+    /// result.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+    /// Why the request couldn't be handled, if it failed for reasons other
+    /// than errors in the document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<EcoString>,
+    /// The errors and warnings emitted during compilation.
+    diagnostics: Vec<JsonDiagnostic>,
+}
+
+impl Response {
+    /// A response for a request that failed.
+    fn error(error: EcoString) -> Self {
+        Self { error: Some(error), ..Self::default() }
+    }
+}
+
+/// The arguments that affect how a world is set up, apart from its input.
+type WorldKey = (Vec<PathBuf>, Vec<(String, String)>, Option<PathBuf>, Option<i64>);
+
+/// The state kept across requests.
+struct Daemon {
+    /// The warm world and the arguments it was created with.
+    world: Option<(WorldKey, SystemWorld)>,
+}
+
+impl Daemon {
+    /// Serve all requests on a connection.
+    ///
+    /// Returns whether the daemon should shut down.
+    fn serve(&mut self, stream: UnixStream) -> io::Result<bool> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request = serde_json::from_str(&line)
+                .map_err(|err| eco_format!("request is malformed ({err})"));
+
+            let shutdown = matches!(request, Ok(Request::Shutdown));
+            let response = match request {
+                Ok(Request::Compile { args }) => self.compile(args),
+                Ok(Request::Query { args }) => self.query(args),
+                Ok(Request::Shutdown) => Ok(Response { ok: true, ..Response::default() }),
+                Err(err) => Err(err),
+            }
+            .unwrap_or_else(Response::error);
+
+            serde_json::to_writer(&mut writer, &response)?;
+            writeln!(writer)?;
+
+            if shutdown {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Handle a compile request.
+    fn compile(&mut self, args: Vec<String>) -> StrResult<Response> {
+        let mut command: CompileCommand = parse("compile", args)?;
+        command.common.permissions.confine_by_default();
+        let world = self.world(&command.common)?;
+
+        // Check if main file can be read and opened.
+        if let Err(errors) = World::source(world, world.main()).at(Span::detached()) {
+            return Ok(failure(world, &errors, &[]));
+        }
+
+        let mut tracer = Tracer::new();
+        let result = enforce(|| typst::compile(world, &mut tracer));
+        let warnings = categorized_warnings(world, tracer, result.is_ok());
+        let (result, warnings, _) = deny_warnings(&command, result, warnings);
+
+        let response = match result {
+            Ok(document) => match export(world, &document, &command, false) {
+                Ok(outputs) => {
+                    let paths: Vec<_> = outputs
+                        .iter()
+                        .map(|output| output.display().to_string())
+                        .collect();
+                    success(world, paths.join("\n"), &warnings)
+                }
+                Err(errors) => failure(world, &errors, &warnings),
+            },
+            Err(errors) => failure(world, &errors, &warnings),
+        };
+
+        comemo::evict(10);
+        Ok(response)
+    }
+
+    /// Handle a query request.
+    fn query(&mut self, args: Vec<String>) -> StrResult<Response> {
+        let mut command: QueryCommand = parse("query", args)?;
+        command.common.permissions.confine_by_default();
+        if command.watch {
+            bail!("the daemon cannot watch queries");
+        }
+
+        let world = self.world(&command.common)?;
+
+        // Check if main file can be read and opened.
+        if let Err(errors) = World::source(world, world.main()).at(Span::detached()) {
+            return Ok(failure(world, &errors, &[]));
+        }
+
+        let mut tracer = Tracer::new();
+        let result = enforce(|| typst::compile(world, &mut tracer));
+        let mut warnings = tracer.warnings();
+        warnings.extend(world.package_warnings());
+
+        let response = match result {
+            Ok(document) => {
+                warnings.extend(world.record_packages());
+                let data = retrieve(world, &command, &document)?;
+                success(world, format(data, &command)?, &warnings)
+            }
+            Err(errors) => failure(world, &errors, &warnings),
+        };
+
+        comemo::evict(10);
+        Ok(response)
+    }
+
+    /// Prepare a world for the given arguments, reusing the warm one if it was
+    /// set up the same way.
+    fn world(&mut self, args: &SharedArgs) -> StrResult<&mut SystemWorld> {
+        if args.reads_stdin() {
+            bail!("the daemon cannot read input from stdin");
+        }
+
+        let key = (
+            args.font_paths.clone(),
+            args.inputs.clone(),
+            args.input_file.clone(),
+            args.creation_timestamp,
+        );
+
+        if self.world.as_ref().map_or(true, |(existing, _)| *existing != key) {
+            self.world = Some((key, SystemWorld::new(args)?));
+        }
+
+        let (_, world) = self.world.as_mut().unwrap();
+        world.set_input(args)?;
+        Ok(world)
+    }
+}
+
+/// Parse the arguments of a request like the arguments of the subcommand.
+fn parse<T: Parser>(name: &str, args: Vec<String>) -> StrResult<T> {
+    T::try_parse_from(std::iter::once(name.into()).chain(args))
+        .map_err(|err| err.to_string().trim().into())
+}
+
+/// A response for a successful compilation.
+fn success(
+    world: &SystemWorld,
+    result: String,
+    warnings: &[SourceDiagnostic],
+) -> Response {
+    Response {
+        ok: true,
+        result: Some(result),
+        diagnostics: json_diagnostics(world, warnings),
+        ..Response::default()
+    }
+}
+
+/// A response for a compilation that failed with errors.
+fn failure(
+    world: &SystemWorld,
+    errors: &[SourceDiagnostic],
+    warnings: &[SourceDiagnostic],
+) -> Response {
+    let mut diagnostics = json_diagnostics(world, warnings);
+    diagnostics.extend(json_diagnostics(world, errors));
+    Response { diagnostics, ..Response::default() }
+}
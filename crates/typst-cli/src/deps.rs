@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use ecow::{eco_format, EcoString};
+use serde::Serialize;
+use typst::diag::StrResult;
+use typst::eval::Tracer;
+use typst::syntax::FileId;
+use typst::World;
+
+use crate::args::DepsCommand;
+use crate::compile::{print_diagnostics, require_sarif_output};
+use crate::limits::enforce;
+use crate::prefetch::ImportWalker;
+use crate::set_failed;
+use crate::world::SystemWorld;
+
+/// Execute a dependency tree command.
+pub fn deps(command: &DepsCommand) -> StrResult<()> {
+    require_sarif_output(&command.common, "deps")?;
+    let mut world = SystemWorld::new(&command.common)?;
+    world.source(world.main()).map_err(|err| err.to_string())?;
+
+    // Compile to find all files that are actually read, including those whose
+    // paths are computed. The tree is still printed if compilation fails, as
+    // far as it is known.
+    let mut tracer = Tracer::new();
+    let result = enforce(|| typst::compile(&world, &mut tracer));
+    let errors = result.err().unwrap_or_default();
+    if !errors.is_empty() {
+        set_failed();
+    }
+    print_diagnostics(
+        &world,
+        &errors,
+        &tracer.warnings(),
+        command.common.diagnostic_format,
+    )
+    .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
+
+    let accessed: HashSet<FileId> = world.dependency_ids().collect();
+    let main = world.main();
+    let mut walker = ImportWalker::new(&world);
+    walker.visit(main);
+
+    let mut builder = TreeBuilder {
+        world: &world,
+        children: &walker.children,
+        accessed: &accessed,
+        placed: HashSet::new(),
+    };
+
+    let mut root = builder.build(main);
+
+    // Files that no literal path leads to hang off the root.
+    let mut rest: Vec<FileId> = accessed.iter().copied().collect();
+    rest.sort_by_key(|&id| describe(id));
+    for id in rest {
+        if !builder.placed.contains(&id) {
+            let node = builder.build(id);
+            root.children.push(node);
+        }
+    }
+
+    if command.json {
+        let json = serde_json::to_string_pretty(&root)
+            .map_err(|err| eco_format!("failed to serialize dependencies ({err})"))?;
+        println!("{json}");
+    } else {
+        println!("{}", root.label());
+        print_children(&root.children, "");
+    }
+
+    Ok(())
+}
+
+/// A file in the dependency tree.
+#[derive(Serialize)]
+struct Node {
+    /// The file's path within its project or package.
+    id: EcoString,
+    /// Whether the file belongs to the project or to a package.
+    kind: &'static str,
+    /// The file's path on the system.
+    path: Option<PathBuf>,
+    /// Whether the file appeared earlier in the tree, where its dependencies
+    /// are listed.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    repeated: bool,
+    /// The files this one depends on.
+    children: Vec<Node>,
+}
+
+impl Node {
+    /// How the file is shown in the printed tree.
+    fn label(&self) -> EcoString {
+        if self.repeated {
+            eco_format!("{} (*)", self.id)
+        } else {
+            self.id.clone()
+        }
+    }
+}
+
+/// Arranges the files of a compilation into a tree.
+struct TreeBuilder<'a> {
+    world: &'a SystemWorld,
+    /// The files each file leads to through literal paths.
+    children: &'a HashMap<FileId, Vec<FileId>>,
+    /// The files the compilation read.
+    accessed: &'a HashSet<FileId>,
+    /// The files that are already part of the tree.
+    placed: HashSet<FileId>,
+}
+
+impl TreeBuilder<'_> {
+    /// Build the subtree of a file. Files that are already part of the tree
+    /// are repeated without their dependencies.
+    fn build(&mut self, id: FileId) -> Node {
+        let repeated = !self.placed.insert(id);
+        let mut children = vec![];
+        if !repeated {
+            let mut seen = HashSet::new();
+            for &child in self.children.get(&id).into_iter().flatten() {
+                if self.accessed.contains(&child) && seen.insert(child) {
+                    children.push(self.build(child));
+                }
+            }
+        }
+
+        Node {
+            id: describe(id),
+            kind: if id.package().is_some() { "package" } else { "project" },
+            path: self.world.path(id).ok(),
+            repeated,
+            children,
+        }
+    }
+}
+
+/// A file's path within its project or package, prefixed with the package.
+fn describe(id: FileId) -> EcoString {
+    let path = id.vpath();
+    match id.package() {
+        Some(package) => eco_format!("{package}{}", path.as_rooted_path().display()),
+        None => eco_format!("{}", path.as_rooted_path().display()),
+    }
+}
+
+/// Print the children of a node with lines that connect them.
+fn print_children(children: &[Node], prefix: &str) {
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let (branch, indent) =
+            if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        println!("{prefix}{branch}{}", child.label());
+        print_children(&child.children, &format!("{prefix}{indent}"));
+    }
+}
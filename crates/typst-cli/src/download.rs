@@ -4,51 +4,178 @@
 
 use std::collections::VecDeque;
 use std::io::{self, ErrorKind, Read, Stderr, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use typst_timing::TimingScope;
 use ureq::Response;
 
+use crate::args::CliArguments;
+
 /// Keep track of this many download speed samples.
 const SPEED_SAMPLES: usize = 5;
 
-/// Lazily loads a custom CA certificate if present, but if there's an error
-/// loading certificate, it just uses the default configuration.
-static TLS_CONFIG: Lazy<Option<Arc<rustls::ClientConfig>>> = Lazy::new(|| {
-    crate::ARGS
-        .cert
-        .as_ref()
-        .map(|path| {
-            let file = std::fs::OpenOptions::new().read(true).open(path)?;
-            let mut buffer = std::io::BufReader::new(file);
-            let certs = rustls_pemfile::certs(&mut buffer)?;
-            let mut store = rustls::RootCertStore::empty();
+/// Lazily loads a custom CA certificate and a client certificate if present.
+/// If loading them fails, every download fails with the error.
+static TLS_CONFIG: Lazy<io::Result<Option<Arc<rustls::ClientConfig>>>> =
+    Lazy::new(|| tls_config(&crate::ARGS));
+
+/// Build a TLS configuration from the custom CA and client certificates, if
+/// any were given.
+fn tls_config(args: &CliArguments) -> io::Result<Option<Arc<rustls::ClientConfig>>> {
+    if args.cert.is_none() && args.client_cert.is_none() {
+        return Ok(None);
+    }
+
+    let mut store = rustls::RootCertStore::empty();
+    match &args.cert {
+        Some(path) => {
+            let certs = read_certs(path)
+                .map_err(|err| context(err, "failed to load CA certificate"))?;
             store.add_parsable_certificates(&certs);
-            let config = rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(store)
-                .with_no_client_auth();
-            Ok::<_, std::io::Error>(Arc::new(config))
-        })
-        .and_then(|x| x.ok())
-});
+        }
+        None => {
+            store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }))
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(store);
+
+    let config = match (&args.client_cert, &args.client_key) {
+        (Some(cert), Some(key)) => {
+            let load = || {
+                let chain =
+                    read_certs(cert)?.into_iter().map(rustls::Certificate).collect();
+                builder
+                    .with_client_auth_cert(chain, read_key(key)?)
+                    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            };
+            load().map_err(|err| context(err, "failed to load client certificate"))?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    Ok(Some(Arc::new(config)))
+}
+
+/// Prefix an error's message with what failed.
+fn context(err: io::Error, what: &str) -> io::Error {
+    io::Error::new(err.kind(), format!("{what} ({err})"))
+}
+
+/// Read all certificates from a PEM file.
+fn read_certs(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let file = std::fs::File::open(path)?;
+    rustls_pemfile::certs(&mut io::BufReader::new(file))
+}
+
+/// Read the first private key from a PEM file.
+fn read_key(path: &Path) -> io::Result<rustls::PrivateKey> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = io::BufReader::new(file);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(rustls::PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "no private key found"))
+}
+
+/// The number of downloads that are currently running.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps the progress of concurrent downloads from interleaving on standard
+/// error.
+static OUTPUT: Mutex<()> = Mutex::new(());
+
+/// The downloads that completed since their timings were last taken. Only
+/// filled while timings are recorded.
+static DOWNLOADS: Mutex<Vec<DownloadTiming>> = Mutex::new(Vec::new());
+
+/// How long a download took.
+pub struct DownloadTiming {
+    /// The downloaded URL.
+    pub url: String,
+    /// The time from sending the request until the body was read.
+    pub duration: Duration,
+    /// The size of the body.
+    pub bytes: usize,
+}
+
+/// Take the timings of the downloads that completed since the last call.
+pub fn take_download_timings() -> Vec<DownloadTiming> {
+    std::mem::take(&mut DOWNLOADS.lock())
+}
 
 /// Download binary data and display its progress.
+///
+/// If a `token` is given, it is sent as a bearer token. While several
+/// downloads run at once, each reports its progress once it completes,
+/// labelled with the name of the downloaded file.
 #[allow(clippy::result_large_err)]
-pub fn download_with_progress(url: &str) -> Result<Vec<u8>, ureq::Error> {
-    let response = download(url)?;
-    Ok(RemoteReader::from_response(response).download()?)
+pub fn download_with_progress(
+    url: &str,
+    token: Option<&str>,
+) -> Result<Vec<u8>, ureq::Error> {
+    let _scope = TimingScope::new("download", None);
+    let start = Instant::now();
+    ACTIVE.fetch_add(1, Ordering::SeqCst);
+    let result = download(url, token).and_then(|response| {
+        let name = url.rsplit('/').next().unwrap_or(url);
+        Ok(RemoteReader::from_response(response, name).download()?)
+    });
+    ACTIVE.fetch_sub(1, Ordering::SeqCst);
+    let data = result?;
+
+    if typst_timing::is_enabled() {
+        DOWNLOADS.lock().push(DownloadTiming {
+            url: url.into(),
+            duration: start.elapsed(),
+            bytes: data.len(),
+        });
+    }
+
+    Ok(data)
 }
 
 /// Download from a URL.
+///
+/// If a `token` is given, it is sent as a bearer token.
 #[allow(clippy::result_large_err)]
-pub fn download(url: &str) -> Result<ureq::Response, ureq::Error> {
+pub fn download(url: &str, token: Option<&str>) -> Result<ureq::Response, ureq::Error> {
     let mut builder = ureq::AgentBuilder::new()
         .user_agent(concat!("typst/{}", env!("CARGO_PKG_VERSION")));
 
-    // Get the network proxy config from the environment.
-    if let Some(proxy) = env_proxy::for_url_str(url)
+    // Apply the configured timeouts, keeping ureq's defaults otherwise.
+    if let Some(secs) = crate::ARGS.connect_timeout {
+        builder = builder.timeout_connect(Duration::from_secs(secs));
+    }
+    if let Some(secs) = crate::ARGS.read_timeout {
+        builder = builder
+            .timeout_read(Duration::from_secs(secs))
+            .timeout_write(Duration::from_secs(secs));
+    }
+
+    // Use the explicitly configured proxy or get the network proxy config
+    // from the environment.
+    if let Some(proxy) = &crate::ARGS.http_proxy {
+        builder = builder.proxy(ureq::Proxy::new(proxy)?);
+    } else if let Some(proxy) = env_proxy::for_url_str(url)
         .to_url()
         .and_then(|url| ureq::Proxy::new(url).ok())
     {
@@ -56,12 +183,19 @@ pub fn download(url: &str) -> Result<ureq::Response, ureq::Error> {
     }
 
     // Apply a custom CA certificate if present.
-    if let Some(config) = &*TLS_CONFIG {
-        builder = builder.tls_config(config.clone());
+    match &*TLS_CONFIG {
+        Ok(Some(config)) => builder = builder.tls_config(config.clone()),
+        Ok(None) => {}
+        Err(err) => return Err(io::Error::new(err.kind(), err.to_string()).into()),
     }
 
     let agent = builder.build();
-    agent.get(url).call()
+    let mut request = agent.get(url);
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+
+    request.call()
 }
 
 /// A wrapper around [`ureq::Response`] that reads the response body in chunks
@@ -79,6 +213,10 @@ struct RemoteReader {
     last_print: Option<Instant>,
     displayed_charcount: Option<usize>,
     stderr: Stderr,
+    /// The name of the downloaded file.
+    name: String,
+    /// Whether other downloads ran at the same time.
+    concurrent: bool,
 }
 
 impl RemoteReader {
@@ -86,7 +224,7 @@ impl RemoteReader {
     ///
     /// The 'Content-Length' header is used as a size hint for read
     /// optimization, if present.
-    pub fn from_response(response: Response) -> Self {
+    pub fn from_response(response: Response, name: &str) -> Self {
         let content_len: Option<usize> = response
             .header("Content-Length")
             .and_then(|header| header.parse().ok());
@@ -101,6 +239,8 @@ impl RemoteReader {
             last_print: None,
             displayed_charcount: None,
             stderr: io::stderr(),
+            name: name.into(),
+            concurrent: false,
         }
     }
 
@@ -143,6 +283,12 @@ impl RemoteReader {
             self.total_downloaded += read;
             self.downloaded_this_sec += read;
 
+            // Live updates of concurrent downloads would overwrite each
+            // other, so they only report once they are done.
+            if ACTIVE.load(Ordering::SeqCst) > 1 {
+                self.concurrent = true;
+            }
+
             if elapsed >= Duration::from_secs(1) {
                 if self.downloaded_last_few_secs.len() == SPEED_SAMPLES {
                     self.downloaded_last_few_secs.pop_back();
@@ -151,16 +297,26 @@ impl RemoteReader {
                 self.downloaded_last_few_secs.push_front(self.downloaded_this_sec);
                 self.downloaded_this_sec = 0;
 
-                if let Some(n) = self.displayed_charcount {
-                    self.erase_chars(n);
-                }
+                if !self.concurrent {
+                    let _guard = OUTPUT.lock();
+                    if let Some(n) = self.displayed_charcount {
+                        self.erase_chars(n);
+                    }
 
-                self.display();
-                let _ = write!(self.stderr, "\r");
+                    self.display();
+                    let _ = write!(self.stderr, "\r");
+                }
                 self.last_print = Some(Instant::now());
             }
         }
 
+        let _guard = OUTPUT.lock();
+        if self.concurrent {
+            if let Some(n) = self.displayed_charcount.take() {
+                self.erase_chars(n);
+            }
+            let _ = write!(self.stderr, "{}: ", self.name);
+        }
         self.display();
         let _ = writeln!(self.stderr);
 
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
 
+use ecow::eco_format;
 use fontdb::{Database, Source};
-use typst::diag::StrResult;
+use serde::{Deserialize, Serialize};
+use typst::diag::{bail, StrResult};
 use typst::text::{Font, FontBook, FontInfo, FontVariant};
 use typst_timing::TimingScope;
 
@@ -14,11 +19,53 @@ pub fn fonts(command: &FontsCommand) -> StrResult<()> {
     let mut searcher = FontSearcher::new();
     searcher.search(&command.font_paths);
 
-    for (name, infos) in searcher.book.families() {
-        println!("{name}");
+    // Whitespace is left to the shaper and needn't be covered.
+    let text = command.coverage.as_deref().unwrap_or_default();
+    let covers = |info: &FontInfo| {
+        text.chars()
+            .filter(|c| !c.is_whitespace())
+            .all(|c| info.coverage.contains(c as u32))
+    };
+
+    let mut families = vec![];
+    for (name, _) in searcher.book.families() {
+        let variants: Vec<FontEntry> = searcher
+            .book
+            .select_family(&name.to_lowercase())
+            .filter_map(|id| {
+                let info = searcher.book.info(id)?;
+                let slot = &searcher.fonts[id];
+                covers(info).then(|| FontEntry {
+                    variant: info.variant,
+                    // Embedded fonts have no path.
+                    path: Some(slot.path.as_path())
+                        .filter(|path| !path.as_os_str().is_empty()),
+                    index: slot.index,
+                })
+            })
+            .collect();
+
+        if !variants.is_empty() {
+            families.push(FamilyEntry { name, variants });
+        }
+    }
+
+    if command.json {
+        let json = serde_json::to_string_pretty(&families)
+            .map_err(|err| eco_format!("failed to serialize fonts ({err})"))?;
+        println!("{json}");
+        return Ok(());
+    }
+
+    if families.is_empty() && command.coverage.is_some() {
+        bail!("no font family can render all of the text");
+    }
+
+    for family in families {
+        println!("{}", family.name);
         if command.variants {
-            for info in infos {
-                let FontVariant { style, weight, stretch } = info.variant;
+            for entry in family.variants {
+                let FontVariant { style, weight, stretch } = entry.variant;
                 println!("- Style: {style:?}, Weight: {weight:?}, Stretch: {stretch:?}");
             }
         }
@@ -27,6 +74,31 @@ pub fn fonts(command: &FontsCommand) -> StrResult<()> {
     Ok(())
 }
 
+/// A font family, as listed by `typst fonts --json`.
+#[derive(Serialize)]
+struct FamilyEntry<'a> {
+    /// The family's name.
+    name: &'a str,
+    /// The fonts that are part of the family.
+    variants: Vec<FontEntry<'a>>,
+}
+
+/// A font that is part of a family.
+#[derive(Serialize)]
+struct FontEntry<'a> {
+    /// The font's style, weight and stretch.
+    #[serde(flatten)]
+    variant: FontVariant,
+    /// The file the font is loaded from, if it isn't embedded.
+    path: Option<&'a Path>,
+    /// The index of the font in its collection.
+    index: u32,
+}
+
+/// The version of the font cache's format. Must be bumped whenever the format
+/// changes.
+const FONT_CACHE_VERSION: u32 = 3;
+
 /// Searches for fonts.
 pub struct FontSearcher {
     /// Metadata about all discovered fonts.
@@ -66,17 +138,23 @@ impl FontSearcher {
     }
 
     /// Search everything that is available.
+    ///
+    /// Fonts are discovered by fontdb. The metadata Typst needs about them is
+    /// cached on disk and only computed anew for font files that were added
+    /// or changed since the last search.
     pub fn search(&mut self, font_paths: &[PathBuf]) {
         let mut db = Database::new();
-
-        // Font paths have highest priority.
-        for path in font_paths {
-            db.load_fonts_dir(path);
+        {
+            let _scope = TimingScope::new("find fonts", None);
+            for path in font_paths {
+                db.load_fonts_dir(path);
+            }
+            db.load_system_fonts();
         }
 
-        // System fonts have second priority.
-        db.load_system_fonts();
-
+        let mut cached = FontCache::load();
+        let mut cache = FontCache::default();
+        let mut changed = false;
         for face in db.faces() {
             let path = match &face.source {
                 Source::File(path) | Source::SharedFile(path, _) => path,
@@ -85,18 +163,35 @@ impl FontSearcher {
                 Source::Binary(_) => continue,
             };
 
-            let info = db
-                .with_face_data(face.id, FontInfo::new)
-                .expect("database must contain this font");
+            let Some(file) = FontFile::new(path) else { continue };
+            let font = match cached.remove(&(file.path.clone(), face.index)) {
+                Some(font) if font.file == file => font,
+                _ => {
+                    let _scope = TimingScope::new("scan font", None);
+                    changed = true;
+                    let info = db
+                        .with_face_data(face.id, FontInfo::new)
+                        .expect("database must contain this font");
+                    CachedFont { file, index: face.index, info }
+                }
+            };
 
-            if let Some(info) = info {
-                self.book.push(info);
+            if let Some(info) = &font.info {
+                self.book.push(info.clone());
                 self.fonts.push(FontSlot {
-                    path: path.clone(),
-                    index: face.index,
+                    path: font.file.path.clone(),
+                    index: font.index,
                     font: OnceLock::new(),
                 });
             }
+
+            cache.fonts.push(font);
+        }
+
+        // Also write the cache if fonts were removed, so that it doesn't grow
+        // forever.
+        if changed || !cached.is_empty() {
+            cache.store();
         }
 
         // Embedded fonts have lowest priority.
@@ -142,3 +237,103 @@ impl FontSearcher {
         add!("DejaVuSansMono-BoldOblique.ttf");
     }
 }
+
+/// Metadata about the fonts on the system, persisted across runs to avoid
+/// computing it anew on every start.
+#[derive(Serialize, Deserialize)]
+struct FontCache {
+    /// The version of the cache format.
+    version: u32,
+    /// The version of Typst that wrote the cache.
+    typst: String,
+    /// The fonts whose metadata is known.
+    fonts: Vec<CachedFont>,
+}
+
+/// A font in the cache.
+#[derive(Serialize, Deserialize)]
+struct CachedFont {
+    /// The file the font is contained in.
+    file: FontFile,
+    /// The index of the font in its collection.
+    index: u32,
+    /// The font's metadata, or `None` if Typst can't use the font.
+    info: Option<FontInfo>,
+}
+
+/// A font file, along with its size and modification time at the time its
+/// fonts were scanned.
+#[derive(PartialEq, Serialize, Deserialize)]
+struct FontFile {
+    path: PathBuf,
+    size: u64,
+    modified: Option<(u64, u32)>,
+}
+
+impl FontFile {
+    /// Read the size and modification time of a font file.
+    fn new(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| (duration.as_secs(), duration.subsec_nanos()));
+        Some(Self { path: path.into(), size: metadata.len(), modified })
+    }
+}
+
+impl Default for FontCache {
+    fn default() -> Self {
+        Self {
+            version: FONT_CACHE_VERSION,
+            typst: crate::typst_version().into(),
+            fonts: vec![],
+        }
+    }
+}
+
+impl FontCache {
+    /// Load the fonts of the cache, keyed by their path and index. Returns
+    /// nothing if there is no cache or it was written by another version.
+    fn load() -> HashMap<(PathBuf, u32), CachedFont> {
+        let _scope = TimingScope::new("load font cache", None);
+        let Some(cache) = cache_path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice::<Self>(&data).ok())
+            .filter(|cache| {
+                cache.version == FONT_CACHE_VERSION
+                    && cache.typst == crate::typst_version()
+            })
+        else {
+            return HashMap::new();
+        };
+
+        cache
+            .fonts
+            .into_iter()
+            .map(|font| ((font.file.path.clone(), font.index), font))
+            .collect()
+    }
+
+    /// Write the cache to disk. Failures are ignored since the cache is only
+    /// an optimization.
+    fn store(&self) {
+        let Some(path) = cache_path() else { return };
+        let Some(dir) = path.parent() else { return };
+        let Ok(data) = serde_json::to_vec(self) else { return };
+
+        // Write to a temporary file first and then move it into place, so that
+        // concurrent processes never read a partially written cache.
+        fs::create_dir_all(dir).ok();
+        let Ok(mut file) = tempfile::NamedTempFile::new_in(dir) else { return };
+        if file.write_all(&data).is_ok() {
+            file.persist(&path).ok();
+        }
+    }
+}
+
+/// The path of the font cache file.
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("typst").join("fonts.json"))
+}
@@ -0,0 +1,282 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use ecow::eco_format;
+use tempfile::TempDir;
+use typst::diag::{bail, StrResult};
+use typst::syntax::{PackageSpec, VirtualPath};
+
+use crate::args::InitCommand;
+use crate::download::download_with_progress;
+use crate::package::{prepare_package, PackageManifest};
+use crate::permissions::Permissions;
+
+/// Execute an initialization command.
+pub fn init(command: &InitCommand) -> StrResult<()> {
+    // Keeps a downloaded archive alive until the template is copied.
+    let mut _archive = None;
+
+    let (name, dir) = if command.template.starts_with('@') {
+        let spec: PackageSpec = command.template.parse()?;
+        let dir = prepare_package(&spec, &Permissions::all(), None)
+            .map_err(|err| eco_format!("failed to prepare package {spec} ({err})"))?;
+        (spec.name.to_string(), dir)
+    } else if command.template.starts_with("https://") {
+        let archive = download_archive(&command.template)?;
+        let dir = archive_root(archive.path());
+        _archive = Some(archive);
+        (archive_name(&command.template), dir)
+    } else {
+        bail!("template must be a package like `@preview/name:0.1.0` or an HTTPS URL");
+    };
+
+    // Templates that are packages declare where their files are. Other
+    // archives are copied as a whole.
+    let (source, entrypoint) = match read_manifest(&dir)? {
+        Some(PackageManifest { template: Some(template), .. }) => {
+            let source = VirtualPath::new(template.path.as_str())
+                .resolve(&dir)
+                .ok_or("template directory is outside of the package")?;
+            (source, Some(template.entrypoint))
+        }
+        Some(_) => bail!("{} is not a template", command.template),
+        None => (dir, None),
+    };
+
+    if !source.is_dir() {
+        bail!("template directory {} does not exist", source.display());
+    }
+
+    let target = command.dir.clone().unwrap_or_else(|| name.into());
+    if fs::read_dir(&target).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{} already exists and is not empty", target.display());
+    }
+
+    let project = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut placeholders = Placeholders {
+        title: command.title.clone(),
+        author: command.author.clone(),
+        default_title: project,
+    };
+
+    copy_dir(&source, &target, &mut placeholders)?;
+
+    eprintln!("created project in {}", target.display());
+    if let Some(entrypoint) = entrypoint {
+        eprintln!("to start writing, run `typst watch {entrypoint}` in it");
+    }
+
+    Ok(())
+}
+
+/// Download a `.tar.gz` archive and unpack it into a temporary directory.
+fn download_archive(url: &str) -> StrResult<TempDir> {
+    eprintln!("downloading {url}");
+    let data = download_with_progress(url, None)
+        .map_err(|err| eco_format!("failed to download template ({err})"))?;
+
+    let dir = tempfile::tempdir()
+        .map_err(|err| eco_format!("failed to create temporary directory ({err})"))?;
+    let decompressed = flate2::read::GzDecoder::new(data.as_slice());
+    tar::Archive::new(decompressed)
+        .unpack(dir.path())
+        .map_err(|err| eco_format!("template archive is malformed ({err})"))?;
+
+    Ok(dir)
+}
+
+/// The directory in an unpacked archive that holds its contents.
+///
+/// Archives generated by code hosting platforms wrap everything in a single
+/// top-level directory, which is skipped.
+fn archive_root(dir: &Path) -> PathBuf {
+    let entries: Vec<fs::DirEntry> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .collect();
+    match entries.as_slice() {
+        [single] if single.file_type().is_ok_and(|kind| kind.is_dir()) => single.path(),
+        _ => dir.into(),
+    }
+}
+
+/// Derive a project name from an archive URL, like `thesis` from
+/// `https://example.com/thesis.tar.gz`.
+fn archive_name(url: &str) -> String {
+    let file = url.split(['?', '#']).next().unwrap_or(url);
+    let file = file.trim_end_matches('/').rsplit('/').next().unwrap_or(file);
+    let name = file.split('.').next().unwrap_or(file);
+    if name.is_empty() {
+        "project".into()
+    } else {
+        name.into()
+    }
+}
+
+/// Read the package manifest in a directory, if there is one.
+fn read_manifest(dir: &Path) -> StrResult<Option<PackageManifest>> {
+    match fs::read(dir.join("typst.toml")) {
+        Ok(bytes) => PackageManifest::parse(&bytes).map(Some),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => bail!("failed to read package manifest ({err})"),
+    }
+}
+
+/// Copy a directory recursively, filling in placeholders in text files.
+///
+/// Templates with symbolic links are rejected, so that a template can neither
+/// pull in files from outside of it nor make the copy loop forever.
+fn copy_dir(src: &Path, dst: &Path, placeholders: &mut Placeholders) -> StrResult<()> {
+    fs::create_dir_all(dst)
+        .map_err(|err| eco_format!("failed to create {} ({err})", dst.display()))?;
+
+    let entries = fs::read_dir(src)
+        .map_err(|err| eco_format!("failed to read {} ({err})", src.display()))?;
+    for entry in entries {
+        let entry = entry.map_err(|err| eco_format!("failed to read entry ({err})"))?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let kind = entry
+            .file_type()
+            .map_err(|err| eco_format!("failed to read {} ({err})", from.display()))?;
+        if kind.is_symlink() {
+            bail!("template contains a symbolic link at {}", from.display());
+        } else if kind.is_dir() {
+            copy_dir(&from, &to, placeholders)?;
+            continue;
+        }
+
+        let bytes = fs::read(&from)
+            .map_err(|err| eco_format!("failed to read {} ({err})", from.display()))?;
+        let bytes = match String::from_utf8(bytes) {
+            Ok(text) => placeholders.fill(text)?.into_bytes(),
+            Err(err) => err.into_bytes(),
+        };
+        fs::write(&to, bytes)
+            .map_err(|err| eco_format!("failed to write {} ({err})", to.display()))?;
+    }
+
+    Ok(())
+}
+
+/// The values that replace placeholders in a template's files.
+///
+/// Values that weren't given on the command line are asked for once they are
+/// first needed.
+struct Placeholders {
+    /// The value for `{{title}}`.
+    title: Option<String>,
+    /// The value for `{{author}}`.
+    author: Option<String>,
+    /// The title to use if none is given, the project directory's name.
+    default_title: String,
+}
+
+impl Placeholders {
+    /// Replace all placeholders in a text.
+    fn fill(&mut self, mut text: String) -> StrResult<String> {
+        if text.contains("{{title}}") {
+            let default = self.default_title.clone();
+            let title = ask(&mut self.title, "title", &default)?;
+            text = text.replace("{{title}}", title);
+        }
+        if text.contains("{{author}}") {
+            let author = ask(&mut self.author, "author", "")?;
+            text = text.replace("{{author}}", author);
+        }
+
+        Ok(text)
+    }
+}
+
+/// Get the value of a placeholder, asking for it on the terminal if it isn't
+/// known yet.
+fn ask<'a>(
+    value: &'a mut Option<String>,
+    name: &str,
+    default: &str,
+) -> StrResult<&'a str> {
+    if value.is_none() {
+        let mut answer = String::new();
+        if io::stdin().is_terminal() {
+            eprint!("{name} [{default}]: ");
+            io::stderr().flush().ok();
+            io::stdin()
+                .lock()
+                .read_line(&mut answer)
+                .map_err(|err| eco_format!("failed to read {name} ({err})"))?;
+        }
+
+        let answer = answer.trim();
+        *value = Some(if answer.is_empty() { default } else { answer }.into());
+    }
+
+    Ok(value.as_deref().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_name() {
+        assert_eq!(archive_name("https://example.com/thesis.tar.gz"), "thesis");
+        assert_eq!(archive_name("https://example.com/a/report.tar.gz?v=2#x"), "report");
+        assert_eq!(archive_name("https://example.com/letter/"), "letter");
+        assert_eq!(archive_name("https://example.com/.tar.gz"), "project");
+    }
+
+    #[test]
+    fn test_archive_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("repo-main")).unwrap();
+        assert_eq!(archive_root(dir.path()), dir.path().join("repo-main"));
+
+        fs::write(dir.path().join("README.md"), "").unwrap();
+        assert_eq!(archive_root(dir.path()), dir.path());
+
+        let file = tempfile::tempdir().unwrap();
+        fs::write(file.path().join("main.typ"), "").unwrap();
+        assert_eq!(archive_root(file.path()), file.path());
+    }
+
+    #[test]
+    fn test_placeholders_fill() {
+        let mut placeholders = Placeholders {
+            title: Some("Thesis".into()),
+            author: Some("Jane".into()),
+            default_title: "project".into(),
+        };
+        let text = "= {{title}}\nby {{author}}, {{title}}".to_string();
+        assert_eq!(placeholders.fill(text).unwrap(), "= Thesis\nby Jane, Thesis");
+        assert_eq!(placeholders.fill("{{other}}".into()).unwrap(), "{{other}}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_dir_rejects_symlinks() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        fs::write(src.path().join("main.typ"), "= {{title}}").unwrap();
+        std::os::unix::fs::symlink(src.path(), src.path().join("loop")).unwrap();
+
+        let mut placeholders = Placeholders {
+            title: Some("Title".into()),
+            author: None,
+            default_title: "project".into(),
+        };
+        let target = dst.path().join("project");
+        let err = copy_dir(src.path(), &target, &mut placeholders).unwrap_err();
+        assert!(err.starts_with("template contains a symbolic link"));
+        assert!(!target.join("loop").exists());
+
+        fs::remove_file(src.path().join("loop")).unwrap();
+        copy_dir(src.path(), &target, &mut placeholders).unwrap();
+        assert_eq!(fs::read_to_string(target.join("main.typ")).unwrap(), "= Title");
+    }
+}
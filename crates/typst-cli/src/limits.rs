@@ -0,0 +1,262 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ecow::{eco_format, eco_vec};
+use typst::diag::{SourceDiagnostic, SourceResult};
+use typst::syntax::Span;
+
+/// How often the limits are checked during a compilation.
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Counts heap allocations of threads that run a compilation under limits and
+/// otherwise forwards to the system allocator.
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    /// The budget of the compilation that runs on this thread, if any.
+    ///
+    /// This is a plain pointer rather than an `Arc` so that the allocator can
+    /// access it without allocating or registering a destructor. It is only
+    /// set while [`enforce`] keeps the budget alive.
+    static BUDGET: Cell<*const Budget> = const { Cell::new(ptr::null()) };
+}
+
+/// The state of one compilation that runs under limits.
+///
+/// Each compilation has its own budget, so that concurrent compilations,
+/// e.g. of world snapshots, and unrelated work like exporting or downloading
+/// on other threads don't count against each other's limits.
+#[derive(Default)]
+struct Budget {
+    /// Whether the compilation exceeded a limit and should stop.
+    cancelled: AtomicBool,
+    /// The number of bytes allocated by the compiling thread since the
+    /// compilation started. May become negative when memory allocated before
+    /// is freed.
+    allocated: AtomicIsize,
+}
+
+/// Run a compilation under the configured time and memory limits.
+///
+/// If a compilation exceeds a limit, it is cancelled and stops the next time
+/// it accesses the world. Only this compilation fails with an error,
+/// long-running commands like `watch` keep going.
+pub fn enforce<T>(f: impl FnOnce() -> SourceResult<T>) -> SourceResult<T> {
+    let max_time = crate::ARGS.max_compile_seconds.map(Duration::from_secs);
+    enforce_with(max_time, crate::ARGS.max_memory, f)
+}
+
+/// Run a compilation under the given time and memory limits.
+///
+/// Memory is counted for the calling thread only. Work that the compilation
+/// hands off to other threads isn't counted.
+///
+/// A cancelled compilation unwinds through memoized functions. Their results
+/// are only cached once they return, so nothing is cached for the calls that
+/// were interrupted. Still, the whole memoization cache is evicted afterwards
+/// so that the next compilation doesn't build on the state of one that was
+/// aborted at an arbitrary point.
+fn enforce_with<T>(
+    max_time: Option<Duration>,
+    max_memory: Option<u64>,
+    f: impl FnOnce() -> SourceResult<T>,
+) -> SourceResult<T> {
+    if max_time.is_none() && max_memory.is_none() {
+        return f();
+    }
+
+    let start = Instant::now();
+    let budget = Arc::new(Budget::default());
+
+    let (tx, rx) = mpsc::channel::<()>();
+    let watched = budget.clone();
+    let watchdog = thread::spawn(move || loop {
+        match rx.recv_timeout(CHECK_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return None,
+        }
+
+        let mut message = None;
+        if let Some(max) = max_time.filter(|&max| start.elapsed() > max) {
+            message = Some(eco_format!(
+                "compilation exceeded the time limit of {}s",
+                max.as_secs()
+            ));
+        }
+
+        let used = watched.allocated.load(Ordering::Relaxed);
+        if let Some(max) = max_memory.filter(|&max| used > 0 && used as u64 > max) {
+            message =
+                Some(eco_format!("compilation exceeded the memory limit of {max} bytes"));
+        }
+
+        if message.is_some() {
+            watched.cancelled.store(true, Ordering::Relaxed);
+            return message;
+        }
+    });
+
+    // The budget outlives the thread-local pointer to it, as it is only
+    // dropped at the end of this function.
+    let previous = BUDGET.with(|current| current.replace(Arc::as_ptr(&budget)));
+    let output = panic::catch_unwind(AssertUnwindSafe(f));
+    BUDGET.with(|current| current.set(previous));
+    drop(tx);
+    let exceeded = watchdog.join().ok().flatten();
+
+    match (output, exceeded) {
+        (Err(payload), _) if !payload.is::<Cancelled>() => panic::resume_unwind(payload),
+        (_, Some(message)) => {
+            comemo::evict(0);
+            Err(eco_vec![SourceDiagnostic::error(Span::detached(), message)])
+        }
+        (output, None) => output.unwrap_or_else(|payload| panic::resume_unwind(payload)),
+    }
+}
+
+/// Stop the running compilation if it exceeded a limit.
+///
+/// This unwinds back to [`enforce`] without invoking the panic hook. Called
+/// by the world whenever the compilation accesses it.
+pub fn stop_if_cancelled() {
+    let cancelled = BUDGET.with(|current| {
+        let budget = current.get();
+        // Safety: The pointer is only set while the budget is alive.
+        !budget.is_null() && unsafe { (*budget).cancelled.load(Ordering::Relaxed) }
+    });
+
+    if cancelled {
+        panic::resume_unwind(Box::new(Cancelled));
+    }
+}
+
+/// The payload that a cancelled compilation unwinds with.
+struct Cancelled;
+
+/// The system allocator, counting the allocated bytes of threads that run a
+/// compilation under limits.
+struct CountingAllocator;
+
+impl CountingAllocator {
+    /// Record a change of the allocated bytes.
+    fn record(&self, delta: isize) {
+        // The thread-local may already be destroyed when a thread exits.
+        BUDGET
+            .try_with(|current| {
+                let budget = current.get();
+                if !budget.is_null() {
+                    // Safety: The pointer is only set while the budget is
+                    // alive.
+                    unsafe { (*budget).allocated.fetch_add(delta, Ordering::Relaxed) };
+                }
+            })
+            .ok();
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            self.record(new_size as isize - layout.size() as isize);
+        }
+        new
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.record(-(layout.size() as isize));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Access the world until the compilation is cancelled.
+    fn spin() -> SourceResult<()> {
+        loop {
+            stop_if_cancelled();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_enforce_time_limit() {
+        let result = enforce_with(Some(Duration::ZERO), None, spin);
+        let errors = result.unwrap_err();
+        assert_eq!(errors[0].message, "compilation exceeded the time limit of 0s");
+    }
+
+    #[test]
+    fn test_enforce_memory_limit() {
+        let result = enforce_with(None, Some(1 << 20), || -> SourceResult<()> {
+            let mut buffers = vec![];
+            loop {
+                buffers.push(vec![1u8; 1 << 16]);
+                stop_if_cancelled();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let errors = result.unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "compilation exceeded the memory limit of 1048576 bytes"
+        );
+    }
+
+    #[test]
+    fn test_enforce_counts_only_the_compiling_thread() {
+        let result = enforce_with(None, Some(1 << 20), || {
+            let buffer = thread::spawn(|| vec![1u8; 16 << 20]).join().unwrap();
+            thread::sleep(CHECK_INTERVAL * 3);
+            stop_if_cancelled();
+            Ok(buffer.len())
+        });
+        assert_eq!(result.unwrap(), 16 << 20);
+    }
+
+    #[test]
+    fn test_enforce_concurrent_compilations() {
+        let limited = thread::spawn(|| enforce_with(Some(Duration::ZERO), None, spin));
+        let unlimited = thread::spawn(|| {
+            enforce_with(Some(Duration::from_secs(60)), None, || {
+                thread::sleep(CHECK_INTERVAL * 3);
+                stop_if_cancelled();
+                Ok(())
+            })
+        });
+        assert!(limited.join().unwrap().is_err());
+        assert!(unlimited.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_enforce_without_limits() {
+        assert_eq!(enforce_with(None, None, || Ok(1)).unwrap(), 1);
+        stop_if_cancelled();
+    }
+}
@@ -0,0 +1,256 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ecow::{eco_format, EcoString};
+use serde::{Deserialize, Serialize};
+use typst::diag::{bail, StrResult};
+use typst::syntax::{PackageSpec, PackageVersion};
+
+/// The name of the lockfile in the project root.
+pub const LOCKFILE: &str = "typst.lock";
+
+/// The comment at the top of a written lockfile.
+const HEADER: &str = "# This file is generated by Typst and records the packages \
+                      the project uses.\n# It is not meant to be edited by hand.\n\n";
+
+/// The packages that a project resolved, as recorded in its `typst.lock`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// The recorded packages, sorted by their specification.
+    #[serde(default, rename = "package")]
+    packages: Vec<LockedPackage>,
+}
+
+/// A package recorded in a lockfile.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+struct LockedPackage {
+    namespace: EcoString,
+    name: EcoString,
+    version: PackageVersion,
+    /// The checksum of the package's contents, if it was downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<EcoString>,
+    /// The SHA-256 checksum of the package's archive that its registry
+    /// published when the package was updated to this version, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<EcoString>,
+}
+
+impl LockedPackage {
+    /// The key that packages are sorted by.
+    fn key(&self) -> (&str, &str, PackageVersion) {
+        (&self.namespace, &self.name, self.version)
+    }
+}
+
+impl Lockfile {
+    /// Read the lockfile of the project at `root`, if there is one.
+    pub fn read(root: &Path) -> StrResult<Option<Self>> {
+        let path = root.join(LOCKFILE);
+        let string = match fs::read_to_string(&path) {
+            Ok(string) => string,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => bail!("failed to read {} ({err})", path.display()),
+        };
+
+        let mut lockfile: Self = toml::from_str(&string)
+            .map_err(|err| eco_format!("{LOCKFILE} is malformed: {}", err.message()))?;
+        lockfile.packages.sort_by(|a, b| a.key().cmp(&b.key()));
+        lockfile.packages.dedup_by(|a, b| a.key() == b.key());
+        Ok(Some(lockfile))
+    }
+
+    /// Read the lockfile of the project at `root`, which must exist.
+    pub fn require(root: &Path) -> StrResult<Self> {
+        Self::read(root)?.ok_or_else(|| {
+            eco_format!(
+                "{LOCKFILE} is missing in {}, compile with `--lock` to create it",
+                root.display()
+            )
+        })
+    }
+
+    /// Whether the lockfile contains a package.
+    pub fn contains(&self, spec: &PackageSpec) -> bool {
+        self.find(spec).is_ok()
+    }
+
+    /// The recorded packages.
+    pub fn packages(&self) -> impl Iterator<Item = PackageSpec> + '_ {
+        self.packages.iter().map(|package| PackageSpec {
+            namespace: package.namespace.clone(),
+            name: package.name.clone(),
+            version: package.version,
+        })
+    }
+
+    /// The recorded checksum of a package's contents, if any.
+    pub fn checksum(&self, spec: &PackageSpec) -> Option<&str> {
+        let i = self.find(spec).ok()?;
+        self.packages[i].checksum.as_deref()
+    }
+
+    /// The recorded checksum of a package's archive, if any.
+    pub fn sha256(&self, spec: &PackageSpec) -> Option<&str> {
+        let i = self.find(spec).ok()?;
+        self.packages[i].sha256.as_deref()
+    }
+
+    /// Add packages and the checksums of their contents to the lockfile of
+    /// the project at `root`.
+    ///
+    /// The lockfile is only created if `create` is true. Otherwise, packages
+    /// are only recorded if the project has a lockfile already. The file is
+    /// only written if it changes. Packages that are no longer used are kept,
+    /// so that compiling several files of a project records the packages of
+    /// all of them. Delete the file to start over.
+    pub fn record(
+        root: &Path,
+        packages: impl IntoIterator<Item = (PackageSpec, Option<EcoString>)>,
+        create: bool,
+    ) -> StrResult<()> {
+        let existing = Self::read(root)?;
+        if existing.is_none() && !create {
+            return Ok(());
+        }
+
+        let mut lockfile = existing.clone().unwrap_or_default();
+        for (spec, checksum) in packages {
+            lockfile.insert(&spec, checksum);
+        }
+        if Some(&lockfile) == existing.as_ref()
+            || (existing.is_none() && lockfile.packages.is_empty())
+        {
+            return Ok(());
+        }
+
+        lockfile.write(root)
+    }
+
+    /// Replace packages in the lockfile of the project at `root` with other
+    /// versions, if the project has a lockfile.
+    ///
+    /// The new versions are recorded with the checksums of their archives,
+    /// as published by their registries. The checksums of their contents are
+    /// recorded once they are downloaded.
+    pub fn replace(
+        root: &Path,
+        changes: &[(PackageSpec, PackageSpec, Option<EcoString>)],
+    ) -> StrResult<()> {
+        let Some(mut lockfile) = Self::read(root)? else { return Ok(()) };
+        for (old, new, sha256) in changes {
+            if let Ok(i) = lockfile.find(old) {
+                lockfile.packages.remove(i);
+            }
+            lockfile.insert(new, None);
+            if let Ok(i) = lockfile.find(new) {
+                lockfile.packages[i].sha256 = sha256.clone();
+            }
+        }
+        lockfile.write(root)
+    }
+
+    /// Find the index of a package, or where it would have to be inserted.
+    fn find(&self, spec: &PackageSpec) -> Result<usize, usize> {
+        self.packages.binary_search_by(|package| {
+            package.key().cmp(&(
+                spec.namespace.as_str(),
+                spec.name.as_str(),
+                spec.version,
+            ))
+        })
+    }
+
+    /// Add a package unless it is recorded already. A recorded checksum is
+    /// kept, so that changed contents are noticed with `--locked`.
+    fn insert(&mut self, spec: &PackageSpec, checksum: Option<EcoString>) {
+        match self.find(spec) {
+            Ok(i) => {
+                let package = &mut self.packages[i];
+                if package.checksum.is_none() {
+                    package.checksum = checksum;
+                }
+            }
+            Err(i) => self.packages.insert(
+                i,
+                LockedPackage {
+                    namespace: spec.namespace.clone(),
+                    name: spec.name.clone(),
+                    version: spec.version,
+                    checksum,
+                    sha256: None,
+                },
+            ),
+        }
+    }
+
+    /// Write the lockfile into the project at `root`.
+    fn write(&self, root: &Path) -> StrResult<()> {
+        let body = toml::to_string(self)
+            .map_err(|err| eco_format!("failed to serialize {LOCKFILE} ({err})"))?;
+        let path = root.join(LOCKFILE);
+        fs::write(&path, format!("{HEADER}{body}"))
+            .map_err(|err| eco_format!("failed to write {} ({err})", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn spec(raw: &str) -> PackageSpec {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn test_lockfile_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = spec("@preview/a:0.1.0");
+        let b = spec("@preview/b:1.0.0");
+
+        // Nothing is recorded without a lockfile, unless it is created.
+        Lockfile::record(dir.path(), [(b.clone(), None)], false).unwrap();
+        assert_eq!(Lockfile::read(dir.path()).unwrap(), None);
+        Lockfile::record(dir.path(), [(b.clone(), None)], true).unwrap();
+
+        // An existing lockfile is kept up to date.
+        Lockfile::record(dir.path(), [(a.clone(), Some("abc".into()))], false).unwrap();
+        Lockfile::record(dir.path(), [(a.clone(), Some("def".into()))], false).unwrap();
+        let lockfile = Lockfile::require(dir.path()).unwrap();
+        assert!(lockfile.contains(&a));
+        assert!(lockfile.contains(&b));
+        assert!(!lockfile.contains(&spec("@preview/a:0.2.0")));
+        assert_eq!(lockfile.checksum(&a), Some("abc"));
+        assert_eq!(lockfile.checksum(&b), None);
+        assert_eq!(lockfile.packages().collect::<Vec<_>>(), [a.clone(), b.clone()]);
+
+        let string = fs::read_to_string(dir.path().join(LOCKFILE)).unwrap();
+        assert!(
+            string.find("name = \"a\"").unwrap() < string.find("name = \"b\"").unwrap()
+        );
+        assert_eq!(string.matches("checksum").count(), 1);
+    }
+
+    #[test]
+    fn test_lockfile_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = spec("@preview/a:0.1.0");
+        let new = spec("@preview/a:0.2.0");
+        Lockfile::record(dir.path(), [(old.clone(), Some("abc".into()))], true).unwrap();
+        Lockfile::replace(dir.path(), &[(old.clone(), new.clone(), Some("def".into()))])
+            .unwrap();
+
+        let lockfile = Lockfile::require(dir.path()).unwrap();
+        assert!(!lockfile.contains(&old));
+        assert_eq!(lockfile.sha256(&new), Some("def"));
+        assert_eq!(lockfile.checksum(&new), None);
+
+        // The checksum of the contents is recorded once it is known.
+        Lockfile::record(dir.path(), [(new.clone(), Some("ghi".into()))], false).unwrap();
+        let lockfile = Lockfile::require(dir.path()).unwrap();
+        assert_eq!(lockfile.sha256(&new), Some("def"));
+        assert_eq!(lockfile.checksum(&new), Some("ghi"));
+    }
+}
@@ -0,0 +1,741 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use ecow::{eco_format, EcoVec};
+use serde_json::{json, Value as Json};
+use typst::diag::{bail, SourceDiagnostic, StrResult};
+use typst::eval::Tracer;
+use typst::model::Document;
+use typst::syntax::{
+    ast::{self, AstNode},
+    FileId, LinkedNode, Source, SyntaxKind, SyntaxNode, VirtualPath,
+};
+use typst::{World, WorldExt};
+use typst_ide::{autocomplete, tooltip, CompletionKind, Tooltip};
+
+use crate::args::LspCommand;
+use crate::limits::enforce;
+use crate::package::{self, package_file};
+use crate::permissions::Permissions;
+use crate::world::SystemWorld;
+
+/// How many compilations run at the same time at most. Further edits are
+/// compiled once one of them finishes.
+const MAX_COMPILATIONS: usize = 2;
+
+/// Execute a language server command.
+///
+/// Reads messages of the Language Server Protocol from stdin and answers on
+/// stdout until the client asks the server to exit.
+pub fn lsp(command: &LspCommand) -> StrResult<()> {
+    if command.common.reads_stdin() {
+        bail!("the language server cannot read input from stdin");
+    }
+
+    let world = SystemWorld::new(&command.common)?;
+    let (events, rx) = mpsc::channel();
+
+    // Fetch the registries' indices in the background, so that completions
+    // offer their packages once they are available.
+    if Permissions::from_args(&command.common).network {
+        thread::spawn(package::fetch_indices);
+    }
+
+    // Messages are read on their own thread so that they are handled while
+    // compilations are running.
+    let input = events.clone();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            let message = receive(&mut stdin);
+            let done = !matches!(message, Ok(Some(_)));
+            if input.send(Event::Message(message)).is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let mut server = Server {
+        world,
+        events,
+        document: None,
+        dependencies: vec![],
+        published: HashSet::new(),
+        started: 0,
+        shown: 0,
+        running: 0,
+        pending: false,
+    };
+
+    for event in rx {
+        match event {
+            Event::Message(message) => {
+                let Some(message) = message
+                    .map_err(|err| eco_format!("failed to read message ({err})"))?
+                else {
+                    break;
+                };
+                let exit = server
+                    .handle(&message)
+                    .map_err(|err| eco_format!("failed to send message ({err})"))?;
+                if exit {
+                    break;
+                }
+            }
+            Event::Compiled(compiled) => server
+                .finish(*compiled)
+                .map_err(|err| eco_format!("failed to send message ({err})"))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Something the language server reacts to.
+enum Event {
+    /// A message from the client, or `None` once the input ends.
+    Message(io::Result<Option<Json>>),
+    /// A compilation that finished on another thread.
+    Compiled(Box<Compiled>),
+}
+
+/// The result of compiling a snapshot of the world.
+struct Compiled {
+    /// The number of the compilation, which increases with every edit.
+    number: u64,
+    /// The snapshot that was compiled.
+    world: SystemWorld,
+    /// The compiled document or the errors.
+    result: Result<Document, EcoVec<SourceDiagnostic>>,
+    /// The warnings of the compilation.
+    warnings: Vec<SourceDiagnostic>,
+}
+
+/// The state of the language server.
+struct Server {
+    /// The world, with the editor's unsaved changes overlaid.
+    world: SystemWorld,
+    /// Where finished compilations are sent.
+    events: Sender<Event>,
+    /// The document of the last successful compilation.
+    document: Option<Document>,
+    /// The files that the last shown compilation depended on.
+    dependencies: Vec<FileId>,
+    /// The files for which diagnostics were published last time.
+    published: HashSet<PathBuf>,
+    /// The number of the last compilation that was started.
+    started: u64,
+    /// The number of the last compilation whose results were shown.
+    shown: u64,
+    /// How many compilations are running.
+    running: usize,
+    /// Whether the world changed since the last compilation was started.
+    pending: bool,
+}
+
+impl Server {
+    /// Handle a request or notification.
+    ///
+    /// Returns whether the server should exit.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let params = &message["params"];
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => capabilities(),
+            "shutdown" => Json::Null,
+            "exit" => return Ok(true),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                self.update(&document["uri"], document["text"].as_str())?;
+                return Ok(false);
+            }
+            "textDocument/didChange" => {
+                // We request full syncing, so the last change is the whole
+                // text.
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let Some(text) = text {
+                    self.update(&params["textDocument"]["uri"], Some(text))?;
+                }
+                return Ok(false);
+            }
+            "textDocument/didSave" => {
+                self.compile()?;
+                return Ok(false);
+            }
+            "textDocument/didClose" => {
+                self.update(&params["textDocument"]["uri"], None)?;
+                return Ok(false);
+            }
+            "textDocument/completion" => self.completion(params).unwrap_or(Json::Null),
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            _ => {
+                // Answer unknown requests with an error and ignore unknown
+                // notifications.
+                if let Some(id) = message.get("id") {
+                    send(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": "method not found" },
+                    }))?;
+                }
+                return Ok(false);
+            }
+        };
+
+        send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))?;
+        Ok(false)
+    }
+
+    /// Overlay a file with the editor's contents, or remove the overlay if
+    /// `text` is `None`, and recompile.
+    fn update(&mut self, uri: &Json, text: Option<&str>) -> io::Result<()> {
+        let Some(id) = uri.as_str().and_then(|uri| self.id(uri)) else {
+            return Ok(());
+        };
+
+        match text {
+            Some(text) => self.world.overlay(id, text.into()),
+            None => {
+                self.world.remove_overlay(id);
+            }
+        }
+
+        self.compile()
+    }
+
+    /// Start compiling the main file on a snapshot of the world.
+    ///
+    /// The compilation runs on another thread, so that the server keeps
+    /// answering requests and picking up edits in the meantime. An edit made
+    /// during a compilation starts another one right away, up to a limit.
+    fn compile(&mut self) -> io::Result<()> {
+        // Files are read anew on the next access, so that requests see the
+        // latest edits.
+        self.world.reset();
+        if self.running >= MAX_COMPILATIONS {
+            self.pending = true;
+            return Ok(());
+        }
+
+        self.started += 1;
+        self.running += 1;
+        self.pending = false;
+
+        let number = self.started;
+        let world = self.world.snapshot();
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let mut tracer = Tracer::new();
+            let result = enforce(|| typst::compile(&world, &mut tracer));
+            let warnings = tracer.warnings().to_vec();
+            let compiled = Compiled { number, world, result, warnings };
+            events.send(Event::Compiled(Box::new(compiled))).ok();
+        });
+
+        Ok(())
+    }
+
+    /// Show the results of a finished compilation, unless a later one was
+    /// shown already.
+    fn finish(&mut self, compiled: Compiled) -> io::Result<()> {
+        self.running -= 1;
+        if compiled.number > self.shown {
+            self.shown = compiled.number;
+            self.world.adopt(compiled.world);
+            self.dependencies = self.world.dependency_ids().collect();
+
+            let mut diagnostics = compiled.warnings;
+            diagnostics.extend(self.world.package_warnings());
+            match compiled.result {
+                Ok(document) => {
+                    diagnostics.extend(self.world.record_packages());
+                    self.document = Some(document);
+                }
+                Err(errors) => diagnostics.extend(errors.iter().cloned()),
+            }
+
+            self.publish(&diagnostics)?;
+            self.world.reset();
+            comemo::evict(10);
+        }
+
+        if self.pending {
+            self.compile()?;
+        }
+
+        Ok(())
+    }
+
+    /// Publish diagnostics, grouped by file, and clear them for files that
+    /// don't have any anymore.
+    fn publish(&mut self, diagnostics: &[SourceDiagnostic]) -> io::Result<()> {
+        let mut files: HashMap<PathBuf, Vec<Json>> = HashMap::new();
+        for diagnostic in diagnostics {
+            // Diagnostics without a location are shown at the start of the
+            // main file.
+            let located = diagnostic.span.id().and_then(|id| {
+                let path = self.world.path(id).ok()?;
+                let source = self.world.source(id).ok()?;
+                let range = self.world.range(diagnostic.span)?;
+                Some((path, range_json(&source, range.start, range.end)?))
+            });
+            let Some((path, range)) = located.or_else(|| {
+                let path = self.world.input()?.to_path_buf();
+                Some((path, json!({ "start": origin(), "end": origin() })))
+            }) else {
+                continue;
+            };
+
+            let mut message = diagnostic.message.to_string();
+            for hint in &diagnostic.hints {
+                write!(message, "\nhint: {hint}").ok();
+            }
+
+            files.entry(path).or_default().push(json!({
+                "range": range,
+                "severity": match diagnostic.severity {
+                    typst::diag::Severity::Error => 1,
+                    typst::diag::Severity::Warning => 2,
+                },
+                "source": "typst",
+                "message": message,
+            }));
+        }
+
+        let stale: Vec<PathBuf> = self
+            .published
+            .iter()
+            .filter(|path| !files.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in stale {
+            files.insert(path, vec![]);
+        }
+
+        self.published.clear();
+        for (path, diagnostics) in files {
+            if !diagnostics.is_empty() {
+                self.published.insert(path.clone());
+            }
+
+            send(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": path_to_uri(&path), "diagnostics": diagnostics },
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    /// Provide completions at a position.
+    fn completion(&mut self, params: &Json) -> Option<Json> {
+        let (source, cursor) = self.locate(params)?;
+
+        // A trigger kind of 1 means that the completion was invoked explicitly.
+        let explicit = params["context"]["triggerKind"].as_u64() == Some(1);
+        let (from, completions) =
+            autocomplete(&self.world, self.document.as_ref(), &source, cursor, explicit)?;
+
+        let range = range_json(&source, from, cursor)?;
+        let items: Vec<Json> = completions
+            .into_iter()
+            .map(|completion| {
+                let text = match &completion.apply {
+                    Some(apply) => snippet(apply),
+                    None => escape_snippet(&completion.label),
+                };
+                json!({
+                    "label": completion.label,
+                    "kind": completion_kind(&completion.kind),
+                    "detail": completion.detail,
+                    "textEdit": { "range": range, "newText": text },
+                    "insertTextFormat": 2,
+                })
+            })
+            .collect();
+
+        Some(Json::Array(items))
+    }
+
+    /// Describe the item at a position.
+    fn hover(&mut self, params: &Json) -> Option<Json> {
+        let (source, cursor) = self.locate(params)?;
+        let value = match tooltip(&self.world, self.document.as_ref(), &source, cursor)? {
+            Tooltip::Text(text) => text.to_string(),
+            Tooltip::Code(code) => format!("```typst\n{code}\n```"),
+        };
+        Some(json!({ "contents": { "kind": "markdown", "value": value } }))
+    }
+
+    /// Find where the item at a position is defined.
+    ///
+    /// Supports variables and functions defined in the same file, labels, and
+    /// the paths of imported and included files.
+    fn definition(&mut self, params: &Json) -> Option<Json> {
+        let (source, cursor) = self.locate(params)?;
+        let leaf = LinkedNode::new(source.root()).leaf_at(cursor)?;
+
+        let (id, start, end) = match leaf.kind() {
+            SyntaxKind::Ident | SyntaxKind::MathIdent => {
+                let mut declared = vec![];
+                declarations(source.root(), &mut declared);
+                let range = declared
+                    .into_iter()
+                    .filter(|ident| ident.as_str() == leaf.text().as_str())
+                    .filter_map(|ident| source.range(ident.to_untyped().span()))
+                    .rfind(|range| range.start <= leaf.offset())?;
+                (source.id(), range.start, range.end)
+            }
+            SyntaxKind::RefMarker => {
+                let name = leaf.text().trim_start_matches('@');
+                let label = eco_format!("<{name}>");
+                self.find_label(&source, &label)?
+            }
+            SyntaxKind::Str
+                if leaf.parent_kind().is_some_and(|kind| {
+                    matches!(kind, SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude)
+                }) =>
+            {
+                let path = leaf.cast::<ast::Str>()?.get();
+                if path.starts_with('@') {
+                    return None;
+                }
+                (source.id().join(&path), 0, 0)
+            }
+            _ => return None,
+        };
+
+        let target = self.world.source(id).ok()?;
+        Some(json!({
+            "uri": path_to_uri(&self.world.path(id).ok()?),
+            "range": range_json(&target, start, end)?,
+        }))
+    }
+
+    /// Find the definition of a label, first in the given source and then in
+    /// all other files of the last compilation.
+    fn find_label(
+        &mut self,
+        source: &Source,
+        label: &str,
+    ) -> Option<(FileId, usize, usize)> {
+        let others = self.dependencies.clone();
+        std::iter::once(source.clone())
+            .chain(others.into_iter().filter_map(|id| self.world.source(id).ok()))
+            .find_map(|source| {
+                let node = find_node(&LinkedNode::new(source.root()), |node| {
+                    node.kind() == SyntaxKind::Label && node.text().as_str() == label
+                })?;
+                let range = node.range();
+                Some((source.id(), range.start, range.end))
+            })
+    }
+
+    /// Resolve the source and byte offset of a text document position.
+    fn locate(&mut self, params: &Json) -> Option<(Source, usize)> {
+        let id = self.id(params["textDocument"]["uri"].as_str()?)?;
+        let source = self.world.source(id).ok()?;
+        let cursor = offset(&source, &params["position"])?;
+        Some((source, cursor))
+    }
+
+    /// The id of the file at a URI, if it belongs to the project or a
+    /// package.
+    ///
+    /// Files of the last compilation keep the id they were compiled with,
+    /// which also covers packages given with `--package-path`.
+    fn id(&mut self, uri: &str) -> Option<FileId> {
+        let path = uri_to_path(uri)?;
+        let canonical = path.canonicalize().ok();
+        let matches = |other: &Path| other == path || Some(other) == canonical.as_deref();
+
+        if let Some(&id) = self
+            .dependencies
+            .iter()
+            .find(|&&id| self.world.path(id).is_ok_and(|other| matches(&other)))
+        {
+            return Some(id);
+        }
+
+        let root = self.world.root();
+        if let Some(vpath) = VirtualPath::within_root(&path, root)
+            .or_else(|| VirtualPath::within_root(canonical.as_deref()?, root))
+        {
+            return Some(FileId::new(None, vpath));
+        }
+
+        let (spec, vpath) =
+            package_file(&path).or_else(|| package_file(canonical.as_deref()?))?;
+        Some(FileId::new(Some(spec), vpath))
+    }
+}
+
+/// The capabilities announced to the client.
+fn capabilities() -> Json {
+    json!({
+        "capabilities": {
+            // Always send the full text of changed documents.
+            "textDocumentSync": 1,
+            "completionProvider": {
+                "triggerCharacters": ["#", ".", "@", "(", ",", ":", "\"", "/"],
+            },
+            "hoverProvider": true,
+            "definitionProvider": true,
+        },
+        "serverInfo": { "name": "typst", "version": crate::typst_version() },
+    })
+}
+
+/// Collect the identifiers of all bindings in a syntax tree.
+fn declarations<'a>(node: &'a SyntaxNode, out: &mut Vec<ast::Ident<'a>>) {
+    if let Some(binding) = node.cast::<ast::LetBinding>() {
+        out.extend(binding.kind().idents());
+    } else if let Some(closure) = node.cast::<ast::Closure>() {
+        for param in closure.params().children() {
+            match param {
+                ast::Param::Pos(pattern) => out.extend(pattern.idents()),
+                ast::Param::Named(named) => out.push(named.name()),
+                ast::Param::Sink(spread) => out.extend(spread.name()),
+            }
+        }
+    } else if let Some(for_loop) = node.cast::<ast::ForLoop>() {
+        out.extend(for_loop.pattern().idents());
+    } else if let Some(import) = node.cast::<ast::ModuleImport>() {
+        if let Some(ast::Imports::Items(items)) = import.imports() {
+            out.extend(items.iter().map(|item| item.bound_name()));
+        }
+        out.extend(import.new_name());
+    }
+
+    for child in node.children() {
+        declarations(child, out);
+    }
+}
+
+/// Find the first node in a tree that matches a predicate.
+fn find_node<'a>(
+    node: &LinkedNode<'a>,
+    predicate: impl Fn(&LinkedNode) -> bool + Copy,
+) -> Option<LinkedNode<'a>> {
+    if predicate(node) {
+        return Some(node.clone());
+    }
+    node.children().find_map(|child| find_node(&child, predicate))
+}
+
+/// The kind of a completion in the protocol.
+fn completion_kind(kind: &CompletionKind) -> u8 {
+    match kind {
+        CompletionKind::Syntax => 15,
+        CompletionKind::Func => 3,
+        CompletionKind::Type => 7,
+        CompletionKind::Param => 5,
+        CompletionKind::Constant => 21,
+        CompletionKind::Symbol(_) => 1,
+    }
+}
+
+/// Convert a completion in Typst's snippet syntax, where placeholders look
+/// like `${name}`, to the protocol's syntax with numbered placeholders.
+fn snippet(apply: &str) -> String {
+    let mut out = String::new();
+    let mut rest = apply;
+    let mut index = 1;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else { break };
+        out.push_str(&escape_snippet(&rest[..start]));
+        let name = &rest[start + 2..start + len];
+        if name.is_empty() {
+            write!(out, "${index}").ok();
+        } else {
+            write!(out, "${{{index}:{}}}", escape_snippet(name)).ok();
+        }
+        index += 1;
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(&escape_snippet(rest));
+    out
+}
+
+/// Escape text so that it is inserted literally as part of a snippet.
+fn escape_snippet(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('$', "\\$").replace('}', "\\}")
+}
+
+/// The position at the start of a file.
+fn origin() -> Json {
+    json!({ "line": 0, "character": 0 })
+}
+
+/// Convert a byte range into the protocol's range.
+fn range_json(source: &Source, start: usize, end: usize) -> Option<Json> {
+    Some(json!({ "start": position(source, start)?, "end": position(source, end)? }))
+}
+
+/// Convert a byte offset into the protocol's line and UTF-16 column.
+fn position(source: &Source, byte: usize) -> Option<Json> {
+    let line = source.byte_to_line(byte)?;
+    let start = source.line_to_byte(line)?;
+    let character = source.byte_to_utf16(byte)? - source.byte_to_utf16(start)?;
+    Some(json!({ "line": line, "character": character }))
+}
+
+/// Convert the protocol's line and UTF-16 column into a byte offset.
+fn offset(source: &Source, position: &Json) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    let start = source.line_to_byte(line)?;
+    source.utf16_to_byte(source.byte_to_utf16(start)? + character)
+}
+
+/// Convert a `file://` URI into a path.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut iter = encoded.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    let path = String::from_utf8(bytes).ok()?;
+
+    // Windows paths look like `/C:/dir/file.typ` in URIs.
+    #[cfg(windows)]
+    let path = path.strip_prefix('/').map(Into::into).unwrap_or(path);
+
+    Some(PathBuf::from(path))
+}
+
+/// Convert a path into a `file://` URI.
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~:".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            write!(uri, "%{byte:02X}").ok();
+        }
+    }
+    uri
+}
+
+/// Read a message, or `None` once the input ends.
+fn receive(reader: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidData, "missing content length")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Write a message to stdout.
+fn send(message: Json) -> io::Result<()> {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_roundtrip() {
+        #[track_caller]
+        fn test(path: &str, uri: &str) {
+            assert_eq!(path_to_uri(Path::new(path)), uri);
+            assert_eq!(uri_to_path(uri), Some(PathBuf::from(path)));
+        }
+
+        test("/home/me/doc.typ", "file:///home/me/doc.typ");
+        test("/my files/a b.typ", "file:///my%20files/a%20b.typ");
+        test("/über/ü.typ", "file:///%C3%BCber/%C3%BC.typ");
+        assert_eq!(uri_to_path("file:///a%2"), None);
+        assert_eq!(uri_to_path("https://typst.app/a.typ"), None);
+    }
+
+    #[test]
+    fn test_position_and_offset() {
+        let source = Source::detached("a😀b\nsecond 😀\nc");
+
+        #[track_caller]
+        fn test(source: &Source, byte: usize, line: u64, character: u64) {
+            let position = json!({ "line": line, "character": character });
+            assert_eq!(super::position(source, byte), Some(position.clone()));
+            assert_eq!(offset(source, &position), Some(byte));
+        }
+
+        test(&source, 0, 0, 0);
+        test(&source, 5, 0, 3);
+        test(&source, 6, 0, 4);
+        test(&source, 7, 1, 0);
+        test(&source, 14, 1, 7);
+        test(&source, 18, 1, 9);
+        test(&source, 19, 2, 0);
+        assert_eq!(offset(&source, &json!({ "line": 5, "character": 0 })), None);
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet("box(${body})"), "box(${1:body})");
+        assert_eq!(snippet("#let ${} = ${}"), "#let $1 = $2");
+        assert_eq!(snippet("${a} $ ${b}"), "${1:a} \\$ ${2:b}");
+        assert_eq!(snippet("f(${x"), "f(\\${x");
+        assert_eq!(snippet("plain"), "plain");
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut input: &[u8] = b"Content-Length: 7\r\n\r\n{\"a\":1}\
+            content-length: 2\r\nContent-Type: x\r\n\r\n[]";
+        assert_eq!(receive(&mut input).unwrap(), Some(json!({ "a": 1 })));
+        assert_eq!(receive(&mut input).unwrap(), Some(json!([])));
+        assert_eq!(receive(&mut input).unwrap(), None);
+
+        let mut input: &[u8] = b"Content-Type: x\r\n\r\n{}";
+        assert_eq!(receive(&mut input).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut input: &[u8] = b"Content-Length: 10\r\n\r\n{}";
+        assert_eq!(receive(&mut input).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
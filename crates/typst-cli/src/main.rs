@@ -1,9 +1,24 @@
 mod args;
+mod cache;
 mod compile;
+mod completions;
+#[cfg(unix)]
+mod daemon;
+mod deps;
 mod download;
 mod fonts;
+mod init;
+mod limits;
+mod lock;
+mod lsp;
 mod package;
+mod package_cmd;
+mod permissions;
+mod prefetch;
 mod query;
+mod resolve;
+mod serve;
+mod text;
 mod timings;
 #[cfg(feature = "self-update")]
 mod update;
@@ -28,19 +43,48 @@ thread_local! {
 }
 
 /// The parsed commandline arguments.
-static ARGS: Lazy<CliArguments> = Lazy::new(CliArguments::parse);
+static ARGS: Lazy<CliArguments> = Lazy::new(|| {
+    let mut args = CliArguments::parse();
+    match &mut args.command {
+        Command::Serve(command) => command.common.permissions.confine_by_default(),
+        Command::Lsp(command) => command.common.permissions.confine_by_default(),
+        _ => {}
+    }
+    args
+});
 
 /// Entry point.
 fn main() -> ExitCode {
+    // The thread pool must be set up before anything runs in parallel.
+    if let Some(jobs) = ARGS.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.get())
+            .build_global()
+            .expect("thread pool was already set up");
+    }
+
     let timer = Timer::new(&ARGS);
     let res = match &ARGS.command {
         Command::Compile(command) => crate::compile::compile(timer, command.clone()),
         Command::Watch(command) => crate::watch::watch(timer, command.clone()),
         Command::Query(command) => crate::query::query(command),
+        Command::Deps(command) => crate::deps::deps(command),
+        Command::Init(command) => crate::init::init(command),
         Command::Fonts(command) => crate::fonts::fonts(command),
+        Command::Cache(command) => crate::cache::cache(command),
+        Command::Prefetch(command) => crate::prefetch::prefetch(command),
+        Command::Package(command) => crate::package_cmd::package(command),
+        Command::Daemon(command) => crate::daemon::daemon(command),
+        Command::Serve(command) => crate::serve::serve(command),
+        Command::Lsp(command) => crate::lsp::lsp(command),
+        Command::Completions(command) => crate::completions::completions(command),
         Command::Update(command) => crate::update::update(command),
     };
 
+    // Commands that compile write all their diagnostics as one SARIF log.
+    let output = ARGS.command.shared().and_then(|args| args.sarif_output.as_deref());
+    let res = res.and(crate::compile::write_sarif(output));
+
     if let Err(msg) = res {
         set_failed();
         print_error(&msg).expect("failed to print error");
@@ -54,6 +98,26 @@ fn set_failed() {
     EXIT.with(|cell| cell.set(ExitCode::FAILURE));
 }
 
+/// Ensure a failure exit code that signals that only denied warnings made the
+/// compilation fail, unless it failed for other reasons already.
+fn set_denied() {
+    EXIT.with(|cell| {
+        if cell.get() == ExitCode::SUCCESS {
+            cell.set(ExitCode::from(2));
+        }
+    });
+}
+
+/// Ensure an exit code that signals that the compilation succeeded with
+/// warnings, unless it failed already.
+fn set_warned() {
+    EXIT.with(|cell| {
+        if cell.get() == ExitCode::SUCCESS {
+            cell.set(ExitCode::from(3));
+        }
+    });
+}
+
 /// Print an application-level error (independent from a source file).
 fn print_error(msg: &str) -> io::Result<()> {
     let mut w = color_stream();
@@ -99,3 +163,13 @@ mod update {
         )
     }
 }
+
+#[cfg(not(unix))]
+mod daemon {
+    use crate::args::DaemonCommand;
+    use typst::diag::{bail, StrResult};
+
+    pub fn daemon(_: &DaemonCommand) -> StrResult<()> {
+        bail!("the daemon is only supported on Unix-like platforms")
+    }
+}
@@ -1,34 +1,149 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
 use codespan_reporting::term::{self, termcolor};
-use ecow::eco_format;
+use ecow::{eco_format, EcoString};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use termcolor::WriteColor;
-use typst::diag::{PackageError, PackageResult};
-use typst::syntax::PackageSpec;
+use typst::diag::{bail, PackageError, PackageResult, StrResult};
+use typst::syntax::{PackageSpec, PackageVersion, VirtualPath};
 
 use crate::color_stream;
-use crate::download::download_with_progress;
+use crate::download::{download, download_with_progress};
+use crate::lock::LOCKFILE;
+use crate::permissions::Permissions;
 
-/// Make a package available in the on-disk cache.
-pub fn prepare_package(spec: &PackageSpec) -> PackageResult<PathBuf> {
-    let subdir =
-        format!("typst/packages/{}/{}/{}", spec.namespace, spec.name, spec.version);
+/// The subdirectory of the data and cache directories in which packages are
+/// stored.
+pub const PACKAGES_SUBDIR: &str = "typst/packages";
 
-    if let Some(data_dir) = dirs::data_dir() {
-        let dir = data_dir.join(&subdir);
-        if dir.exists() {
-            return Ok(dir);
-        }
+/// The registry from which `@preview` packages are downloaded unless
+/// configured otherwise.
+const DEFAULT_REGISTRY: &str = "https://packages.typst.org";
+
+/// The namespace that is available from the default registry.
+const DEFAULT_NAMESPACE: &str = "preview";
+
+/// The file in a downloaded package's directory that records a checksum of
+/// the package's contents.
+pub const CHECKSUM_FILE: &str = ".typst-checksum";
+
+/// The file in a downloaded package's directory that keeps the package's
+/// entry from its registry's index, so that advisories are known without
+/// asking the registry again.
+pub const INDEX_FILE: &str = ".typst-index.json";
+
+/// The registries configured for package namespaces, loaded on first use.
+static REGISTRIES: Lazy<StrResult<RegistryConfig>> = Lazy::new(RegistryConfig::load);
+
+/// The entries of a registry's index, shared between the users of the index.
+type RegistryIndex = Arc<Vec<IndexEntry>>;
+
+/// The indices of the registries fetched so far, by registry URL. Failures
+/// aren't kept, so that a registry that was unreachable once is asked again
+/// the next time.
+static INDICES: Lazy<Mutex<HashMap<EcoString, RegistryIndex>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The directory that packages are downloaded into.
+///
+/// This is `TYPST_PACKAGE_CACHE_PATH` if set and `typst/packages` in the
+/// system's cache directory otherwise.
+pub fn package_cache_dir() -> Option<PathBuf> {
+    match &crate::ARGS.package_cache_path {
+        Some(path) => Some(path.clone()),
+        None => dirs::cache_dir().map(|dir| dir.join(PACKAGES_SUBDIR)),
     }
+}
+
+/// The directory that packages of a namespace are installed into by
+/// `typst package restore`.
+///
+/// This is the package cache for namespaces that are downloaded from a
+/// registry and `typst/packages` in the system's data directory for local
+/// namespaces, which the package cache must not hold because it may be
+/// cleaned at any time.
+pub fn package_install_dir(namespace: &str) -> StrResult<PathBuf> {
+    let downloaded = registry(namespace).map_err(|err| eco_format!("{err}"))?.is_some();
+    let dir = if downloaded {
+        package_cache_dir()
+    } else {
+        dirs::data_dir().map(|dir| dir.join(PACKAGES_SUBDIR))
+    };
+    dir.ok_or_else(|| {
+        eco_format!("failed to locate the directory for @{namespace} packages")
+    })
+}
 
-    if let Some(cache_dir) = dirs::cache_dir() {
-        let dir = cache_dir.join(&subdir);
+/// The directories in which packages are looked up, in order: local packages
+/// in the data directory, the package cache, and the read-only cache layers.
+fn package_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    dirs.extend(dirs::data_dir().map(|dir| dir.join(PACKAGES_SUBDIR)));
+    dirs.extend(package_cache_dir());
+    dirs.extend(crate::ARGS.package_cache_layers.iter().cloned());
+    dirs
+}
+
+/// The package that a file in one of the package directories belongs to, and
+/// the file's path within it.
+pub fn package_file(path: &Path) -> Option<(PackageSpec, VirtualPath)> {
+    package_dirs().iter().find_map(|dir| file_in(dir, path))
+}
+
+/// The package and path within it of a file in a directory that is laid out
+/// like `{namespace}/{name}/{version}/{path}`.
+fn file_in(dir: &Path, path: &Path) -> Option<(PackageSpec, VirtualPath)> {
+    let mut components = path.strip_prefix(dir).ok()?.components();
+    let mut next = || match components.next()? {
+        Component::Normal(part) => part.to_str(),
+        _ => None,
+    };
+
+    let namespace = next()?.into();
+    let name = next()?.into();
+    let version = next()?.parse().ok()?;
+    let rest = components.as_path();
+    if rest.as_os_str().is_empty() {
+        return None;
+    }
+
+    Some((PackageSpec { namespace, name, version }, VirtualPath::new(rest)))
+}
+
+/// Make a package available in the on-disk cache.
+///
+/// Downloads the package if it isn't available locally yet and the
+/// permissions allow it. A downloaded archive must match the checksum in the
+/// registry's index and the given `sha256`, e.g. one recorded in a lockfile.
+pub fn prepare_package(
+    spec: &PackageSpec,
+    permissions: &Permissions,
+    sha256: Option<&str>,
+) -> PackageResult<PathBuf> {
+    if let Some(dir) = available_package(spec) {
+        return Ok(dir);
+    }
+
+    if let Some(cache_dir) = package_cache_dir() {
+        let dir = cache_dir.join(package_subdir(spec));
 
         // Download from network if it doesn't exist yet.
-        if spec.namespace == "preview" && !dir.exists() {
-            download_package(spec, &dir)?;
+        if !dir.exists() {
+            if let Some(registry) = registry(&spec.namespace)? {
+                if !permissions.network {
+                    return Err(PackageError::NetworkFailed(Some(
+                        "network access is not allowed in a sandbox".into(),
+                    )));
+                }
+                download_package(spec, &dir, &registry, sha256)?;
+            }
         }
 
         if dir.exists() {
@@ -39,20 +154,178 @@ pub fn prepare_package(spec: &PackageSpec) -> PackageResult<PathBuf> {
     Err(PackageError::NotFound(spec.clone()))
 }
 
-/// Download a package over the network.
-fn download_package(spec: &PackageSpec, package_dir: &Path) -> PackageResult<()> {
-    // The `@preview` namespace is the only namespace that supports on-demand
-    // fetching.
-    assert_eq!(spec.namespace, "preview");
+/// The directory of a package that is available without downloading.
+fn available_package(spec: &PackageSpec) -> Option<PathBuf> {
+    let subdir = package_subdir(spec);
+    package_dirs()
+        .into_iter()
+        .map(|dir| dir.join(&subdir))
+        .find(|dir| dir.exists())
+}
+
+/// The path of a package's directory within a package directory.
+fn package_subdir(spec: &PackageSpec) -> String {
+    format!("{}/{}/{}", spec.namespace, spec.name, spec.version)
+}
 
+/// The checksum of a package's contents that was stored when it was
+/// downloaded, if it is available without downloading.
+pub fn stored_checksum(spec: &PackageSpec) -> Option<EcoString> {
+    let path = available_package(spec)?.join(CHECKSUM_FILE);
+    let checksum = fs::read_to_string(path).ok()?;
+    Some(checksum.trim().into())
+}
+
+/// List the packages that are available without downloading, along with their
+/// descriptions.
+pub fn local_packages() -> Vec<(PackageSpec, Option<EcoString>)> {
+    let mut packages: Vec<(PackageSpec, Option<EcoString>)> = vec![];
+    for root in package_dirs() {
+        for namespace in subdirs(&root) {
+            for version in subdirs(&namespace).flat_map(|name| subdirs(&name)) {
+                let Ok(bytes) = fs::read(version.join("typst.toml")) else { continue };
+                let Ok(manifest) = PackageManifest::parse(&bytes) else { continue };
+                let spec = PackageSpec {
+                    namespace: namespace
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into(),
+                    name: manifest.package.name,
+                    version: manifest.package.version,
+                };
+                if !packages.iter().any(|(existing, _)| *existing == spec) {
+                    packages.push((spec, manifest.package.description));
+                }
+            }
+        }
+    }
+    packages
+}
+
+/// List the packages in the indices of the registries that this process
+/// fetched, along with their descriptions.
+///
+/// Never causes a request, see [`fetch_indices`]. Yanked and deprecated
+/// versions are left out.
+pub fn index_packages() -> Vec<(PackageSpec, Option<EcoString>)> {
+    let indices = INDICES.lock();
+    registries()
+        .into_iter()
+        .filter_map(|(namespace, registry)| {
+            Some((namespace, indices.get(&registry.url)?.clone()))
+        })
+        .flat_map(|(namespace, index)| {
+            index
+                .iter()
+                .filter(|entry| !entry.yanked && !entry.deprecated)
+                .map(|entry| {
+                    let spec = PackageSpec {
+                        namespace: namespace.clone(),
+                        name: entry.name.clone(),
+                        version: entry.version,
+                    };
+                    (spec, entry.description.clone())
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Fetch the indices of all registries, so that [`index_packages`] lists
+/// their packages. Registries that can't be reached are skipped.
+pub fn fetch_indices() {
+    for (_, registry) in registries() {
+        registry_index(&registry).ok();
+    }
+}
+
+/// The directories directly contained in `dir`, skipping hidden ones (like
+/// in-progress downloads).
+pub fn subdirs(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && !path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+}
+
+/// Where a file in a package can be viewed online.
+///
+/// Only known for packages from the default registry, whose sources are
+/// published in the `typst/packages` repository.
+pub fn file_url(spec: &PackageSpec, path: &VirtualPath) -> Option<EcoString> {
+    let registry = registry(&spec.namespace).ok()??;
+    if registry.url != eco_format!("{DEFAULT_REGISTRY}/{DEFAULT_NAMESPACE}") {
+        return None;
+    }
+
+    Some(eco_format!(
+        "https://github.com/typst/packages/blob/main/packages/{}/{}/{}{}",
+        spec.namespace,
+        spec.name,
+        spec.version,
+        path.as_rooted_path().display(),
+    ))
+}
+
+/// Determine the registry serving a namespace, if any.
+fn registry(namespace: &str) -> PackageResult<Option<Registry>> {
+    let config = REGISTRIES
+        .as_ref()
+        .map_err(|err| PackageError::Other(Some(err.clone())))?;
+    Ok(match config.namespaces.get(namespace) {
+        Some(registry) => Some(registry.clone()),
+        None if namespace == DEFAULT_NAMESPACE => Some(Registry {
+            url: eco_format!("{DEFAULT_REGISTRY}/{DEFAULT_NAMESPACE}"),
+            token: None,
+        }),
+        None => None,
+    })
+}
+
+/// All namespaces that are served by a registry, along with their registry.
+fn registries() -> Vec<(EcoString, Registry)> {
+    let mut namespaces: Vec<EcoString> = match &*REGISTRIES {
+        Ok(config) => config.namespaces.keys().cloned().collect(),
+        Err(_) => vec![],
+    };
+    if !namespaces.iter().any(|namespace| namespace == DEFAULT_NAMESPACE) {
+        namespaces.push(DEFAULT_NAMESPACE.into());
+    }
+
+    namespaces
+        .into_iter()
+        .filter_map(|namespace| {
+            let registry = registry(&namespace).ok()??;
+            Some((namespace, registry))
+        })
+        .collect()
+}
+
+/// Download a package over the network.
+fn download_package(
+    spec: &PackageSpec,
+    package_dir: &Path,
+    registry: &Registry,
+    sha256: Option<&str>,
+) -> PackageResult<()> {
     let url = format!(
-        "https://packages.typst.org/preview/{}-{}.tar.gz",
-        spec.name, spec.version
+        "{}/{}-{}.tar.gz",
+        registry.url.trim_end_matches('/'),
+        spec.name,
+        spec.version
     );
 
     print_downloading(spec).unwrap();
 
-    let data = match download_with_progress(&url) {
+    let data = match download_with_progress(&url, registry.token.as_deref()) {
         Ok(data) => data,
         Err(ureq::Error::Status(404, _)) => {
             return Err(PackageError::NotFound(spec.clone()))
@@ -60,13 +333,230 @@ fn download_package(spec: &PackageSpec, package_dir: &Path) -> PackageResult<()>
         Err(err) => return Err(PackageError::NetworkFailed(Some(eco_format!("{err}")))),
     };
 
+    // Check the archive against the checksum the registry publishes for it
+    // and the one it is expected to have.
+    let entry = index_entry(spec, registry)?;
+    let actual = hex(&Sha256::digest(&data));
+    let expected = [
+        (entry.as_ref().and_then(|entry| entry.sha256.as_deref()), "the registry index"),
+        (sha256, LOCKFILE),
+    ];
+    for (expected, source) in expected {
+        let Some(expected) = expected else { continue };
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(PackageError::Other(Some(eco_format!(
+                "archive checksum {actual} does not match {expected} from {source}"
+            ))));
+        }
+    }
+
+    // Unpack into a temporary sibling directory first and then move it into
+    // place atomically. This way, concurrent processes never observe a
+    // partially unpacked package.
+    let parent = package_dir.parent().unwrap_or(package_dir);
+    let tempdir = fs::create_dir_all(parent)
+        .and_then(|_| tempfile::Builder::new().prefix(".download-").tempdir_in(parent))
+        .map_err(|err| PackageError::Other(Some(eco_format!("{err}"))))?;
+
     let decompressed = flate2::read::GzDecoder::new(data.as_slice());
-    tar::Archive::new(decompressed).unpack(package_dir).map_err(|err| {
-        fs::remove_dir_all(package_dir).ok();
-        PackageError::MalformedArchive(Some(eco_format!("{err}")))
+    tar::Archive::new(decompressed)
+        .unpack(tempdir.path())
+        .map_err(|err| PackageError::MalformedArchive(Some(eco_format!("{err}"))))?;
+
+    // Record a checksum of the unpacked files, so that later changes to them
+    // can be detected.
+    contents_checksum(tempdir.path())
+        .and_then(|checksum| fs::write(tempdir.path().join(CHECKSUM_FILE), checksum))
+        .map_err(|err| PackageError::Other(Some(eco_format!("{err}"))))?;
+
+    // Keep the package's index entry, so that its advisories are known for
+    // later builds without a request.
+    if let Some(entry) = &entry {
+        write_index_entry(tempdir.path(), entry)
+            .map_err(|err| PackageError::Other(Some(eco_format!("{err}"))))?;
+    }
+
+    // Once moved, dropping the temporary directory is a no-op.
+    match fs::rename(tempdir.path(), package_dir) {
+        Ok(()) => Ok(()),
+        // Another process finished unpacking the same package first. Keep
+        // its copy and let our temporary directory be removed.
+        Err(_) if package_dir.exists() => Ok(()),
+        Err(err) => Err(PackageError::Other(Some(eco_format!("{err}")))),
+    }
+}
+
+/// A package's entry in its registry's index, if the registry has an index
+/// that lists the package.
+///
+/// Registries without an index are trusted. If a registry's index can't be
+/// fetched or parsed, it is unknown whether the registry publishes checksums,
+/// so this fails rather than skipping verification.
+fn index_entry(
+    spec: &PackageSpec,
+    registry: &Registry,
+) -> PackageResult<Option<IndexEntry>> {
+    let index = registry_index(registry).map_err(|err| {
+        PackageError::NetworkFailed(Some(eco_format!(
+            "failed to fetch the registry index to verify {spec} ({err})"
+        )))
+    })?;
+    Ok(index
+        .iter()
+        .find(|entry| entry.name == spec.name && entry.version == spec.version)
+        .cloned())
+}
+
+/// The registry's advice against using a package version, if the registry
+/// marks it as yanked or deprecated.
+///
+/// Uses the registry's index if this process fetched it already and the
+/// index entry kept next to the downloaded package otherwise, so checking a
+/// cached package never causes a request. The kept entry is refreshed from
+/// a fetched index. Packages without a registry or index entry have no
+/// advisories.
+pub fn advisory(spec: &PackageSpec) -> Option<Advisory> {
+    let registry = registry(&spec.namespace).ok()??;
+    let dir = package_cache_dir()?.join(package_subdir(spec));
+    let fetched = INDICES.lock().get(&registry.url).cloned();
+    let entry = match fetched {
+        Some(index) => {
+            let entry = index
+                .iter()
+                .find(|entry| entry.name == spec.name && entry.version == spec.version)?
+                .clone();
+            if dir.exists() {
+                write_index_entry(&dir, &entry).ok();
+            }
+            entry
+        }
+        None => serde_json::from_slice(&fs::read(dir.join(INDEX_FILE)).ok()?).ok()?,
+    };
+
+    (entry.yanked || entry.deprecated).then(|| Advisory {
+        yanked: entry.yanked,
+        replacement: entry.replacement,
     })
 }
 
+/// Keep a package's index entry next to its files.
+fn write_index_entry(dir: &Path, entry: &IndexEntry) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(entry)?;
+    fs::write(dir.join(INDEX_FILE), json)
+}
+
+/// The newest version of a package in its registry that is newer than the
+/// given one and compatible with it, if any, along with the checksum of its
+/// archive if the registry publishes one.
+///
+/// Like in Cargo, versions are compatible if they agree up to and including
+/// their first non-zero component. Yanked and deprecated versions are
+/// skipped.
+pub fn compatible_update(
+    spec: &PackageSpec,
+) -> PackageResult<Option<(PackageVersion, Option<EcoString>)>> {
+    let Some(registry) = registry(&spec.namespace)? else { return Ok(None) };
+    let index = registry_index(&registry)?;
+    let current = spec.version;
+    Ok(index
+        .iter()
+        .filter(|entry| entry.name == spec.name && !entry.yanked && !entry.deprecated)
+        .filter(|entry| {
+            let version = entry.version;
+            version > current
+                && if current.major > 0 {
+                    version.major == current.major
+                } else if current.minor > 0 {
+                    version.major == 0 && version.minor == current.minor
+                } else {
+                    false
+                }
+        })
+        .max_by_key(|entry| entry.version)
+        .map(|entry| (entry.version, entry.sha256.clone())))
+}
+
+/// Fetch the index of a registry, or reuse it if it was fetched before.
+///
+/// The lock on the fetched indices isn't held during the download, so that
+/// other threads aren't blocked on it. If two threads fetch the same index
+/// concurrently, the first one to finish wins.
+fn registry_index(registry: &Registry) -> PackageResult<RegistryIndex> {
+    if let Some(index) = INDICES.lock().get(&registry.url) {
+        return Ok(index.clone());
+    }
+
+    let index = Arc::new(fetch_index(registry)?);
+    Ok(INDICES.lock().entry(registry.url.clone()).or_insert(index).clone())
+}
+
+/// Download and parse the index of a registry.
+fn fetch_index(registry: &Registry) -> PackageResult<Vec<IndexEntry>> {
+    let url = format!("{}/index.json", registry.url.trim_end_matches('/'));
+    match download(&url, registry.token.as_deref()) {
+        Ok(response) => serde_json::from_reader(response.into_reader()).map_err(|err| {
+            PackageError::Other(Some(eco_format!("registry index is malformed ({err})")))
+        }),
+        Err(ureq::Error::Status(404, _)) => Ok(vec![]),
+        Err(err) => Err(PackageError::NetworkFailed(Some(eco_format!("{err}")))),
+    }
+}
+
+/// A checksum of all files in an unpacked package, apart from the checksum
+/// file itself.
+pub fn contents_checksum(dir: &Path) -> io::Result<String> {
+    let mut files = vec![];
+    collect_files(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for (name, path) in files {
+        let data = fs::read(path)?;
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(&data);
+    }
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Collect the files below `dir` along with their `/`-separated paths
+/// relative to `root`.
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    out: &mut Vec<(String, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, out)?;
+            continue;
+        }
+
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if name != CHECKSUM_FILE && name != INDEX_FILE {
+            out.push((name, path));
+        }
+    }
+    Ok(())
+}
+
+/// Format bytes as lowercase hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 /// Print that a package downloading is happening.
 fn print_downloading(spec: &PackageSpec) -> io::Result<()> {
     let mut w = color_stream();
@@ -78,3 +568,180 @@ fn print_downloading(spec: &PackageSpec) -> io::Result<()> {
     w.reset()?;
     writeln!(w, " {spec}")
 }
+
+/// Maps package namespaces to the registries they are downloaded from.
+///
+/// Read from the TOML file at `TYPST_REGISTRIES` or, by default,
+/// `registries.toml` in the Typst configuration directory:
+///
+/// ```toml
+/// [namespaces.internal]
+/// url = "https://packages.corp.example/internal"
+/// token = "secret"
+/// ```
+///
+/// With this configuration, `@internal/report:1.0.0` is downloaded from
+/// `https://packages.corp.example/internal/report-1.0.0.tar.gz`, sending the
+/// token as a bearer token. Configuring `preview` replaces the default
+/// registry, e.g. with a mirror.
+#[derive(Debug, Default, Deserialize)]
+struct RegistryConfig {
+    /// The registry of each namespace.
+    #[serde(default)]
+    namespaces: HashMap<EcoString, Registry>,
+}
+
+/// A package listed in a registry's `index.json`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct IndexEntry {
+    /// The package's name.
+    name: EcoString,
+    /// The package's version.
+    version: PackageVersion,
+    /// A short description of the package.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<EcoString>,
+    /// The SHA-256 checksum of the package's archive in hexadecimal, if the
+    /// registry publishes one.
+    #[serde(default)]
+    sha256: Option<EcoString>,
+    /// Whether the version was withdrawn by its authors.
+    #[serde(default)]
+    yanked: bool,
+    /// Whether the version should no longer be used.
+    #[serde(default)]
+    deprecated: bool,
+    /// The package to use instead, like `@preview/name:0.2.0`, if any.
+    #[serde(default)]
+    replacement: Option<EcoString>,
+}
+
+/// A registry's advice against using a package version.
+#[derive(Debug, Clone)]
+pub struct Advisory {
+    /// Whether the version was withdrawn by its authors, rather than only
+    /// deprecated.
+    pub yanked: bool,
+    /// The package to use instead, if any.
+    pub replacement: Option<EcoString>,
+}
+
+/// A package registry serving one namespace.
+#[derive(Debug, Clone, Deserialize)]
+struct Registry {
+    /// The base URL under which `{name}-{version}.tar.gz` archives live.
+    url: EcoString,
+    /// A bearer token to authenticate with, if any.
+    token: Option<EcoString>,
+}
+
+impl RegistryConfig {
+    /// Load the configuration file, falling back to an empty configuration if
+    /// there is none.
+    fn load() -> StrResult<Self> {
+        let path = match std::env::var_os("TYPST_REGISTRIES") {
+            Some(path) => PathBuf::from(path),
+            None => match dirs::config_dir() {
+                Some(dir) => dir.join("typst").join("registries.toml"),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let string = match fs::read_to_string(&path) {
+            Ok(string) => string,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(err) => bail!("failed to read {} ({err})", path.display()),
+        };
+
+        toml::from_str(&string).map_err(|err| {
+            eco_format!("registry configuration is malformed: {}", err.message())
+        })
+    }
+}
+
+/// The parts of a package manifest (`typst.toml`) that the CLI needs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackageManifest {
+    /// Details about the package itself.
+    pub package: PackageInfo,
+    /// Details about the template, if the package provides one.
+    #[serde(default)]
+    pub template: Option<TemplateInfo>,
+}
+
+/// The `package` key in the manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackageInfo {
+    /// The name of the package within its namespace.
+    pub name: EcoString,
+    /// The package's version.
+    pub version: PackageVersion,
+    /// The path of the entrypoint into the package.
+    pub entrypoint: EcoString,
+    /// A short description of the package.
+    #[serde(default)]
+    pub description: Option<EcoString>,
+    /// The minimum Typst version the package requires.
+    #[serde(default)]
+    pub compiler: Option<PackageVersion>,
+    /// Glob patterns of files that are not part of the published package.
+    #[serde(default)]
+    pub exclude: Vec<EcoString>,
+}
+
+/// The `template` key in the manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateInfo {
+    /// The directory within the package that contains the template's files.
+    pub path: EcoString,
+    /// The path of the main file within the template directory.
+    pub entrypoint: EcoString,
+    /// The path of a preview image of the template within the package.
+    #[serde(default)]
+    pub thumbnail: Option<EcoString>,
+}
+
+impl PackageManifest {
+    /// Parse the manifest from raw bytes.
+    pub fn parse(bytes: &[u8]) -> StrResult<Self> {
+        let string = std::str::from_utf8(bytes)
+            .map_err(|_| "package manifest is not valid UTF-8")?;
+        toml::from_str(string).map_err(|err| {
+            eco_format!("package manifest is malformed: {}", err.message())
+        })
+    }
+    /// Ensure that this manifest is indeed for the specified package.
+    pub fn validate(&self, spec: &PackageSpec) -> StrResult<()> {
+        if self.package.name != spec.name {
+            bail!("package manifest contains mismatched name `{}`", self.package.name);
+        }
+
+        if self.package.version != spec.version {
+            bail!(
+                "package manifest contains mismatched version {}",
+                self.package.version
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_in() {
+        let dir = Path::new("/cache/typst/packages");
+        let (spec, vpath) =
+            file_in(dir, &dir.join("preview/example/0.1.0/src/lib.typ")).unwrap();
+        assert_eq!(spec.to_string(), "@preview/example:0.1.0");
+        assert_eq!(vpath, VirtualPath::new("src/lib.typ"));
+        assert!(file_in(dir, &dir.join("preview/example/0.1.0")).is_none());
+        assert!(file_in(dir, &dir.join("preview/example/latest/lib.typ")).is_none());
+        assert!(file_in(dir, Path::new("/elsewhere/preview/a/0.1.0/lib.typ")).is_none());
+    }
+}
@@ -0,0 +1,791 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use codespan_reporting::term::termcolor;
+
+use ecow::{eco_format, EcoString};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tempfile::TempDir;
+use termcolor::{Color, ColorSpec, WriteColor};
+use typst::diag::{bail, StrResult};
+use typst::eval::Tracer;
+use typst::syntax::{is_ident, FileId, PackageSpec, PackageVersion, Source, VirtualPath};
+
+use crate::args::{PackageCommand, PackageSubcommand, SharedArgs};
+use crate::compile::print_diagnostics;
+use crate::limits::enforce;
+use crate::lock::{Lockfile, LOCKFILE};
+use crate::package::{
+    compatible_update, contents_checksum, package_install_dir, prepare_package, subdirs,
+    PackageManifest, CHECKSUM_FILE,
+};
+use crate::permissions::Permissions;
+use crate::prefetch::{find_paths, package_imports, prefetch_packages, FoundPath};
+use crate::resolve::local_package_root;
+use crate::world::SystemWorld;
+use crate::{color_stream, print_error, set_failed};
+
+/// The directory in a bundle that contains the packages, laid out like the
+/// package cache.
+const BUNDLE_DIR: &str = "packages";
+
+/// Execute a package command.
+pub fn package(command: &PackageCommand) -> StrResult<()> {
+    match &command.command {
+        PackageSubcommand::Download { packages, permissions } => {
+            download(packages, &Permissions::from_permission_args(permissions))
+        }
+        PackageSubcommand::Init { name, dir } => init(name, dir.as_deref()),
+        PackageSubcommand::Check { dir } => check(dir),
+        PackageSubcommand::Bundle { common, output } => bundle(common, output),
+        PackageSubcommand::Restore { bundle, root } => restore(bundle, root),
+        PackageSubcommand::Update { dir, yes } => update(dir, *yes),
+    }
+}
+
+/// Download packages into the package cache, unless they are available
+/// already.
+///
+/// A package that fails doesn't stop the others from being downloaded.
+fn download(packages: &[String], permissions: &Permissions) -> StrResult<()> {
+    for raw in packages {
+        match download_one(raw, permissions) {
+            Ok(spec) => println!("{spec} is available"),
+            Err(err) => {
+                set_failed();
+                print_error(&eco_format!("{raw}: {err}"))
+                    .map_err(|err| eco_format!("failed to print error ({err})"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Download a single package and check that its manifest matches it.
+fn download_one(raw: &str, permissions: &Permissions) -> StrResult<PackageSpec> {
+    let spec: PackageSpec = raw.parse()?;
+    let dir = prepare_package(&spec, permissions, None)?;
+    let path = dir.join("typst.toml");
+    let bytes = fs::read(&path)
+        .map_err(|err| eco_format!("failed to read {} ({err})", path.display()))?;
+    PackageManifest::parse(&bytes)?.validate(&spec)?;
+    Ok(spec)
+}
+
+/// Create a new package with a manifest and an entrypoint.
+fn init(name: &str, dir: Option<&Path>) -> StrResult<()> {
+    if !is_ident(name) {
+        bail!("`{name}` is not a valid package name");
+    }
+
+    let target = dir.map_or_else(|| PathBuf::from(name), Path::to_path_buf);
+    if fs::read_dir(&target).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{} already exists and is not empty", target.display());
+    }
+
+    let manifest = format!(
+        "[package]\n\
+         name = \"{name}\"\n\
+         version = \"0.1.0\"\n\
+         entrypoint = \"lib.typ\"\n\
+         authors = []\n\
+         license = \"\"\n\
+         description = \"\"\n"
+    );
+    let entrypoint = format!(
+        "// Everything defined here is available to documents that import the\n\
+         // package, e.g. with `#import \"@preview/{name}:0.1.0\": *`.\n\
+         \n\
+         #let hello(name) = [Hello, #name!]\n"
+    );
+
+    let write = |file: &str, contents: &str| {
+        let path = target.join(file);
+        fs::write(&path, contents)
+            .map_err(|err| eco_format!("failed to write {} ({err})", path.display()))
+    };
+
+    fs::create_dir_all(&target).map_err(|err| {
+        eco_format!("failed to create directory {} ({err})", target.display())
+    })?;
+    write("typst.toml", &manifest)?;
+    write("lib.typ", &entrypoint)?;
+
+    eprintln!("created package in {}", target.display());
+    Ok(())
+}
+
+/// Bundle the packages that a document uses into an archive.
+///
+/// The document is compiled to find all packages it uses, including those
+/// imported through computed paths. Packages are the only files that
+/// compilation fetches over the network, so together with the project itself
+/// they are all that an offline compilation needs. Packages that resolve to
+/// local directories through `--package-path` are left out, as they are
+/// configured per machine.
+///
+/// Bundles are gzip-compressed, as no Zstandard encoder is available among
+/// the dependencies.
+fn bundle(common: &SharedArgs, output: &Path) -> StrResult<()> {
+    check_bundle_format(output)?;
+
+    let mut world = SystemWorld::new(common)?;
+    prefetch_packages(&world);
+
+    let mut tracer = Tracer::new();
+    let result = enforce(|| typst::compile(&world, &mut tracer));
+    let errors = result.err().unwrap_or_default();
+    print_diagnostics(&world, &errors, &tracer.warnings(), common.diagnostic_format)
+        .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
+    if !errors.is_empty() {
+        set_failed();
+        return Ok(());
+    }
+
+    let mut packages: Vec<PackageSpec> = vec![];
+    let mut skipped = HashSet::new();
+    for spec in world.dependency_ids().filter_map(|id| id.package().cloned()) {
+        if packages.contains(&spec) {
+            continue;
+        } else if local_package_root(&common.package_paths, &spec).is_some() {
+            skipped.insert(spec);
+        } else {
+            packages.push(spec);
+        }
+    }
+    packages.sort_by_key(|spec| spec.to_string());
+
+    let file = File::create(output)
+        .map_err(|err| eco_format!("failed to create {} ({err})", output.display()))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for spec in &packages {
+        let manifest_id = FileId::new(Some(spec.clone()), VirtualPath::new("typst.toml"));
+        let manifest =
+            world.path(manifest_id).map_err(|err| eco_format!("{spec}: {err}"))?;
+        let dir = manifest.parent().unwrap_or(Path::new("."));
+        let name =
+            format!("{BUNDLE_DIR}/{}/{}/{}", spec.namespace, spec.name, spec.version);
+        builder
+            .append_dir_all(name, dir)
+            .map_err(|err| eco_format!("failed to add {spec} to the bundle ({err})"))?;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|err| eco_format!("failed to write {} ({err})", output.display()))?;
+
+    eprintln!("bundled {} package(s) into {}", packages.len(), output.display());
+    if !skipped.is_empty() {
+        eprintln!(
+            "left out {} package(s) from `--package-path` directories",
+            skipped.len()
+        );
+    }
+    Ok(())
+}
+
+/// Unpack the packages of a bundle into the directories they are installed
+/// into, keeping those that are available already.
+///
+/// Packages of namespaces with a registry go into the package cache and
+/// those of local namespaces into the data directory. Each package is checked
+/// before it is moved into place, see [`check_restored`].
+fn restore(bundle: &Path, root: &Path) -> StrResult<()> {
+    check_bundle_format(bundle)?;
+    let lockfile = Lockfile::read(root)?;
+    let file = File::open(bundle)
+        .map_err(|err| eco_format!("failed to open {} ({err})", bundle.display()))?;
+    let failed =
+        |err: io::Error| eco_format!("failed to unpack {} ({err})", bundle.display());
+
+    // Unpack next to the installed packages first, so that each package can
+    // be moved into place atomically.
+    let mut tempdirs: HashMap<PathBuf, TempDir> = HashMap::new();
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let path = entry.path().map_err(failed)?;
+        let Some(namespace) = path
+            .strip_prefix(BUNDLE_DIR)
+            .ok()
+            .and_then(|path| path.iter().next())
+            .and_then(|namespace| namespace.to_str())
+        else {
+            continue;
+        };
+
+        let dir = package_install_dir(namespace)?;
+        let tempdir = match tempdirs.entry(dir) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let tempdir = fs::create_dir_all(entry.key())
+                    .and_then(|_| {
+                        tempfile::Builder::new()
+                            .prefix(".restore-")
+                            .tempdir_in(entry.key())
+                    })
+                    .map_err(|err| {
+                        eco_format!("failed to create temporary directory ({err})")
+                    })?;
+                entry.insert(tempdir)
+            }
+        };
+        entry.unpack_in(tempdir.path()).map_err(failed)?;
+    }
+
+    let (mut restored, mut present) = (0, 0);
+    for (dir, tempdir) in &tempdirs {
+        let root = tempdir.path().join(BUNDLE_DIR);
+        for version in subdirs(&root)
+            .flat_map(|namespace| subdirs(&namespace))
+            .flat_map(|name| subdirs(&name))
+        {
+            let relative = version.strip_prefix(&root).unwrap_or(&version);
+            let target = dir.join(relative);
+            if target.exists() {
+                present += 1;
+                continue;
+            }
+
+            if let Err(problem) = check_restored(&version, relative, lockfile.as_ref()) {
+                set_failed();
+                print_error(&eco_format!("{}: {problem}", relative.display()))
+                    .map_err(|err| eco_format!("failed to print error ({err})"))?;
+                continue;
+            }
+
+            fs::create_dir_all(target.parent().unwrap_or(dir))
+                .and_then(|_| fs::rename(&version, &target))
+                .map_err(|err| {
+                    eco_format!("failed to restore {} ({err})", relative.display())
+                })?;
+            restored += 1;
+        }
+    }
+
+    eprintln!("restored {restored} package(s), {present} were available already");
+    Ok(())
+}
+
+/// Check an unpacked package of a bundle before it is restored.
+///
+/// The package's manifest must match its `<namespace>/<name>/<version>` path
+/// within the bundle. If the project's lockfile records a checksum for the
+/// package, its contents must match that checksum. Otherwise, they must match
+/// the checksum stored when the package was downloaded, if any, which only
+/// detects accidental changes as it is part of the bundle itself.
+fn check_restored(
+    dir: &Path,
+    relative: &Path,
+    lockfile: Option<&Lockfile>,
+) -> StrResult<()> {
+    let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+    let [namespace, name, version] = parts.as_slice() else {
+        bail!("package is not at a `<namespace>/<name>/<version>` path");
+    };
+    let spec = PackageSpec {
+        namespace: namespace.as_ref().into(),
+        name: name.as_ref().into(),
+        version: version.parse()?,
+    };
+
+    let bytes = fs::read(dir.join("typst.toml"))
+        .map_err(|err| eco_format!("failed to read package manifest ({err})"))?;
+    PackageManifest::parse(&bytes)?.validate(&spec)?;
+
+    let actual = contents_checksum(dir)
+        .map_err(|err| eco_format!("failed to read package contents ({err})"))?;
+    if let Some(expected) = lockfile.and_then(|lockfile| lockfile.checksum(&spec)) {
+        if actual != expected {
+            bail!("package contents do not match the checksum in {LOCKFILE}");
+        }
+    } else if let Ok(expected) = fs::read_to_string(dir.join(CHECKSUM_FILE)) {
+        if expected.trim() != actual {
+            bail!("package contents were modified after downloading");
+        }
+    }
+
+    Ok(())
+}
+
+/// Ensure that a bundle is a gzip-compressed tarball.
+fn check_bundle_format(path: &Path) -> StrResult<()> {
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        bail!("zstd compression is not supported, use a `.tar.gz` file instead");
+    }
+    Ok(())
+}
+
+/// An import of a package that can be updated to a newer version.
+struct Update {
+    /// The byte range of the string literal with the package specification.
+    range: Range<usize>,
+    new: PackageSpec,
+}
+
+/// Update the package imports of a project to the newest compatible versions.
+///
+/// The changes are shown as a diff and only applied after confirmation.
+fn update(dir: &Path, yes: bool) -> StrResult<()> {
+    let mut paths = vec![];
+    typ_files(dir, &mut paths);
+    paths.sort();
+
+    let mut newest = HashMap::new();
+    let mut files = vec![];
+    for path in paths {
+        let text = fs::read_to_string(&path)
+            .map_err(|err| eco_format!("failed to read {} ({err})", path.display()))?;
+        let source = Source::detached(text);
+        let mut updates = vec![];
+        for (spec, span) in package_imports(source.root()) {
+            if !newest.contains_key(&spec) {
+                let update = compatible_update(&spec)?;
+                newest.insert(spec.clone(), update);
+            }
+
+            if let (Some((version, _)), Some(range)) =
+                (&newest[&spec], source.range(span))
+            {
+                let version = *version;
+                let new = PackageSpec { version, ..spec };
+                updates.push(Update { range, new });
+            }
+        }
+
+        if !updates.is_empty() {
+            files.push((path, source, updates));
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("all packages are up to date");
+        return Ok(());
+    }
+
+    for (path, source, updates) in &files {
+        print_updates(path, source, updates)
+            .map_err(|err| eco_format!("failed to print changes ({err})"))?;
+    }
+
+    if !yes && !confirm("apply these changes?")? {
+        eprintln!("no changes were made, run with `--yes` to apply them");
+        return Ok(());
+    }
+
+    for (path, source, updates) in &files {
+        let mut text = source.text().to_string();
+        for update in updates.iter().rev() {
+            text.replace_range(update.range.clone(), &format!("\"{}\"", update.new));
+        }
+        fs::write(path, text)
+            .map_err(|err| eco_format!("failed to write {} ({err})", path.display()))?;
+    }
+
+    let changes: Vec<_> = newest
+        .into_iter()
+        .filter_map(|(old, update)| {
+            let (version, sha256) = update?;
+            let new = PackageSpec { version, ..old.clone() };
+            Some((old, new, sha256))
+        })
+        .collect();
+    Lockfile::replace(dir, &changes)?;
+
+    eprintln!("updated {} package(s) in {} file(s)", changes.len(), files.len());
+    Ok(())
+}
+
+/// Find the Typst files in a directory and its subdirectories, skipping
+/// hidden ones.
+fn typ_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "typ") {
+            out.push(path);
+        }
+    }
+
+    for sub in subdirs(dir) {
+        typ_files(&sub, out);
+    }
+}
+
+/// Print the lines that updating the imports of a file changes.
+fn print_updates(path: &Path, source: &Source, updates: &[Update]) -> io::Result<()> {
+    let text = source.text();
+    let mut w = color_stream();
+    for update in updates {
+        let Some(line) = source.byte_to_line(update.range.start) else { continue };
+        let Some(range) = source.line_to_range(line) else { continue };
+        let new = format!(
+            "{}\"{}\"{}",
+            &text[range.start..update.range.start],
+            update.new,
+            &text[update.range.end..range.end],
+        );
+
+        writeln!(w, "{}:{}", path.display(), line + 1)?;
+        w.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+        writeln!(w, "- {}", text[range].trim_end())?;
+        w.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+        writeln!(w, "+ {}", new.trim_end())?;
+        w.reset()?;
+    }
+    Ok(())
+}
+
+/// Ask a yes-or-no question on the terminal. Without a terminal, the answer
+/// is no.
+fn confirm(question: &str) -> StrResult<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+
+    eprint!("{question} [y/N] ");
+    io::stderr().flush().ok();
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|err| eco_format!("failed to read answer ({err})"))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// The type of a value in the manifest.
+#[derive(Copy, Clone)]
+enum Kind {
+    Str,
+    Strs,
+    Version,
+    Table,
+}
+
+/// The keys allowed in the manifest, whether they are required, and their
+/// types.
+const TOP_KEYS: &[(&str, bool, Kind)] = &[
+    ("package", true, Kind::Table),
+    ("template", false, Kind::Table),
+    ("tool", false, Kind::Table),
+];
+
+/// The keys allowed in the `package` table.
+const PACKAGE_KEYS: &[(&str, bool, Kind)] = &[
+    ("name", true, Kind::Str),
+    ("version", true, Kind::Version),
+    ("entrypoint", true, Kind::Str),
+    ("authors", false, Kind::Strs),
+    ("license", false, Kind::Str),
+    ("description", false, Kind::Str),
+    ("homepage", false, Kind::Str),
+    ("repository", false, Kind::Str),
+    ("keywords", false, Kind::Strs),
+    ("categories", false, Kind::Strs),
+    ("disciplines", false, Kind::Strs),
+    ("compiler", false, Kind::Version),
+    ("exclude", false, Kind::Strs),
+];
+
+/// The keys allowed in the `template` table.
+const TEMPLATE_KEYS: &[(&str, bool, Kind)] = &[
+    ("path", true, Kind::Str),
+    ("entrypoint", true, Kind::Str),
+    ("thumbnail", false, Kind::Str),
+];
+
+/// Check that a package is well-formed and report all problems.
+fn check(dir: &Path) -> StrResult<()> {
+    let path = dir.join("typst.toml");
+    let string = fs::read_to_string(&path)
+        .map_err(|err| eco_format!("failed to read {} ({err})", path.display()))?;
+    let table: toml::Table = toml::from_str(&string)
+        .map_err(|err| eco_format!("package manifest is malformed: {}", err.message()))?;
+
+    let mut checker = Checker { root: dir, problems: vec![], excludes: vec![] };
+    checker.check_schema(&table);
+    if checker.problems.is_empty() {
+        let manifest = PackageManifest::parse(string.as_bytes())?;
+        checker.check_package(&manifest);
+    }
+
+    for problem in &checker.problems {
+        set_failed();
+        print_error(problem)
+            .map_err(|err| eco_format!("failed to print error ({err})"))?;
+    }
+
+    if checker.problems.is_empty() {
+        println!("no problems found in {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Collects the problems of a package.
+struct Checker<'a> {
+    /// The package's directory.
+    root: &'a Path,
+    /// The problems found so far.
+    problems: Vec<EcoString>,
+    /// The patterns of the files excluded from the package.
+    excludes: Vec<glob::Pattern>,
+}
+
+impl Checker<'_> {
+    /// Check that the manifest only has known keys with values of the right
+    /// type.
+    fn check_schema(&mut self, table: &toml::Table) {
+        self.check_table("", table, TOP_KEYS);
+        for (key, keys) in [("package", PACKAGE_KEYS), ("template", TEMPLATE_KEYS)] {
+            if let Some(toml::Value::Table(inner)) = table.get(key) {
+                self.check_table(key, inner, keys);
+            }
+        }
+    }
+
+    /// Check the keys of a single table.
+    fn check_table(
+        &mut self,
+        prefix: &str,
+        table: &toml::Table,
+        keys: &[(&str, bool, Kind)],
+    ) {
+        let full = |key: &str| {
+            if prefix.is_empty() {
+                EcoString::from(key)
+            } else {
+                eco_format!("{prefix}.{key}")
+            }
+        };
+
+        for (key, value) in table {
+            let Some(&(_, _, kind)) = keys.iter().find(|(name, ..)| name == key) else {
+                self.problems.push(eco_format!("unknown key `{}`", full(key)));
+                continue;
+            };
+
+            let problem = match (kind, value) {
+                (Kind::Str, toml::Value::String(_)) => None,
+                (Kind::Str, _) => Some("must be a string"),
+                (Kind::Strs, toml::Value::Array(items))
+                    if items.iter().all(toml::Value::is_str) =>
+                {
+                    None
+                }
+                (Kind::Strs, _) => Some("must be an array of strings"),
+                (Kind::Version, toml::Value::String(v))
+                    if v.parse::<PackageVersion>().is_ok() =>
+                {
+                    None
+                }
+                (Kind::Version, _) => Some("must be a version like `0.1.0`"),
+                (Kind::Table, toml::Value::Table(_)) => None,
+                (Kind::Table, _) => Some("must be a table"),
+            };
+
+            if let Some(problem) = problem {
+                self.problems.push(eco_format!("`{}` {problem}", full(key)));
+            }
+        }
+
+        for &(key, required, _) in keys {
+            if required && !table.contains_key(key) {
+                self.problems.push(eco_format!("missing key `{}`", full(key)));
+            }
+        }
+    }
+
+    /// Check the contents of a package against its manifest.
+    fn check_package(&mut self, manifest: &PackageManifest) {
+        let info = &manifest.package;
+        if !is_ident(&info.name) {
+            self.problems
+                .push(eco_format!("`{}` is not a valid package name", info.name));
+        }
+
+        if let (Some(compiler), Ok(current)) =
+            (&info.compiler, env!("CARGO_PKG_VERSION").parse::<PackageVersion>())
+        {
+            if *compiler > current {
+                self.problems.push(eco_format!(
+                    "package requires Typst {compiler}, but this is Typst {current}"
+                ));
+            }
+        }
+
+        for pattern in &info.exclude {
+            match glob::Pattern::new(pattern) {
+                Ok(pattern) => self.excludes.push(pattern),
+                Err(err) => self
+                    .problems
+                    .push(eco_format!("invalid exclude pattern `{pattern}` ({err})")),
+            }
+        }
+
+        let entrypoint = VirtualPath::new(info.entrypoint.as_str());
+        if self.check_file(&entrypoint, "package entrypoint", "typst.toml") {
+            self.check_imports(entrypoint);
+        }
+
+        if let Some(template) = &manifest.template {
+            let path = VirtualPath::new(template.path.as_str());
+            match path.resolve(self.root) {
+                Some(dir) if dir.is_dir() => {
+                    let main = VirtualPath::new(
+                        path.as_rooted_path().join(&*template.entrypoint),
+                    );
+                    self.check_file(&main, "template entrypoint", "typst.toml");
+                }
+                _ => self.problems.push(eco_format!(
+                    "template directory `{}` does not exist",
+                    template.path
+                )),
+            }
+
+            if let Some(thumbnail) = &template.thumbnail {
+                let thumbnail = VirtualPath::new(thumbnail.as_str());
+                self.check_file(&thumbnail, "template thumbnail", "typst.toml");
+            }
+        }
+    }
+
+    /// Check that a file exists within the package and isn't excluded from
+    /// it. Returns whether it does and isn't.
+    fn check_file(&mut self, path: &VirtualPath, what: &str, source: &str) -> bool {
+        let shown = path.as_rootless_path().display();
+        match path.resolve(self.root) {
+            None => {
+                self.problems.push(eco_format!(
+                    "{what} `{shown}` in {source} is outside of the package"
+                ));
+                false
+            }
+            Some(real) if !real.is_file() => {
+                self.problems
+                    .push(eco_format!("{what} `{shown}` in {source} does not exist"));
+                false
+            }
+            Some(_) if self.is_excluded(path) => {
+                self.problems.push(eco_format!(
+                    "{what} `{shown}` in {source} is excluded from the package"
+                ));
+                false
+            }
+            Some(_) => true,
+        }
+    }
+
+    /// Whether a file or one of its directories matches an exclude pattern.
+    fn is_excluded(&self, path: &VirtualPath) -> bool {
+        path.as_rootless_path()
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| {
+                self.excludes.iter().any(|pattern| pattern.matches_path(ancestor))
+            })
+    }
+
+    /// Check that all files that a source file and the files it imports or
+    /// includes use through literal paths are part of the package.
+    fn check_imports(&mut self, entrypoint: VirtualPath) {
+        let mut seen = HashSet::new();
+        let mut queue = vec![entrypoint];
+        while let Some(path) = queue.pop() {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+
+            let Some(text) =
+                path.resolve(self.root).and_then(|real| fs::read_to_string(real).ok())
+            else {
+                continue;
+            };
+
+            let mut found = vec![];
+            find_paths(&typst::syntax::parse(&text), &mut found);
+
+            let source = path.as_rootless_path().display().to_string();
+            for FoundPath { path: target, import, .. } in found {
+                // Imports of other packages aren't files of this package.
+                if import && target.starts_with('@') {
+                    continue;
+                }
+
+                let joined = path.join(target.as_str());
+                let what = if import { "import" } else { "file" };
+                if self.check_file(&joined, what, &source) && import {
+                    queue.push(joined);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a package with a manifest for `name` and `version` at the
+    /// given path within `root`.
+    fn package(root: &Path, relative: &str, name: &str, version: &str) -> PathBuf {
+        let dir = root.join(relative);
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = format!(
+            "[package]\nname = \"{name}\"\nversion = \"{version}\"\nentrypoint = \"lib.typ\"\n"
+        );
+        fs::write(dir.join("typst.toml"), manifest).unwrap();
+        fs::write(dir.join("lib.typ"), "#let x = 1").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_check_restored_manifest() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let dir = package(root, "preview/a/0.1.0", "a", "0.1.0");
+        assert!(check_restored(&dir, Path::new("preview/a/0.1.0"), None).is_ok());
+
+        let dir = package(root, "preview/b/0.1.0", "a", "0.1.0");
+        let err = check_restored(&dir, Path::new("preview/b/0.1.0"), None).unwrap_err();
+        assert_eq!(err, "package manifest contains mismatched name `a`");
+
+        let dir = package(root, "preview/c/0.2.0", "c", "0.1.0");
+        let err = check_restored(&dir, Path::new("preview/c/0.2.0"), None).unwrap_err();
+        assert_eq!(err, "package manifest contains mismatched version 0.1.0");
+    }
+
+    #[test]
+    fn test_check_restored_lockfile() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let dir = package(root, "preview/a/0.1.0", "a", "0.1.0");
+        let relative = Path::new("preview/a/0.1.0");
+        let checksum = contents_checksum(&dir).unwrap();
+
+        // The checksum stored in the bundle is ignored in favor of the
+        // lockfile.
+        fs::write(dir.join(CHECKSUM_FILE), &checksum).unwrap();
+        let lock = |checksum: &str| {
+            fs::write(
+                root.join(LOCKFILE),
+                format!(
+                    "[[package]]\nnamespace = \"preview\"\nname = \"a\"\n\
+                     version = \"0.1.0\"\nchecksum = \"{checksum}\"\n"
+                ),
+            )
+            .unwrap();
+            Lockfile::read(root).unwrap().unwrap()
+        };
+
+        assert!(check_restored(&dir, relative, Some(&lock(&checksum))).is_ok());
+        let err = check_restored(&dir, relative, Some(&lock("0000"))).unwrap_err();
+        assert_eq!(err, "package contents do not match the checksum in typst.lock");
+    }
+}
@@ -0,0 +1,113 @@
+use crate::args::{PermissionArgs, SharedArgs};
+
+/// What a compilation may access on the system.
+///
+/// The CLI grants everything by default, except that `serve`, `lsp`, and the
+/// daemon confine symlinks to the project root. With `--sandbox`, everything
+/// is denied unless allowed individually.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Permissions {
+    /// Whether packages may be downloaded.
+    pub network: bool,
+    /// Whether documents may observe the environment they are compiled in,
+    /// i.e. the current date and time.
+    pub env: bool,
+    /// Whether files may lead outside of the project root or their package
+    /// through symlinks.
+    pub outside_root: bool,
+}
+
+impl Permissions {
+    /// Permissions that grant everything.
+    pub fn all() -> Self {
+        Self { network: true, env: true, outside_root: true }
+    }
+
+    /// Permissions that deny everything.
+    pub fn none() -> Self {
+        Self { network: false, env: false, outside_root: false }
+    }
+
+    /// Determine the permissions from the command line arguments.
+    ///
+    /// With `--frozen`, downloading packages is denied even outside of a
+    /// sandbox.
+    pub fn from_args(args: &SharedArgs) -> Self {
+        let mut permissions = Self::from_permission_args(&args.permissions);
+        permissions.network &= !args.frozen;
+        permissions
+    }
+
+    /// Determine the permissions from the arguments of a command that doesn't
+    /// compile a document.
+    pub fn from_permission_args(args: &PermissionArgs) -> Self {
+        let base = if args.sandbox { Self::none() } else { Self::all() };
+        Self {
+            network: base.network || args.allow_network,
+            env: base.env || args.allow_env,
+            outside_root: (base.outside_root || args.allow_abs_paths)
+                && !args.confine_symlinks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::args::CompileCommand;
+
+    /// Parse the arguments of a compile command.
+    fn parse(args: &[&str]) -> Result<SharedArgs, clap::Error> {
+        let args = ["compile", "main.typ"].iter().chain(args);
+        CompileCommand::try_parse_from(args).map(|command| command.common)
+    }
+
+    /// The permissions for the arguments, for a command that confines
+    /// symlinks by default or not.
+    fn permissions(args: &[&str], confine: bool) -> Permissions {
+        let mut args = parse(args).unwrap();
+        if confine {
+            args.permissions.confine_by_default();
+        }
+        Permissions::from_args(&args)
+    }
+
+    #[test]
+    fn test_permissions_from_args() {
+        assert_eq!(permissions(&[], false), Permissions::all());
+        assert_eq!(permissions(&["--sandbox"], false), Permissions::none());
+        assert_eq!(
+            permissions(&["--sandbox", "--allow-network", "--allow-env"], false),
+            Permissions { network: true, env: true, outside_root: false },
+        );
+        assert_eq!(
+            permissions(&["--frozen"], false),
+            Permissions { network: false, env: true, outside_root: true },
+        );
+        assert!(parse(&["--allow-network"]).is_err());
+    }
+
+    #[test]
+    fn test_permissions_symlinks() {
+        let outside_root =
+            |args: &[&str], confine| permissions(args, confine).outside_root;
+        assert!(!outside_root(&["--confine-symlinks"], false));
+        assert!(!outside_root(
+            &["--sandbox", "--allow-abs-paths", "--confine-symlinks"],
+            false
+        ));
+        assert!(outside_root(&["--sandbox", "--allow-abs-paths"], false));
+
+        // Commands that confine symlinks by default.
+        assert!(!outside_root(&[], true));
+        assert!(outside_root(&["--follow-symlinks"], true));
+        assert!(!outside_root(&["--sandbox", "--follow-symlinks"], true));
+        assert!(outside_root(
+            &["--sandbox", "--allow-abs-paths", "--follow-symlinks"],
+            true
+        ));
+        assert!(parse(&["--confine-symlinks", "--follow-symlinks"]).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use ecow::{eco_format, EcoString};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use typst::diag::StrResult;
+use typst::syntax::ast::{self, AstNode};
+use typst::syntax::{FileId, PackageSpec, Span, SyntaxNode, VirtualPath};
+use typst::World;
+
+use crate::args::PrefetchCommand;
+use crate::package::PackageManifest;
+use crate::world::SystemWorld;
+use crate::{print_error, set_failed};
+
+/// Execute a prefetch command.
+pub fn prefetch(command: &PrefetchCommand) -> StrResult<()> {
+    let world = SystemWorld::new(&command.common)?;
+    prefetch_packages(&world);
+
+    let mut walker = ImportWalker::new(&world);
+    walker.visit(world.main());
+
+    for error in &walker.errors {
+        set_failed();
+        print_error(error).map_err(|err| eco_format!("failed to print error ({err})"))?;
+    }
+
+    eprintln!("{} package(s) available", walker.packages.len());
+    Ok(())
+}
+
+/// How many packages are downloaded at the same time at most.
+const PARALLEL_DOWNLOADS: usize = 8;
+
+/// Download the packages that the input file imports, and those that they
+/// import in turn, ahead of compilation.
+///
+/// Compilation loads packages one at a time as it reaches their imports. This
+/// instead downloads all packages at the same depth in parallel, so that a
+/// document with several packages doesn't wait for each download in turn.
+/// Like the [`ImportWalker`], it only finds imports with literal paths. Files
+/// are read directly, so they don't count as dependencies of the next
+/// compilation, and errors are left for compilation to report.
+pub fn prefetch_packages(world: &SystemWorld) {
+    let mut seen = HashSet::new();
+    let mut known = HashSet::new();
+    let mut files = vec![world.main()];
+    let mut packages = vec![];
+    let mut pool = None;
+
+    while !files.is_empty() {
+        while let Some(id) = files.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+
+            let Some(text) =
+                world.path(id).ok().and_then(|path| fs::read_to_string(path).ok())
+            else {
+                continue;
+            };
+
+            let mut found = vec![];
+            find_paths(&typst::syntax::parse(&text), &mut found);
+            for found in found.into_iter().filter(|found| found.import) {
+                if !found.path.starts_with('@') {
+                    files.push(id.join(&found.path));
+                } else if let Ok(spec) = found.path.parse::<PackageSpec>() {
+                    if known.insert(spec.clone()) {
+                        packages.push(spec);
+                    }
+                }
+            }
+        }
+
+        if packages.is_empty() {
+            break;
+        }
+
+        // Downloads wait on the network rather than the CPU, so they get
+        // their own threads, which are shared by all levels. Resolving a
+        // package's manifest downloads the package if needed.
+        if pool.is_none() {
+            pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(PARALLEL_DOWNLOADS)
+                .build()
+                .ok();
+        }
+        let Some(pool) = &pool else { break };
+        files = pool.install(|| {
+            std::mem::take(&mut packages)
+                .into_par_iter()
+                .filter_map(|spec| {
+                    let manifest_id =
+                        FileId::new(Some(spec), VirtualPath::new("typst.toml"));
+                    let bytes = fs::read(world.path(manifest_id).ok()?).ok()?;
+                    let manifest = PackageManifest::parse(&bytes).ok()?;
+                    Some(manifest_id.join(&manifest.package.entrypoint))
+                })
+                .collect()
+        });
+    }
+}
+
+/// A file that a syntax tree refers to through a literal path.
+pub struct FoundPath {
+    /// The path as written, which is a package specification for imports of
+    /// packages.
+    pub path: EcoString,
+    /// The span of the string literal.
+    pub span: Span,
+    /// Whether the file is imported or included rather than loaded through a
+    /// function like `image`.
+    pub import: bool,
+}
+
+/// Find the literal paths of the files that a syntax tree imports, includes,
+/// or loads, in order of appearance.
+pub fn find_paths(node: &SyntaxNode, out: &mut Vec<FoundPath>) {
+    let source = if let Some(import) = node.cast::<ast::ModuleImport>() {
+        Some(import.source())
+    } else {
+        node.cast::<ast::ModuleInclude>().map(|include| include.source())
+    };
+
+    if let Some(source) = source {
+        if let ast::Expr::Str(path) = source {
+            out.push(FoundPath { path: path.get(), span: path.span(), import: true });
+        }
+        return;
+    }
+
+    if let Some(path) = loaded_path(node) {
+        out.push(FoundPath { path: path.get(), span: path.span(), import: false });
+    }
+
+    for child in node.children() {
+        find_paths(child, out);
+    }
+}
+
+/// Find the imports and includes of packages in a syntax tree, along with the
+/// spans of their specifications.
+pub fn package_imports(node: &SyntaxNode) -> Vec<(PackageSpec, Span)> {
+    let mut found = vec![];
+    find_paths(node, &mut found);
+    found
+        .into_iter()
+        .filter(|found| found.import)
+        .filter_map(|found| Some((found.path.parse().ok()?, found.span)))
+        .collect()
+}
+
+/// The functions that load a file whose path is their first argument.
+const LOADING_FUNCS: &[&str] = &[
+    "read",
+    "image",
+    "csv",
+    "json",
+    "toml",
+    "yaml",
+    "xml",
+    "cbor",
+    "bibliography",
+    "plugin",
+];
+
+/// Statically follows `import` and `include` statements with literal paths,
+/// loading every reachable file and package along the way.
+///
+/// Loading a package file makes the world download the package if it isn't
+/// cached yet. Imports with computed paths cannot be discovered this way.
+pub struct ImportWalker<'a> {
+    /// The world to load files from.
+    world: &'a SystemWorld,
+    /// Files that were already visited.
+    seen: HashSet<FileId>,
+    /// The entrypoints of the packages that were found.
+    entrypoints: HashMap<PackageSpec, FileId>,
+    /// All packages that were found, in discovery order.
+    pub packages: Vec<PackageSpec>,
+    /// The files that each visited file imports, includes, or loads through
+    /// a function like `image` with a literal path, in order of appearance.
+    pub children: HashMap<FileId, Vec<FileId>>,
+    /// Errors that occurred while resolving files or packages.
+    pub errors: Vec<EcoString>,
+}
+
+impl<'a> ImportWalker<'a> {
+    /// Create a new walker that loads files from the given world.
+    pub fn new(world: &'a SystemWorld) -> Self {
+        Self {
+            world,
+            seen: HashSet::new(),
+            entrypoints: HashMap::new(),
+            packages: vec![],
+            children: HashMap::new(),
+            errors: vec![],
+        }
+    }
+
+    /// Visit a source file and everything it imports or includes.
+    pub fn visit(&mut self, id: FileId) {
+        if !self.seen.insert(id) {
+            return;
+        }
+
+        match self.world.source(id) {
+            Ok(source) => self.visit_node(id, source.root()),
+            Err(err) => self.errors.push(eco_format!("{id:?}: {err}")),
+        }
+    }
+
+    /// Find imports, includes, and loaded files in a syntax tree.
+    fn visit_node(&mut self, id: FileId, node: &SyntaxNode) {
+        let mut found = vec![];
+        find_paths(node, &mut found);
+        for FoundPath { path, import, .. } in found {
+            if !import {
+                self.children.entry(id).or_default().push(id.join(&path));
+                continue;
+            }
+
+            let target = if path.starts_with('@') {
+                self.visit_package(&path)
+            } else {
+                Some(id.join(&path))
+            };
+
+            if let Some(target) = target {
+                self.children.entry(id).or_default().push(target);
+                self.visit(target);
+            }
+        }
+    }
+
+    /// Load a package's manifest and return its entrypoint.
+    fn visit_package(&mut self, spec: &str) -> Option<FileId> {
+        let result = spec.parse::<PackageSpec>().and_then(|spec| {
+            if let Some(&entrypoint) = self.entrypoints.get(&spec) {
+                return Ok(Some(entrypoint));
+            }
+
+            let manifest_id =
+                FileId::new(Some(spec.clone()), VirtualPath::new("typst.toml"));
+            if self.seen.contains(&manifest_id) {
+                return Ok(None);
+            }
+
+            self.seen.insert(manifest_id);
+            let bytes =
+                self.world.file(manifest_id).map_err(|err| eco_format!("{err}"))?;
+            let manifest = PackageManifest::parse(&bytes)?;
+            manifest.validate(&spec)?;
+            let entrypoint = manifest_id.join(&manifest.package.entrypoint);
+            self.entrypoints.insert(spec.clone(), entrypoint);
+            self.children.entry(entrypoint).or_default().push(manifest_id);
+            self.packages.push(spec);
+            Ok(Some(entrypoint))
+        });
+
+        result.unwrap_or_else(|err| {
+            self.errors.push(eco_format!("{spec}: {err}"));
+            None
+        })
+    }
+}
+
+/// The literal path of a call to a function that loads a file, like
+/// `image("logo.png")`.
+fn loaded_path(node: &SyntaxNode) -> Option<ast::Str<'_>> {
+    let call = node.cast::<ast::FuncCall>()?;
+    let ast::Expr::Ident(callee) = call.callee() else { return None };
+    if !LOADING_FUNCS.contains(&callee.as_str()) {
+        return None;
+    }
+
+    match call.args().items().next()? {
+        ast::Arg::Pos(ast::Expr::Str(path)) => Some(path),
+        _ => None,
+    }
+}
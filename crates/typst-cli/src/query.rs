@@ -9,28 +9,59 @@ use typst::syntax::Span;
 use typst::World;
 
 use crate::args::{QueryCommand, SerializationFormat};
-use crate::compile::print_diagnostics;
-use crate::set_failed;
+use crate::compile::{print_diagnostics, require_sarif_output, write_sarif};
+use crate::limits::enforce;
+use crate::watch::FileWatcher;
 use crate::world::SystemWorld;
+use crate::{print_error, set_failed};
 
 /// Execute a query command.
 pub fn query(command: &QueryCommand) -> StrResult<()> {
-    let mut world = SystemWorld::new(&command.common)?;
+    require_sarif_output(&command.common, "query")?;
+    // Files are only memory-mapped if they aren't watched for edits.
+    let mmap_threshold = crate::ARGS.mmap_threshold.filter(|_| !command.watch);
+    let mut world = SystemWorld::new_mapped(&command.common, mmap_threshold)?;
+    if !command.watch {
+        return query_once(&mut world, command);
+    }
+
+    if command.common.reads_stdin() {
+        bail!("cannot watch input from stdin");
+    }
+
+    // Keep watching if a query fails, the document may be fixed later.
+    let mut watcher = FileWatcher::new()?;
+    loop {
+        if let Err(err) = query_once(&mut world, command) {
+            print_error(&err).ok();
+        }
+        write_sarif(command.common.sarif_output.as_deref())?;
+
+        comemo::evict(10);
+        watcher.update(&mut world)?;
+        watcher.wait(&mut world, &[])?;
+    }
+}
+
+/// Compile the document and print the query result.
+fn query_once(world: &mut SystemWorld, command: &QueryCommand) -> StrResult<()> {
     // Reset everything and ensure that the main file is present.
     world.reset();
     world.source(world.main()).map_err(|err| err.to_string())?;
 
     let mut tracer = Tracer::new();
-    let result = typst::compile(&world, &mut tracer);
-    let warnings = tracer.warnings();
+    let result = enforce(|| typst::compile(&*world, &mut tracer));
+    let mut warnings = tracer.warnings();
+    warnings.extend(world.package_warnings());
 
     match result {
         // Retrieve and print query results.
         Ok(document) => {
-            let data = retrieve(&world, command, &document)?;
+            warnings.extend(world.record_packages());
+            let data = retrieve(&*world, command, &document)?;
             let serialized = format(data, command)?;
             println!("{serialized}");
-            print_diagnostics(&world, &[], &warnings, command.common.diagnostic_format)
+            print_diagnostics(world, &[], &warnings, command.common.diagnostic_format)
                 .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
         }
 
@@ -38,7 +69,7 @@ pub fn query(command: &QueryCommand) -> StrResult<()> {
         Err(errors) => {
             set_failed();
             print_diagnostics(
-                &world,
+                world,
                 &errors,
                 &warnings,
                 command.common.diagnostic_format,
@@ -51,7 +82,7 @@ pub fn query(command: &QueryCommand) -> StrResult<()> {
 }
 
 /// Retrieve the matches for the selector.
-fn retrieve(
+pub fn retrieve(
     world: &dyn World,
     command: &QueryCommand,
     document: &Document,
@@ -82,7 +113,7 @@ fn retrieve(
 }
 
 /// Format the query result in the output format.
-fn format(elements: Vec<Content>, command: &QueryCommand) -> StrResult<String> {
+pub fn format(elements: Vec<Content>, command: &QueryCommand) -> StrResult<String> {
     if command.one && elements.len() != 1 {
         bail!("expected exactly one element, found {}", elements.len());
     }
@@ -99,20 +130,84 @@ fn format(elements: Vec<Content>, command: &QueryCommand) -> StrResult<String> {
         let Some(value) = mapped.first() else {
             bail!("no such field found for element");
         };
-        serialize(value, command.format)
+        serialize(value, command.format, command.watch)
     } else {
-        serialize(&mapped, command.format)
+        serialize(&mapped, command.format, command.watch)
     }
 }
 
 /// Serialize data to the output format.
-fn serialize(data: &impl Serialize, format: SerializationFormat) -> StrResult<String> {
+///
+/// When `watching`, the result is formatted so that consecutive results can
+/// be told apart in a stream.
+fn serialize(
+    data: &impl Serialize,
+    format: SerializationFormat,
+    watching: bool,
+) -> StrResult<String> {
     match format {
+        SerializationFormat::Json if watching => {
+            serde_json::to_string(data).map_err(|e| eco_format!("{e}"))
+        }
         SerializationFormat::Json => {
             serde_json::to_string_pretty(data).map_err(|e| eco_format!("{e}"))
         }
         SerializationFormat::Yaml => {
-            serde_yaml::to_string(&data).map_err(|e| eco_format!("{e}"))
+            let yaml = serde_yaml::to_string(&data).map_err(|e| eco_format!("{e}"))?;
+            Ok(if watching { format!("---\n{}", yaml.trim_end()) } else { yaml })
+        }
+        SerializationFormat::Csv => {
+            let value = serde_json::to_value(data).map_err(|e| eco_format!("{e}"))?;
+            to_csv(value)
         }
     }
 }
+
+/// Write data as CSV.
+///
+/// Each element of an array becomes a row. If all rows are dictionaries, they
+/// get a column for each of their keys. Otherwise, there is a single `value`
+/// column.
+fn to_csv(value: serde_json::Value) -> StrResult<String> {
+    let rows = match value {
+        serde_json::Value::Array(rows) => rows,
+        value => vec![value],
+    };
+
+    let columns: Vec<String> = if rows.iter().all(serde_json::Value::is_object) {
+        let mut columns = vec![];
+        for row in &rows {
+            for key in row.as_object().into_iter().flat_map(|row| row.keys()) {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+        columns
+    } else {
+        vec!["value".into()]
+    };
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let failed = |err| eco_format!("failed to write CSV ({err})");
+    writer.write_record(&columns).map_err(failed)?;
+    for row in &rows {
+        let cells = match row.as_object() {
+            Some(row) => columns.iter().map(|column| csv_cell(row.get(column))).collect(),
+            None => vec![csv_cell(Some(row))],
+        };
+        writer.write_record(cells).map_err(failed)?;
+    }
+
+    let bytes = writer.into_inner().map_err(|err| failed(err.into_error().into()))?;
+    String::from_utf8(bytes).map_err(|err| eco_format!("{err}"))
+}
+
+/// The text of a CSV cell for a value.
+fn csv_cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(string)) => string.clone(),
+        Some(value) => value.to_string(),
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use ecow::eco_format;
+use parking_lot::Mutex;
+use typst::diag::{FileError, FileResult, PackageError};
+use typst::foundations::Bytes;
+use typst::syntax::{FileId, PackageSpec, VirtualPath};
+
+use crate::lock::{Lockfile, LOCKFILE};
+use crate::package::{contents_checksum, prepare_package};
+use crate::permissions::Permissions;
+
+/// Resolves file ids to files on the system.
+///
+/// The world consults its resolvers in order and uses the first one that
+/// handles a file. Other sources of files (archives, databases, in-memory
+/// bundles) can be supported by adding a resolver instead of changing the
+/// world itself.
+pub trait FileResolver: Send + Sync {
+    /// Whether this resolver is responsible for the file.
+    fn handles(&self, id: FileId) -> bool;
+
+    /// The location of the file on the system.
+    ///
+    /// This is what is reported as a dependency of the compilation.
+    fn path(&self, id: FileId) -> FileResult<PathBuf>;
+
+    /// Read the contents of the file.
+    fn read(&self, id: FileId) -> FileResult<Bytes> {
+        read(&self.path(id)?, None)
+    }
+
+    /// A cheap stamp identifying the current version of the file, if
+    /// available.
+    ///
+    /// When the stamp is unchanged, the world reuses the previously read
+    /// contents without reading the file again.
+    fn stamp(&self, id: FileId) -> Option<Stamp> {
+        Stamp::of(&self.path(id).ok()?)
+    }
+}
+
+/// Identifies a version of a file on the system without reading it.
+///
+/// Modification time and size alone miss quick edits that keep the size
+/// within the timestamp granularity. On Unix, the stamp therefore also
+/// includes the inode, which changes when an editor saves by replacing the
+/// file, and the status change time, which changes on every write.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Stamp {
+    /// The time the file was last modified.
+    modified: SystemTime,
+    /// The size of the file in bytes.
+    len: u64,
+    /// The file's inode number.
+    #[cfg(unix)]
+    inode: u64,
+    /// The time the file's status last changed, in seconds and nanoseconds.
+    #[cfg(unix)]
+    changed: (i64, i64),
+}
+
+impl Stamp {
+    /// Determine the stamp of the file at the given path.
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        if metadata.is_dir() {
+            return None;
+        }
+
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+            #[cfg(unix)]
+            inode: metadata.ino(),
+            #[cfg(unix)]
+            changed: (metadata.ctime(), metadata.ctime_nsec()),
+        })
+    }
+}
+
+/// Resolves files in the project root.
+pub struct ProjectResolver {
+    /// The root relative to which absolute paths are resolved.
+    root: PathBuf,
+    /// What the resolver may access.
+    permissions: Permissions,
+    /// The size from which files are memory-mapped, if any.
+    mmap_threshold: Option<u64>,
+}
+
+impl ProjectResolver {
+    /// Create a resolver for the given project root.
+    ///
+    /// Files of at least `mmap_threshold` bytes are memory-mapped instead of
+    /// read into memory. Commands that keep loaded files across compilations
+    /// must not enable this: The user is expected to edit files then, and a
+    /// mapped file that is truncated or rewritten in place would crash the
+    /// process or silently change under memoized results.
+    pub fn new(
+        root: PathBuf,
+        permissions: Permissions,
+        mmap_threshold: Option<u64>,
+    ) -> Self {
+        Self { root, permissions, mmap_threshold }
+    }
+}
+
+impl FileResolver for ProjectResolver {
+    fn handles(&self, id: FileId) -> bool {
+        id.package().is_none()
+    }
+
+    fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        resolve(id.vpath(), &self.root, &self.permissions)
+    }
+
+    fn read(&self, id: FileId) -> FileResult<Bytes> {
+        read(&self.path(id)?, self.mmap_threshold)
+    }
+}
+
+/// Resolves files in packages, downloading the package if necessary.
+pub struct PackageResolver {
+    /// What the resolver may access.
+    permissions: Permissions,
+    /// The lockfile that packages must be recorded in, with `--locked`.
+    lockfile: Option<Lockfile>,
+    /// The packages whose contents were found to match their checksum in the
+    /// lockfile.
+    verified: Mutex<HashSet<PackageSpec>>,
+}
+
+impl PackageResolver {
+    /// Check that a package is recorded in the lockfile, if there is one, and
+    /// that its contents in `dir` match the recorded checksum.
+    fn verify(&self, spec: &PackageSpec, dir: &Path) -> FileResult<()> {
+        let Some(lockfile) = &self.lockfile else { return Ok(()) };
+        let Some(expected) = lockfile.checksum(spec) else { return Ok(()) };
+        if self.verified.lock().contains(spec) {
+            return Ok(());
+        }
+
+        let checksum =
+            contents_checksum(dir).map_err(|err| FileError::from_io(err, dir))?;
+        if checksum != expected {
+            return Err(PackageError::Other(Some(eco_format!(
+                "package contents do not match the checksum in {LOCKFILE}"
+            )))
+            .into());
+        }
+
+        self.verified.lock().insert(spec.clone());
+        Ok(())
+    }
+}
+
+impl FileResolver for PackageResolver {
+    fn handles(&self, id: FileId) -> bool {
+        id.package().is_some()
+    }
+
+    fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        let spec = id.package().ok_or(FileError::AccessDenied)?;
+        if self
+            .lockfile
+            .as_ref()
+            .is_some_and(|lockfile| !lockfile.contains(spec))
+        {
+            return Err(PackageError::Other(Some(eco_format!(
+                "package is not recorded in {LOCKFILE}"
+            )))
+            .into());
+        }
+        let sha256 = self.lockfile.as_ref().and_then(|lockfile| lockfile.sha256(spec));
+        let root = prepare_package(spec, &self.permissions, sha256)?;
+        self.verify(spec, &root)?;
+        resolve(id.vpath(), &root, &self.permissions)
+    }
+}
+
+/// Resolves files in packages that are replaced with local directories, e.g.
+/// working copies of packages under development.
+pub struct LocalPackageResolver {
+    /// The packages and their directories, as given by `--package-path`.
+    paths: Vec<(String, PathBuf)>,
+    /// What the resolver may access.
+    permissions: Permissions,
+}
+
+impl LocalPackageResolver {
+    /// The directory a package resolves to, if any.
+    fn root(&self, spec: &PackageSpec) -> Option<&Path> {
+        local_package_root(&self.paths, spec)
+    }
+}
+
+/// The directory that a package resolves to with the given `--package-path`
+/// arguments, if any. A package with a version takes precedence over one
+/// without.
+pub fn local_package_root<'a>(
+    paths: &'a [(String, PathBuf)],
+    spec: &PackageSpec,
+) -> Option<&'a Path> {
+    let exact = spec.to_string();
+    let any = eco_format!("@{}/{}", spec.namespace, spec.name);
+    let find = |key: &str| paths.iter().rev().find(|(package, _)| package == key);
+    find(&exact).or_else(|| find(&any)).map(|(_, dir)| dir.as_path())
+}
+
+impl FileResolver for LocalPackageResolver {
+    fn handles(&self, id: FileId) -> bool {
+        id.package().is_some_and(|spec| self.root(spec).is_some())
+    }
+
+    fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        let spec = id.package().ok_or(FileError::AccessDenied)?;
+        let root = self.root(spec).ok_or(FileError::AccessDenied)?;
+        resolve(id.vpath(), root, &self.permissions)
+    }
+}
+
+/// An ordered list of resolvers, with in-memory overlays on top.
+#[derive(Clone)]
+pub struct Resolvers {
+    /// The resolvers, in order of priority. Shared with world snapshots.
+    list: Vec<Arc<dyn FileResolver>>,
+    /// Contents that shadow the resolved files, e.g. unsaved editor buffers.
+    overlays: HashMap<FileId, Bytes>,
+}
+
+impl Resolvers {
+    /// The default resolvers for project and package files, with packages
+    /// resolved to local directories first. With a lockfile, only the
+    /// packages recorded in it are resolved. See [`ProjectResolver::new`] for
+    /// the `mmap_threshold`.
+    pub fn new(
+        root: PathBuf,
+        permissions: Permissions,
+        package_paths: Vec<(String, PathBuf)>,
+        lockfile: Option<Lockfile>,
+        mmap_threshold: Option<u64>,
+    ) -> Self {
+        Self {
+            list: vec![
+                Arc::new(ProjectResolver::new(root, permissions, mmap_threshold)),
+                Arc::new(LocalPackageResolver { paths: package_paths, permissions }),
+                Arc::new(PackageResolver {
+                    permissions,
+                    lockfile,
+                    verified: Mutex::new(HashSet::new()),
+                }),
+            ],
+            overlays: HashMap::new(),
+        }
+    }
+
+    /// Shadow a file with in-memory contents.
+    pub fn overlay(&mut self, id: FileId, data: Vec<u8>) {
+        self.overlays.insert(id, data.into());
+    }
+
+    /// Remove the overlay of a file, if any, and return whether there was one.
+    pub fn remove_overlay(&mut self, id: FileId) -> bool {
+        self.overlays.remove(&id).is_some()
+    }
+
+    /// The location of a file on the system.
+    ///
+    /// Overlaid files still report the location of the file they shadow.
+    pub fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        self.find(id)?.path(id)
+    }
+
+    /// Read the contents of a file, preferring its overlay.
+    pub fn read(&self, id: FileId) -> FileResult<Bytes> {
+        if let Some(data) = self.overlays.get(&id) {
+            return Ok(data.clone());
+        }
+
+        self.find(id)?.read(id)
+    }
+
+    /// The modification stamp of a file.
+    ///
+    /// Overlaid files have no stamp, so their contents are always compared
+    /// by fingerprint.
+    pub fn stamp(&self, id: FileId) -> Option<Stamp> {
+        if self.overlays.contains_key(&id) {
+            return None;
+        }
+
+        self.find(id).ok()?.stamp(id)
+    }
+
+    /// Find the first resolver that handles a file.
+    fn find(&self, id: FileId) -> FileResult<&dyn FileResolver> {
+        self.list
+            .iter()
+            .find(|resolver| resolver.handles(id))
+            .map(|resolver| resolver.as_ref())
+            .ok_or_else(|| FileError::NotFound(id.vpath().as_rootless_path().into()))
+    }
+}
+
+/// Join a virtual path to a root. If it tries to escape the root, deny access.
+///
+/// If the permissions allow it, the path can still escape via symlinks.
+fn resolve(
+    vpath: &VirtualPath,
+    root: &Path,
+    permissions: &Permissions,
+) -> FileResult<PathBuf> {
+    let path = if permissions.outside_root {
+        vpath.resolve(root)
+    } else {
+        vpath.resolve_confined(root)
+    };
+    path.ok_or(FileError::AccessDenied)
+}
+
+/// Read a file.
+///
+/// Files of at least `mmap_threshold` bytes are memory-mapped instead of read
+/// into memory.
+pub fn read(path: &Path, mmap_threshold: Option<u64>) -> FileResult<Bytes> {
+    let f = |e| FileError::from_io(e, path);
+    let metadata = fs::metadata(path).map_err(f)?;
+    if metadata.is_dir() {
+        return Err(FileError::IsDirectory);
+    }
+
+    if mmap_threshold.is_some_and(|threshold| metadata.len() >= threshold) {
+        if let Some(mmap) = map(path) {
+            return Ok(Bytes::from_shared(mmap));
+        }
+    }
+
+    fs::read(path).map(Bytes::from).map_err(f)
+}
+
+/// Memory-map a file, if possible.
+///
+/// Returns `None` if mapping fails, e.g. because the file system doesn't
+/// support it, so that the caller can fall back to reading the file.
+fn map(path: &Path) -> Option<memmap2::Mmap> {
+    let file = fs::File::open(path).ok()?;
+
+    // Safety: The file could be modified by other processes while it is mapped,
+    // which the `--mmap-threshold` option documents as unsupported.
+    unsafe { memmap2::Mmap::map(&file) }.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Permissions that only confine symlinks to the project root.
+    fn confined() -> Permissions {
+        Permissions { outside_root: false, ..Permissions::all() }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_project_resolver_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let root = dir.join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("main.typ"), "").unwrap();
+        fs::write(dir.join("secret.typ"), "").unwrap();
+        std::os::unix::fs::symlink("../secret.typ", root.join("link.typ")).unwrap();
+
+        let main = FileId::new(None, VirtualPath::new("main.typ"));
+        let link = FileId::new(None, VirtualPath::new("link.typ"));
+        let parent = FileId::new(None, VirtualPath::new("../secret.typ"));
+
+        let open = ProjectResolver::new(root.clone(), Permissions::all(), None);
+        assert_eq!(open.path(main), Ok(root.join("main.typ")));
+        assert_eq!(open.path(link), Ok(root.join("link.typ")));
+        assert_eq!(open.path(parent), Err(FileError::AccessDenied));
+
+        let confined = ProjectResolver::new(root.clone(), confined(), None);
+        assert_eq!(confined.path(main), Ok(root.join("main.typ")));
+        assert_eq!(confined.path(link), Err(FileError::AccessDenied));
+        assert_eq!(confined.path(parent), Err(FileError::AccessDenied));
+    }
+
+    #[test]
+    fn test_local_package_root() {
+        let paths = vec![
+            ("@local/pkg".to_string(), PathBuf::from("any")),
+            ("@local/pkg:0.1.0".to_string(), PathBuf::from("exact")),
+            ("@local/pkg".to_string(), PathBuf::from("later")),
+        ];
+        let root = |spec: &str| local_package_root(&paths, &spec.parse().unwrap());
+        assert_eq!(root("@local/pkg:0.1.0"), Some(Path::new("exact")));
+        assert_eq!(root("@local/pkg:0.2.0"), Some(Path::new("later")));
+        assert_eq!(root("@local/other:0.1.0"), None);
+    }
+
+    #[test]
+    fn test_resolvers() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("project");
+        let package = dir.path().join("package");
+        fs::create_dir(&root).unwrap();
+        fs::create_dir(&package).unwrap();
+        fs::write(root.join("main.typ"), "disk").unwrap();
+
+        let mut resolvers = Resolvers::new(
+            root.clone(),
+            Permissions::none(),
+            vec![("@local/pkg".into(), package.clone())],
+            None,
+            None,
+        );
+
+        // Packages replaced with local directories resolve there, even without
+        // network access.
+        let spec: PackageSpec = "@local/pkg:0.1.0".parse().unwrap();
+        let lib = FileId::new(Some(spec), VirtualPath::new("lib.typ"));
+        assert_eq!(resolvers.path(lib), Ok(package.join("lib.typ")));
+
+        // Overlays shadow the file's contents, but not its location.
+        let main = FileId::new(None, VirtualPath::new("main.typ"));
+        assert!(resolvers.stamp(main).is_some());
+        resolvers.overlay(main, b"overlay".to_vec());
+        assert_eq!(resolvers.read(main), Ok(Bytes::from_static(b"overlay")));
+        assert_eq!(resolvers.path(main), Ok(root.join("main.typ")));
+        assert_eq!(resolvers.stamp(main), None);
+        assert!(resolvers.remove_overlay(main));
+        assert!(!resolvers.remove_overlay(main));
+        assert!(resolvers.stamp(main).is_some());
+    }
+
+    #[test]
+    fn test_read_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.txt"), "contents").unwrap();
+        let id = FileId::new(None, VirtualPath::new("data.txt"));
+        for threshold in [None, Some(0), Some(1 << 20)] {
+            let resolver =
+                ProjectResolver::new(dir.path().into(), Permissions::all(), threshold);
+            assert_eq!(resolver.read(id), Ok(Bytes::from_static(b"contents")));
+        }
+
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let sub = FileId::new(None, VirtualPath::new("sub"));
+        let resolver =
+            ProjectResolver::new(dir.path().into(), Permissions::all(), Some(0));
+        assert_eq!(resolver.read(sub), Err(FileError::IsDirectory));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stamp_replaced() {
+        // Editors that save by replacing the file keep its size, and possibly
+        // its modification time within the timestamp granularity.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.typ");
+        fs::write(&path, "old").unwrap();
+        let before = Stamp::of(&path).unwrap();
+        assert_eq!(Stamp::of(&path), Some(before));
+
+        let temp = dir.path().join("main.typ~");
+        fs::write(&temp, "new").unwrap();
+        fs::rename(&temp, &path).unwrap();
+        assert_ne!(Stamp::of(&path), Some(before));
+        assert_eq!(Stamp::of(dir.path()), None);
+    }
+}
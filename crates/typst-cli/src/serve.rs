@@ -0,0 +1,515 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use ecow::eco_format;
+use parking_lot::{Condvar, Mutex};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use typst::diag::{bail, At, SourceDiagnostic, SourceResult, StrResult};
+use typst::eval::Tracer;
+use typst::model::Document;
+use typst::syntax::Span;
+use typst::visualize::Color;
+use typst::World;
+
+use crate::args::{PreviewFormat, ServeCommand};
+use crate::compile::{print_diagnostics, require_sarif_output, write_sarif};
+use crate::limits::enforce;
+use crate::watch::FileWatcher;
+use crate::world::SystemWorld;
+
+/// The GUID that is appended to the client's key in the websocket handshake.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How often connected viewers are pinged to detect closed connections.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many compilations run at the same time at most. Further changes are
+/// compiled once one of them finishes.
+const MAX_COMPILATIONS: usize = 2;
+
+/// How often finished compilations are picked up while waiting for changes.
+const FINISH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The page that displays the preview in the browser.
+const VIEWER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Typst Preview</title>
+<style>
+  body { margin: 0; padding: 16px; background: #707070; }
+  #pages { display: flex; flex-direction: column; align-items: center; gap: 16px; }
+  #pages img { max-width: 100%; background: white; box-shadow: 0 2px 8px #0006; }
+  #error { margin-bottom: 16px; padding: 8px 12px; border-radius: 4px;
+           background: #fdd; color: #800; font-family: sans-serif; }
+</style>
+</head>
+<body>
+<div id="error" hidden>Compilation failed. See the terminal for details.</div>
+<div id="pages"></div>
+<script>
+  let version = null;
+  let connected = false;
+
+  async function refresh() {
+    const state = await (await fetch("/state")).json();
+    if (state.version === version) return;
+    version = state.version;
+    document.getElementById("error").hidden = !state.failed;
+    document.getElementById("pages").replaceChildren(
+      ...Array.from({ length: state.pages }, (_, i) => {
+        const img = document.createElement("img");
+        img.src = `/page/${i}?v=${version}`;
+        return img;
+      })
+    );
+  }
+
+  function connect() {
+    const socket = new WebSocket(`ws://${location.host}/ws`);
+    socket.onopen = () => connected = true;
+    socket.onmessage = refresh;
+    socket.onclose = () => {
+      connected = false;
+      setTimeout(connect, 2000);
+    };
+  }
+
+  // Fall back to polling while the websocket is unavailable, e.g. behind a
+  // proxy that doesn't support them.
+  setInterval(() => connected || refresh(), 1000);
+  refresh();
+  connect();
+</script>
+</body>
+</html>
+"#;
+
+/// Execute a serve command.
+pub fn serve(command: &ServeCommand) -> StrResult<()> {
+    if command.common.reads_stdin() {
+        bail!("cannot serve input from stdin");
+    }
+    require_sarif_output(&command.common, "serve")?;
+
+    // Create the world that serves sources, files, and fonts.
+    let mut world = SystemWorld::new(&command.common)?;
+
+    let listener =
+        TcpListener::bind((command.host.as_str(), command.port)).map_err(|err| {
+            eco_format!("failed to listen on {}:{} ({err})", command.host, command.port)
+        })?;
+
+    let preview = Arc::new(Preview::new(command.format));
+    let server = preview.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let preview = server.clone();
+            // Errors only affect the connection, e.g. when the viewer was
+            // closed, so they are ignored.
+            thread::spawn(move || preview.handle(stream).ok());
+        }
+    });
+
+    let url = format!("http://{}:{}", command.host, command.port);
+    eprintln!("serving preview of {} at {url}", command.common.input.display());
+
+    // Perform initial compilation.
+    let compiled = compile(&world, command);
+    show(&world, command, &preview, compiled)?;
+    if command.open {
+        open::that_in_background(&url);
+    }
+
+    // Watch all the files that are used by the input file and its dependencies
+    // and recompile on changes.
+    let mut watcher = FileWatcher::new()?;
+    watcher.update(&mut world)?;
+    world.reset();
+
+    // Compilations run on snapshots of the world, so that changes made in the
+    // meantime are picked up right away instead of after the compilation.
+    // Only results that are newer than the ones shown already are shown.
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let mut started = 0;
+        let mut shown = 0;
+        let mut running = 0;
+        let mut pending = false;
+        loop {
+            let timeout = (running > 0).then_some(FINISH_INTERVAL);
+            if watcher.wait_timeout(&mut world, &[], timeout)? {
+                world.reset();
+                pending = true;
+            }
+
+            for (number, snapshot, compiled) in rx.try_iter() {
+                running -= 1;
+                if number > shown {
+                    shown = number;
+                    world.adopt(snapshot);
+                    show(&world, command, &preview, compiled)?;
+                    watcher.update(&mut world)?;
+                    world.reset();
+                    comemo::evict(10);
+                }
+            }
+
+            if pending && running < MAX_COMPILATIONS {
+                started += 1;
+                running += 1;
+                pending = false;
+
+                let number = started;
+                let snapshot = world.snapshot();
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let compiled = compile(&snapshot, command);
+                    tx.send((number, snapshot, compiled)).ok();
+                });
+            }
+        }
+    })
+}
+
+/// The outcome of a compilation in the preview server.
+struct Compiled {
+    /// The rendered pages or the errors.
+    result: SourceResult<Vec<Arc<[u8]>>>,
+    /// The warnings of the compilation.
+    warnings: Vec<SourceDiagnostic>,
+    /// How long compiling and rendering took.
+    duration: Duration,
+}
+
+/// Compile the document and render its pages.
+fn compile(world: &SystemWorld, command: &ServeCommand) -> Compiled {
+    let start = Instant::now();
+    let mut tracer = Tracer::new();
+    let result = world
+        .source(world.main())
+        .at(Span::detached())
+        .and_then(|_| enforce(|| typst::compile(world, &mut tracer)))
+        .and_then(|document| render(&document, command).at(Span::detached()));
+    let warnings = tracer.warnings().to_vec();
+    Compiled { result, warnings, duration: start.elapsed() }
+}
+
+/// Publish the rendered pages to the viewers and print the diagnostics.
+fn show(
+    world: &SystemWorld,
+    command: &ServeCommand,
+    preview: &Preview,
+    compiled: Compiled,
+) -> StrResult<()> {
+    let timestamp = chrono::offset::Local::now().format("%H:%M:%S");
+    let errors = match compiled.result {
+        Ok(pages) => {
+            preview.publish(Some(pages));
+            eprintln!("[{timestamp}] compiled in {:.2?}", compiled.duration);
+            vec![]
+        }
+        Err(errors) => {
+            preview.publish(None);
+            eprintln!("[{timestamp}] compiled with errors");
+            errors.to_vec()
+        }
+    };
+
+    print_diagnostics(
+        world,
+        &errors,
+        &compiled.warnings,
+        command.common.diagnostic_format,
+    )
+    .map_err(|err| eco_format!("failed to print diagnostics ({err})"))?;
+    write_sarif(command.common.sarif_output.as_deref())
+}
+
+/// Render all pages of a document in the preview format.
+fn render(document: &Document, command: &ServeCommand) -> StrResult<Vec<Arc<[u8]>>> {
+    document
+        .pages
+        .par_iter()
+        .map(|frame| match command.format {
+            PreviewFormat::Svg => Ok(typst_svg::svg(frame).into_bytes().into()),
+            PreviewFormat::Png => {
+                typst_render::render(frame, command.ppi / 72.0, Color::WHITE)
+                    .encode_png()
+                    .map(Into::into)
+                    .map_err(|err| eco_format!("failed to encode PNG ({err})"))
+            }
+        })
+        .collect()
+}
+
+/// The preview shared between the compiler and the connections.
+struct Preview {
+    /// The content type of the rendered pages.
+    content_type: &'static str,
+    /// The latest compilation result.
+    state: Mutex<State>,
+    /// Notified whenever the state changes.
+    changed: Condvar,
+}
+
+/// The latest compilation result.
+#[derive(Default)]
+struct State {
+    /// Increases with every compilation.
+    version: u64,
+    /// The rendered pages of the last successful compilation.
+    pages: Vec<Arc<[u8]>>,
+    /// Whether the last compilation failed.
+    failed: bool,
+}
+
+/// The state as reported to the viewer.
+#[derive(Serialize)]
+struct StateInfo {
+    version: u64,
+    pages: usize,
+    failed: bool,
+}
+
+impl Preview {
+    /// Create an empty preview.
+    fn new(format: PreviewFormat) -> Self {
+        Self {
+            content_type: match format {
+                PreviewFormat::Svg => "image/svg+xml",
+                PreviewFormat::Png => "image/png",
+            },
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Publish the result of a compilation, keeping the previous pages if it
+    /// failed.
+    fn publish(&self, pages: Option<Vec<Arc<[u8]>>>) {
+        let mut state = self.state.lock();
+        state.version += 1;
+        state.failed = pages.is_none();
+        if let Some(pages) = pages {
+            state.pages = pages;
+        }
+        self.changed.notify_all();
+    }
+
+    /// Answer an HTTP request.
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let request = Request::read(&mut BufReader::new(stream.try_clone()?))?;
+        if request.method != "GET" {
+            return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+        }
+
+        match request.path() {
+            "/" => respond(&mut stream, "200 OK", "text/html", VIEWER.as_bytes()),
+            "/state" => {
+                let info = {
+                    let state = self.state.lock();
+                    StateInfo {
+                        version: state.version,
+                        pages: state.pages.len(),
+                        failed: state.failed,
+                    }
+                };
+                let json = serde_json::to_vec(&info)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+                respond(&mut stream, "200 OK", "application/json", &json)
+            }
+            "/ws" => match &request.key {
+                Some(key) => self.push(stream, key),
+                None => respond(&mut stream, "400 Bad Request", "text/plain", b""),
+            },
+            _ => {
+                let page = request
+                    .path()
+                    .strip_prefix("/page/")
+                    .and_then(|index| index.parse::<usize>().ok())
+                    .and_then(|index| self.state.lock().pages.get(index).cloned());
+                match page {
+                    Some(page) => {
+                        respond(&mut stream, "200 OK", self.content_type, &page)
+                    }
+                    None => respond(&mut stream, "404 Not Found", "text/plain", b""),
+                }
+            }
+        }
+    }
+
+    /// Upgrade a connection to a websocket and notify the viewer of every new
+    /// version until the connection is closed.
+    fn push(&self, mut stream: TcpStream, key: &str) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key),
+        )?;
+
+        let mut version = self.state.lock().version;
+        loop {
+            let mut state = self.state.lock();
+            if state.version == version {
+                self.changed.wait_for(&mut state, PING_INTERVAL);
+            }
+
+            if state.version == version {
+                // Nothing changed for a while. Ping the viewer so that we
+                // notice when it's gone.
+                drop(state);
+                send_frame(&mut stream, 0x9, b"")?;
+            } else {
+                version = state.version;
+                drop(state);
+                send_frame(&mut stream, 0x1, version.to_string().as_bytes())?;
+            }
+        }
+    }
+}
+
+/// The parts of an HTTP request the server looks at.
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+    /// The request method, like `GET`.
+    method: String,
+    /// The request target, including the query.
+    target: String,
+    /// The value of the `Sec-WebSocket-Key` header, if any.
+    key: Option<String>,
+}
+
+impl Request {
+    /// Read the request line and headers of an HTTP request.
+    fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let mut request = Self {
+            method: parts.next().unwrap_or_default().into(),
+            target: parts.next().unwrap_or_default().into(),
+            key: None,
+        };
+
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("sec-websocket-key") {
+                    request.key = Some(value.trim().into());
+                }
+            }
+        }
+
+        Ok(request)
+    }
+
+    /// The target without the query.
+    fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+}
+
+/// Compute the `Sec-WebSocket-Accept` value for a client's key.
+fn accept_key(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD
+        .encode(Sha1::digest(format!("{key}{WEBSOCKET_GUID}")))
+}
+
+/// Write an HTTP response and close the connection.
+fn respond(
+    stream: &mut impl Write,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Send a small, unfragmented websocket frame with the given opcode.
+fn send_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    debug_assert!(payload.len() < 126);
+    stream.write_all(&[0x80 | opcode, payload.len() as u8])?;
+    stream.write_all(payload)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_read() {
+        let mut data: &[u8] = b"GET /page/2?v=7 HTTP/1.1\r\n\
+            Host: localhost:3000\r\n\
+            sec-websocket-key:  dGhlIHNhbXBsZSBub25jZQ== \r\n\
+            \r\n\
+            ignored body";
+        let request = Request::read(&mut data).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/page/2?v=7");
+        assert_eq!(request.path(), "/page/2");
+        assert_eq!(request.key.as_deref(), Some("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert_eq!(data, b"ignored body");
+    }
+
+    #[test]
+    fn test_request_read_truncated() {
+        let request = Request::read(&mut &b"POST /state"[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path(), "/state");
+        assert_eq!(request.key, None);
+        assert_eq!(Request::read(&mut &b""[..]).unwrap(), Request::default());
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_send_frame() {
+        let mut out = vec![];
+        send_frame(&mut out, 0x1, b"42").unwrap();
+        send_frame(&mut out, 0x9, b"").unwrap();
+        assert_eq!(out, [0x81, 2, b'4', b'2', 0x89, 0]);
+    }
+
+    #[test]
+    fn test_respond() {
+        let mut out = vec![];
+        respond(&mut out, "404 Not Found", "text/plain", b"gone").unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(text.contains("Content-Type: text/plain\r\n"));
+        assert!(text.contains("Content-Length: 4\r\n"));
+        assert!(text.ends_with("\r\n\r\ngone"));
+    }
+}
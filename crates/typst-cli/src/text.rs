@@ -0,0 +1,469 @@
+use std::num::NonZeroUsize;
+
+use ecow::EcoString;
+use typst::foundations::StyleChain;
+use typst::introspection::{Location, Meta};
+use typst::layout::{Abs, Frame, FrameItem, Point, Transform};
+use typst::math::EquationElem;
+use typst::model::{Destination, Document, HeadingElem};
+
+/// How extracted text is formatted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextFormat {
+    /// Plain text with paragraphs separated by blank lines.
+    Plain,
+    /// Markdown with headings, lists and links.
+    Markdown,
+}
+
+/// Extract the readable text of some pages of a document.
+///
+/// The text is recovered from the laid out pages. Lines are joined into
+/// paragraphs based on their spacing, so the result follows the reading order
+/// of the layout rather than the structure of the markup. Headings and links
+/// are recognized through their introspection markers, and lists through the
+/// markers in front of their items.
+pub fn text(document: &Document, pages: &[usize], format: TextFormat) -> String {
+    let mut writer = Writer { format, out: String::new(), indents: vec![] };
+    for &i in pages {
+        let mut page = Page::default();
+        page.collect(&document.pages[i], Transform::identity());
+        writer.write_lines(&page.lines());
+    }
+
+    writer.out.truncate(writer.out.trim_end().len());
+    writer.out.push('\n');
+    writer.out
+}
+
+/// A piece of text on a page.
+struct Run {
+    /// The start of the run's baseline.
+    pos: Point,
+    /// Where the run ends horizontally.
+    end: Abs,
+    /// The font size.
+    size: Abs,
+    /// The run's text.
+    text: EcoString,
+}
+
+/// An area of a page that a heading, block equation or link covers.
+struct Area {
+    /// The top left corner.
+    min: Point,
+    /// The bottom right corner.
+    max: Point,
+    /// What covers the area.
+    kind: AreaKind,
+}
+
+/// What covers an area of a page.
+enum AreaKind {
+    /// A heading of the given level.
+    Heading(NonZeroUsize),
+    /// A block equation.
+    Equation(Location),
+    /// A link to a URL.
+    Link(EcoString),
+}
+
+/// The text and marked areas of a page.
+#[derive(Default)]
+struct Page {
+    runs: Vec<Run>,
+    areas: Vec<Area>,
+}
+
+impl Page {
+    /// Collect the runs and areas in a frame.
+    fn collect(&mut self, frame: &Frame, ts: Transform) {
+        for &(pos, ref item) in frame.items() {
+            match item {
+                FrameItem::Group(group) => {
+                    let ts = ts
+                        .pre_concat(Transform::translate(pos.x, pos.y))
+                        .pre_concat(group.transform);
+                    self.collect(&group.frame, ts);
+                }
+                FrameItem::Text(text) => {
+                    let start = pos.transform(ts);
+                    let end = (pos + Point::with_x(text.width())).transform(ts);
+                    self.runs.push(Run {
+                        pos: start,
+                        end: end.x,
+                        size: text.size,
+                        text: text.text.clone(),
+                    });
+                }
+                FrameItem::Meta(meta, size) => {
+                    let kind = match meta {
+                        Meta::Elem(elem) => {
+                            if let Some(heading) = elem.to::<HeadingElem>() {
+                                AreaKind::Heading(heading.level(StyleChain::default()))
+                            } else if let Some(location) = elem.location().filter(|_| {
+                                elem.to::<EquationElem>()
+                                    .is_some_and(|eq| eq.block(StyleChain::default()))
+                            }) {
+                                AreaKind::Equation(location)
+                            } else {
+                                continue;
+                            }
+                        }
+                        Meta::Link(Destination::Url(url)) => AreaKind::Link(url.clone()),
+                        _ => continue,
+                    };
+                    let a = pos.transform(ts);
+                    let b = (pos + size.to_point()).transform(ts);
+                    self.areas.push(Area {
+                        min: Point::new(a.x.min(b.x), a.y.min(b.y)),
+                        max: Point::new(a.x.max(b.x), a.y.max(b.y)),
+                        kind,
+                    });
+                }
+                FrameItem::Shape(..) | FrameItem::Image(..) => {}
+            }
+        }
+    }
+
+    /// The heading level, block equation and link URL of a run, if it lies
+    /// within their areas.
+    fn lookup(
+        &self,
+        run: &Run,
+    ) -> (Option<NonZeroUsize>, Option<Location>, Option<EcoString>) {
+        // The middle of the run, slightly above its baseline.
+        let point = Point::new((run.pos.x + run.end) / 2.0, run.pos.y - run.size * 0.3);
+
+        let mut heading = None;
+        let mut equation = None;
+        let mut link = None;
+        for area in &self.areas {
+            if point.x < area.min.x
+                || point.x > area.max.x
+                || point.y < area.min.y
+                || point.y > area.max.y
+            {
+                continue;
+            }
+            match &area.kind {
+                AreaKind::Heading(level) => heading = Some(*level),
+                AreaKind::Equation(location) => equation = Some(*location),
+                AreaKind::Link(url) => link = Some(url.clone()),
+            }
+        }
+
+        (heading, equation, link)
+    }
+
+    /// Group the runs into lines.
+    fn lines(&self) -> Vec<Line> {
+        let mut lines: Vec<Line> = vec![];
+        for run in &self.runs {
+            if run.text.trim().is_empty() {
+                continue;
+            }
+
+            let (heading, equation, link) = self.lookup(run);
+            let piece = Piece { text: run.text.clone(), link };
+
+            // Runs that continue to the right at about the same height, like
+            // sub- and superscripts, belong to the same line. Block equations
+            // are kept on one line.
+            if let Some(line) = lines.last_mut() {
+                let tolerance = run.size.max(line.size) * 0.5;
+                if (equation.is_some() && line.equation == equation)
+                    || ((run.pos.y - line.y).abs() <= tolerance
+                        && run.pos.x >= line.end - run.size)
+                {
+                    if equation.is_some() || run.pos.x - line.end > run.size * 0.15 {
+                        line.pieces.push(Piece { text: " ".into(), link: None });
+                    }
+                    line.pieces.push(piece);
+                    line.end = line.end.max(run.end);
+                    line.size = line.size.max(run.size);
+                    continue;
+                }
+            }
+
+            lines.push(Line {
+                x: run.pos.x,
+                y: run.pos.y,
+                end: run.end,
+                size: run.size,
+                heading,
+                equation,
+                pieces: vec![piece],
+            });
+        }
+        lines
+    }
+}
+
+/// A line of text.
+struct Line {
+    /// Where the line starts horizontally.
+    x: Abs,
+    /// The line's baseline.
+    y: Abs,
+    /// Where the line ends horizontally.
+    end: Abs,
+    /// The largest font size in the line.
+    size: Abs,
+    /// The level of the heading the line belongs to, if any.
+    heading: Option<NonZeroUsize>,
+    /// The block equation the line belongs to, if any.
+    equation: Option<Location>,
+    /// The line's text.
+    pieces: Vec<Piece>,
+}
+
+impl Line {
+    /// The marker at the start of the line if it is a list item, and the
+    /// pieces after it.
+    fn list_marker(&self) -> Option<(&str, &[Piece])> {
+        let (first, rest) = self.pieces.split_first()?;
+        let marker = first.text.trim();
+        let is_marker = matches!(marker, "•" | "‣" | "–" | "-")
+            || marker
+                .strip_suffix(['.', ')'])
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        let rest = match rest {
+            [space, rest @ ..] if space.text == " " => rest,
+            rest => rest,
+        };
+        (is_marker && !rest.is_empty()).then_some((marker, rest))
+    }
+}
+
+/// A part of a line.
+struct Piece {
+    /// The text.
+    text: EcoString,
+    /// The URL the text links to, if any.
+    link: Option<EcoString>,
+}
+
+/// Writes lines as text.
+struct Writer {
+    format: TextFormat,
+    out: String,
+    /// The horizontal positions of the markers of the enclosing list items.
+    indents: Vec<Abs>,
+}
+
+impl Writer {
+    /// Write the lines of a page.
+    fn write_lines(&mut self, lines: &[Line]) {
+        let mut prev: Option<&Line> = None;
+        for line in lines {
+            let marker = line
+                .list_marker()
+                .filter(|_| line.heading.is_none() && line.equation.is_none());
+
+            // Paragraphs are spaced further apart than their lines, and
+            // headings and block equations always stand alone.
+            let continues = prev.is_some_and(|prev| {
+                prev.heading == line.heading
+                    && prev.equation.is_none()
+                    && line.equation.is_none()
+                    && line.y - prev.y <= line.size.max(prev.size) * 1.6
+                    && line.y > prev.y
+            });
+
+            if continues && marker.is_none() {
+                if !self.out.ends_with(char::is_whitespace) {
+                    self.out.push(' ');
+                }
+                self.write_pieces(&line.pieces);
+            } else {
+                self.start_block(continues && marker.is_some());
+                match marker {
+                    Some((marker, rest)) => self.write_item(line, marker, rest),
+                    None => {
+                        self.indents.clear();
+                        if let (Some(level), TextFormat::Markdown) =
+                            (line.heading, self.format)
+                        {
+                            for _ in 0..level.get() {
+                                self.out.push('#');
+                            }
+                            self.out.push(' ');
+                        }
+                        self.write_pieces(&line.pieces);
+                    }
+                }
+            }
+
+            prev = Some(line);
+        }
+
+        self.start_block(false);
+    }
+
+    /// End the current line and, unless `tight`, leave a blank line.
+    fn start_block(&mut self, tight: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.truncate(self.out.trim_end().len());
+        self.out.push_str(if tight { "\n" } else { "\n\n" });
+    }
+
+    /// Write the first line of a list item.
+    fn write_item(&mut self, line: &Line, marker: &str, rest: &[Piece]) {
+        // Items further to the right are nested in the previous ones.
+        while self.indents.last().is_some_and(|&x| x > line.x + Abs::pt(0.5)) {
+            self.indents.pop();
+        }
+        if self.indents.last().map_or(true, |&x| x < line.x - Abs::pt(0.5)) {
+            self.indents.push(line.x);
+        }
+
+        for _ in 1..self.indents.len() {
+            self.out.push_str("    ");
+        }
+
+        let bullet = !marker.starts_with(|c: char| c.is_ascii_digit());
+        match self.format {
+            TextFormat::Markdown if bullet => self.out.push('-'),
+            _ => self.out.push_str(marker),
+        }
+        self.out.push(' ');
+        self.write_pieces(rest);
+    }
+
+    /// Write the pieces of a line, with Markdown links for consecutive pieces
+    /// that link to the same URL.
+    fn write_pieces(&mut self, pieces: &[Piece]) {
+        let mut i = 0;
+        while i < pieces.len() {
+            let link = pieces[i]
+                .link
+                .as_ref()
+                .filter(|_| self.format == TextFormat::Markdown);
+            let Some(url) = link else {
+                self.write_str(&pieces[i].text);
+                i += 1;
+                continue;
+            };
+
+            let len = pieces[i..]
+                .iter()
+                .take_while(|piece| piece.link.as_ref() == Some(url))
+                .count();
+            self.out.push('[');
+            for piece in &pieces[i..i + len] {
+                self.write_str(&piece.text);
+            }
+            self.out.push_str("](");
+            self.out
+                .push_str(&url.as_str().replace(' ', "%20").replace(')', "%29"));
+            self.out.push(')');
+            i += len;
+        }
+    }
+
+    /// Write text, escaping characters with a meaning in Markdown.
+    fn write_str(&mut self, text: &str) {
+        if self.format == TextFormat::Plain {
+            self.out.push_str(text);
+            return;
+        }
+
+        let line_start = self.out.is_empty() || self.out.ends_with('\n');
+        for (i, c) in text.chars().enumerate() {
+            let special = matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<')
+                || (i == 0 && line_start && matches!(c, '#' | '>' | '+' | '-'));
+            if special {
+                self.out.push('\\');
+            }
+            self.out.push(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run of 10pt text whose characters are 5pt wide, with its baseline
+    /// starting at the given position.
+    fn run(x: f64, y: f64, text: &str) -> Run {
+        Run {
+            pos: Point::new(Abs::pt(x), Abs::pt(y)),
+            end: Abs::pt(x + 5.0 * text.chars().count() as f64),
+            size: Abs::pt(10.0),
+            text: text.into(),
+        }
+    }
+
+    /// Write runs without any marked areas.
+    fn write(runs: Vec<Run>, format: TextFormat) -> String {
+        let page = Page { runs, areas: vec![] };
+        let mut writer = Writer { format, out: String::new(), indents: vec![] };
+        writer.write_lines(&page.lines());
+        writer.out
+    }
+
+    #[test]
+    fn test_text_lines() {
+        let page = Page {
+            runs: vec![
+                run(0.0, 10.0, "Hello"),
+                run(30.0, 10.0, "world"),
+                run(55.0, 7.0, "2"),
+                run(0.0, 22.0, "Next"),
+                run(25.0, 22.0, "line"),
+            ],
+            areas: vec![],
+        };
+
+        let lines = page.lines();
+        let text = |line: &Line| {
+            line.pieces
+                .iter()
+                .map(|piece| piece.text.as_str())
+                .collect::<String>()
+        };
+        assert_eq!(lines.len(), 2);
+        assert_eq!(text(&lines[0]), "Hello world2");
+        assert_eq!(text(&lines[1]), "Next line");
+        assert_eq!(lines[0].end, Abs::pt(60.0));
+    }
+
+    #[test]
+    fn test_text_paragraphs() {
+        let runs = vec![
+            run(0.0, 10.0, "First"),
+            run(0.0, 22.0, "paragraph."),
+            run(0.0, 50.0, "Second."),
+        ];
+        assert_eq!(write(runs, TextFormat::Plain), "First paragraph.\n\nSecond.\n\n");
+    }
+
+    #[test]
+    fn test_text_list_indents() {
+        let runs = vec![
+            run(0.0, 10.0, "•"),
+            run(10.0, 10.0, "One"),
+            run(10.0, 22.0, "‣"),
+            run(20.0, 22.0, "Nested"),
+            run(0.0, 34.0, "•"),
+            run(10.0, 34.0, "Two"),
+            run(0.0, 46.0, "1."),
+            run(15.0, 46.0, "Three"),
+        ];
+        assert_eq!(
+            write(runs, TextFormat::Markdown),
+            "- One\n    - Nested\n- Two\n1. Three\n\n",
+        );
+    }
+
+    #[test]
+    fn test_text_markdown_escapes() {
+        let runs = || vec![run(0.0, 10.0, "#1 is *not* a"), run(0.0, 22.0, "#tag")];
+        assert_eq!(write(runs(), TextFormat::Markdown), "\\#1 is \\*not\\* a #tag\n\n");
+        assert_eq!(write(runs(), TextFormat::Plain), "#1 is *not* a #tag\n\n");
+    }
+}
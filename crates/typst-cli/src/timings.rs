@@ -1,18 +1,26 @@
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
 use std::io::BufWriter;
 use std::path::{Path, PathBuf};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant, SystemTime};
 
+use ecow::eco_format;
+use serde::Serialize;
 use typst::diag::{bail, StrResult};
 use typst::syntax::Span;
 use typst::World;
 
 use crate::args::{CliArguments, Command};
+use crate::download::take_download_timings;
 use crate::world::SystemWorld;
 
 /// Allows to record timings of function executions.
 pub struct Timer {
     /// Where to save the recorded timings of each compilation step.
     path: Option<PathBuf>,
+    /// Where to save the summary of the last compilation.
+    report: Option<PathBuf>,
     /// The current watch iteration.
     index: usize,
 }
@@ -21,21 +29,22 @@ impl Timer {
     /// Initializes the timing system and returns a timer that can be used to
     /// record timings for a specific function invocation.
     pub fn new(args: &CliArguments) -> Timer {
-        let record = match &args.command {
-            Command::Compile(command) => command.timings.clone(),
-            Command::Watch(command) => command.timings.clone(),
-            _ => None,
+        let (record, report) = match &args.command {
+            Command::Compile(command) | Command::Watch(command) => {
+                (command.timings.clone(), command.timings_report.clone())
+            }
+            _ => (None, None),
         };
 
         // Enable event collection.
-        if record.is_some() {
+        if record.is_some() || report.is_some() {
             typst_timing::enable();
         }
 
         let path =
             record.map(|path| path.unwrap_or_else(|| PathBuf::from("record-{n}.json")));
 
-        Timer { path, index: 0 }
+        Timer { path, report, index: 0 }
     }
 
     /// Records all timings in `f` and writes them to disk.
@@ -44,29 +53,43 @@ impl Timer {
         world: &mut SystemWorld,
         f: impl FnOnce(&mut SystemWorld) -> T,
     ) -> StrResult<T> {
-        let Some(path) = &self.path else {
+        if self.path.is_none() && self.report.is_none() {
             return Ok(f(world));
-        };
-
-        typst_timing::clear();
-
-        let string = path.to_str().unwrap_or_default();
-        let numbered = string.contains("{n}");
-        if !numbered && self.index > 0 {
-            bail!("cannot export multiple recordings without `{{n}}` in path");
         }
 
+        // The recording path of this compilation, if any.
         let storage;
-        let path = if numbered {
-            storage = string.replace("{n}", &self.index.to_string());
-            Path::new(&storage)
-        } else {
-            path.as_path()
+        let path = match &self.path {
+            Some(path) => {
+                let string = path.to_str().unwrap_or_default();
+                let numbered = string.contains("{n}");
+                if !numbered && self.index > 0 {
+                    bail!("cannot export multiple recordings without `{{n}}` in path");
+                }
+
+                Some(if numbered {
+                    storage = string.replace("{n}", &self.index.to_string());
+                    Path::new(&storage)
+                } else {
+                    path.as_path()
+                })
+            }
+            None => None,
         };
 
+        typst_timing::clear();
+        take_download_timings();
+
+        let start = Instant::now();
         let output = f(world);
+        let total = start.elapsed();
         self.index += 1;
 
+        if let Some(report) = &self.report {
+            write_report(report, total)?;
+        }
+
+        let Some(path) = path else { return Ok(output) };
         let file =
             File::create(path).map_err(|e| format!("failed to create file: {e}"))?;
         let writer = BufWriter::with_capacity(1 << 20, file);
@@ -79,6 +102,160 @@ impl Timer {
     }
 }
 
+/// Where a compilation spent its time, as written by `--timings-report`.
+#[derive(Serialize)]
+struct Report {
+    /// How long the compilation took.
+    total: f64,
+    /// How the total splits into phases. Time that belongs to no phase is
+    /// listed as `other`.
+    phases: BTreeMap<&'static str, f64>,
+    /// The files that were downloaded.
+    downloads: Vec<ReportedDownload>,
+}
+
+/// A download listed in a report.
+#[derive(Serialize)]
+struct ReportedDownload {
+    url: String,
+    time: f64,
+    bytes: usize,
+}
+
+/// The phases that a compilation's time is split into.
+const PHASES: &[&str] = &["parse", "load", "network", "eval", "layout", "export"];
+
+/// The phase that a recorded scope belongs to, if any.
+fn phase(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "parsing file" | "reparsing file" => "parse",
+        "loading file" | "hashing file" | "load font cache" | "scan fonts"
+        | "load font" | "load plugin" => "load",
+        "download" => "network",
+        "eval" => "eval",
+        "introspect" | "check stabilized" => "layout",
+        _ if name.starts_with("typeset") => "layout",
+        "export" => "export",
+        _ => return None,
+    })
+}
+
+/// Summarize the recorded timings and write them to a file.
+fn write_report(path: &Path, total: Duration) -> StrResult<()> {
+    let time = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+    };
+    let scopes: Vec<_> = typst_timing::measurements()
+        .into_iter()
+        .filter_map(|m| Some((phase(m.name)?, m.thread_id, time(m.start), time(m.end))))
+        .collect();
+
+    let (times, covered) = phase_times(&scopes);
+    let mut phases: BTreeMap<_, _> = PHASES.iter().map(|&name| (name, 0.0)).collect();
+    for (name, duration) in times {
+        phases.insert(name, millis(duration));
+    }
+    phases.insert("other", millis(total.saturating_sub(covered)));
+
+    let downloads = take_download_timings()
+        .into_iter()
+        .map(|download| ReportedDownload {
+            url: download.url,
+            time: millis(download.duration),
+            bytes: download.bytes,
+        })
+        .collect();
+
+    let report = Report { total: millis(total), phases, downloads };
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|err| eco_format!("failed to serialize timings report ({err})"))?;
+    fs::write(path, json)
+        .map_err(|err| eco_format!("failed to write timings report ({err})"))
+}
+
+/// A recorded scope with its phase, thread, and start and end time.
+type Scope = (&'static str, ThreadId, Duration, Duration);
+
+/// How much wall-clock time each phase took, and how much time any phase
+/// took.
+///
+/// Scopes of all threads count, like those of downloads in the prefetching
+/// pool. Phases nest, for instance when evaluating an import parses a file.
+/// Nested time only counts for the innermost phase. Time that a phase spent
+/// on several threads at once only counts once, but different phases that
+/// ran in parallel can add up to more than the total.
+fn phase_times(scopes: &[Scope]) -> (BTreeMap<&'static str, Duration>, Duration) {
+    let mut threads: HashMap<ThreadId, Vec<(&'static str, Duration, Duration)>> =
+        HashMap::new();
+    for &(name, thread, start, end) in scopes {
+        threads.entry(thread).or_default().push((name, start, end));
+    }
+
+    // Split each thread's time into segments that belong to the innermost
+    // phase, using a stack of the scopes that haven't ended yet.
+    let mut segments: Vec<(&'static str, Duration, Duration)> = vec![];
+    for mut scopes in threads.into_values() {
+        scopes.sort_by_key(|&(_, start, end)| (start, std::cmp::Reverse(end)));
+        let mut stack: Vec<(&'static str, Duration)> = vec![];
+        let mut cursor = Duration::ZERO;
+        for (name, start, end) in scopes {
+            while let Some(&(top, top_end)) = stack.last() {
+                if top_end > start {
+                    segments.push((top, cursor, start));
+                    break;
+                }
+                segments.push((top, cursor, top_end));
+                cursor = top_end;
+                stack.pop();
+            }
+            let end = stack.last().map_or(end, |&(_, parent_end)| end.min(parent_end));
+            stack.push((name, end));
+            cursor = start;
+        }
+        while let Some((top, top_end)) = stack.pop() {
+            segments.push((top, cursor, top_end));
+            cursor = top_end;
+        }
+    }
+
+    let mut times = BTreeMap::new();
+    let mut phases: Vec<_> = segments.iter().map(|&(name, _, _)| name).collect();
+    phases.sort();
+    phases.dedup();
+    for phase in phases {
+        let intervals = segments
+            .iter()
+            .filter(|&&(name, _, _)| name == phase)
+            .map(|&(_, start, end)| (start, end));
+        times.insert(phase, union(intervals));
+    }
+
+    let covered = union(segments.iter().map(|&(_, start, end)| (start, end)));
+    (times, covered)
+}
+
+/// The total length of the union of time intervals.
+fn union(intervals: impl Iterator<Item = (Duration, Duration)>) -> Duration {
+    let mut intervals: Vec<_> = intervals.filter(|(start, end)| start < end).collect();
+    intervals.sort();
+
+    let mut total = Duration::ZERO;
+    let mut reached = Duration::ZERO;
+    for (start, end) in intervals {
+        let start = start.max(reached);
+        if end > start {
+            total += end - start;
+            reached = end;
+        }
+    }
+    total
+}
+
+/// A duration in milliseconds.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
 /// Turns a span into a (file, line) pair.
 fn resolve_span(world: &SystemWorld, span: Span) -> Option<(String, u32)> {
     let id = span.id()?;
@@ -87,3 +264,30 @@ fn resolve_span(world: &SystemWorld, span: Span) -> Option<(String, u32)> {
     let line = source.byte_to_line(range.start)?;
     Some((format!("{id:?}"), line as u32 + 1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_times() {
+        let ms = Duration::from_millis;
+        let main = std::thread::current().id();
+        let [a, b] = [(); 2].map(|_| std::thread::spawn(|| std::thread::current().id()));
+        let (a, b) = (a.join().unwrap(), b.join().unwrap());
+
+        let (times, covered) = phase_times(&[
+            // Evaluation that parses a file along the way.
+            ("eval", main, ms(0), ms(10)),
+            ("parse", main, ms(2), ms(5)),
+            // Two downloads in parallel, overlapping the evaluation.
+            ("network", a, ms(8), ms(20)),
+            ("network", b, ms(9), ms(25)),
+        ]);
+
+        assert_eq!(times["eval"], ms(7));
+        assert_eq!(times["parse"], ms(3));
+        assert_eq!(times["network"], ms(17));
+        assert_eq!(covered, ms(25));
+    }
+}
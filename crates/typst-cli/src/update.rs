@@ -109,7 +109,7 @@ impl Release {
             ),
         };
 
-        match download(&url) {
+        match download(&url, None) {
             Ok(response) => response
                 .into_json()
                 .map_err(|err| eco_format!("unable to parse JSON response: {err}")),
@@ -131,7 +131,7 @@ impl Release {
             .ok_or("could not find release for your target platform")?;
 
         eprintln!("Downloading release ...");
-        let data = match download_with_progress(&asset.browser_download_url) {
+        let data = match download_with_progress(&asset.browser_download_url, None) {
             Ok(data) => data,
             Err(ureq::Error::Status(404, _)) => {
                 bail!("asset not found (searched for {})", asset.name);
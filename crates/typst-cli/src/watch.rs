@@ -1,129 +1,244 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
 
 use codespan_reporting::term::{self, termcolor};
 use ecow::eco_format;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 use same_file::is_same_file;
 use termcolor::WriteColor;
-use typst::diag::StrResult;
+use typst::diag::{bail, StrResult};
 
 use crate::args::CompileCommand;
 use crate::color_stream;
-use crate::compile::compile_once;
+use crate::compile::{compile_once, require_sarif_output, write_sarif};
+use crate::prefetch::prefetch_packages;
 use crate::timings::Timer;
 use crate::world::SystemWorld;
 
 /// Execute a watching compilation command.
 pub fn watch(mut timer: Timer, mut command: CompileCommand) -> StrResult<()> {
+    if command.common.reads_stdin() {
+        bail!("cannot watch input from stdin");
+    }
+    require_sarif_output(&command.common, "watch")?;
+
     // Create the world that serves sources, files, and fonts.
     let mut world = SystemWorld::new(&command.common)?;
 
-    // Perform initial compilation.
-    timer.record(&mut world, |world| compile_once(world, &mut command, true))??;
+    // Download the packages the document needs in parallel, then perform the
+    // initial compilation.
+    let mut outputs = timer.record(&mut world, |world| {
+        prefetch_packages(world);
+        compile_once(world, &mut command, true)
+    })??;
+    write_sarif(command.common.sarif_output.as_deref())?;
 
     // Setup file watching.
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())
-        .map_err(|err| eco_format!("failed to setup file watching ({err})"))?;
+    let mut watcher = FileWatcher::new()?;
 
     // Watch all the files that are used by the input file and its dependencies.
-    let mut watched = HashMap::new();
-    watch_dependencies(&mut world, &mut watcher, &mut watched)?;
+    watcher.update(&mut world)?;
 
     // Handle events.
-    let timeout = std::time::Duration::from_millis(100);
-    let output = command.output();
     loop {
-        let mut recompile = false;
-        for event in rx
-            .recv()
-            .into_iter()
-            .chain(std::iter::from_fn(|| rx.recv_timeout(timeout).ok()))
-        {
-            let event =
-                event.map_err(|err| eco_format!("failed to watch directory ({err})"))?;
-
-            // Workaround for notify-rs' implicit unwatch on remove/rename
-            // (triggered by some editors when saving files) with the inotify
-            // backend. By keeping track of the potentially unwatched files, we
-            // can allow those we still depend on to be watched again later on.
-            if matches!(
-                event.kind,
-                notify::EventKind::Remove(notify::event::RemoveKind::File)
-            ) {
-                // Mark the file as unwatched and remove the watch in case it
-                // still exists.
-                let path = &event.paths[0];
-                watched.remove(path);
-                watcher.unwatch(path).ok();
-            }
-
-            recompile |= is_event_relevant(&event, &output);
-        }
+        watcher.wait(&mut world, &outputs)?;
 
-        if recompile {
-            // Reset all dependencies.
-            world.reset();
+        // Reset all dependencies.
+        world.reset();
 
-            // Recompile.
-            timer
-                .record(&mut world, |world| compile_once(world, &mut command, true))??;
+        // Recompile.
+        outputs = timer
+            .record(&mut world, |world| compile_once(world, &mut command, true))??;
+        write_sarif(command.common.sarif_output.as_deref())?;
 
-            comemo::evict(10);
+        comemo::evict(10);
 
-            // Adjust the file watching.
-            watch_dependencies(&mut world, &mut watcher, &mut watched)?;
-        }
+        // Adjust the file watching.
+        watcher.update(&mut world)?;
     }
 }
 
-/// Adjust the file watching. Watches all new dependencies and unwatches
-/// all previously `watched` files that are no relevant anymore.
-fn watch_dependencies(
-    world: &mut SystemWorld,
-    watcher: &mut dyn Watcher,
-    watched: &mut HashMap<PathBuf, bool>,
-) -> StrResult<()> {
-    // Mark all files as not "seen" so that we may unwatch them if they aren't
-    // in the dependency list.
-    for seen in watched.values_mut() {
-        *seen = false;
+/// The interval in which files are polled if native file watching is
+/// unavailable.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches exactly the dependencies of the last compilation.
+///
+/// Uses the platform's native change notifications and falls back to polling
+/// if they are unavailable (e.g. on unsupported platforms or when the
+/// inotify watch limit is exhausted).
+pub struct FileWatcher {
+    /// The underlying watcher.
+    inner: Box<dyn Watcher>,
+    /// Where the watcher sends its events.
+    tx: Sender<notify::Result<notify::Event>>,
+    /// Where the events are received.
+    rx: Receiver<notify::Result<notify::Event>>,
+    /// Whether we fell back to polling.
+    polling: bool,
+    /// The watched paths and whether they were seen in the last dependency
+    /// list.
+    watched: HashMap<PathBuf, bool>,
+}
+
+impl FileWatcher {
+    /// Create a new watcher that doesn't watch anything yet.
+    pub fn new() -> StrResult<Self> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (inner, polling): (Box<dyn Watcher>, _) =
+            match RecommendedWatcher::new(tx.clone(), notify::Config::default()) {
+                Ok(watcher) => (Box::new(watcher), false),
+                Err(_) => (Box::new(poll_watcher(tx.clone())?), true),
+            };
+        Ok(Self { inner, tx, rx, polling, watched: HashMap::new() })
+    }
+
+    /// Block until a watched file changes in a way that is relevant for
+    /// compilation and make the world read the changed files again.
+    ///
+    /// Changes of the `outputs` are ignored.
+    pub fn wait(
+        &mut self,
+        world: &mut SystemWorld,
+        outputs: &[PathBuf],
+    ) -> StrResult<()> {
+        self.wait_timeout(world, outputs, None).map(drop)
+    }
+
+    /// Like [`wait`](Self::wait), but gives up once no event arrived within
+    /// the `timeout`. Returns whether a relevant change happened.
+    pub fn wait_timeout(
+        &mut self,
+        world: &mut SystemWorld,
+        outputs: &[PathBuf],
+        timeout: Option<Duration>,
+    ) -> StrResult<bool> {
+        let debounce = Duration::from_millis(100);
+        loop {
+            // Debounce: Block until the first event arrives and then collect
+            // all events that follow in short succession into one
+            // recompilation.
+            let mut changed = HashSet::new();
+            let first = match timeout {
+                Some(timeout) => match self.rx.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Ok(false),
+                    Err(err) => bail!("failed to watch directory ({err})"),
+                },
+                None => self
+                    .rx
+                    .recv()
+                    .map_err(|err| eco_format!("failed to watch directory ({err})"))?,
+            };
+            let rest = std::iter::from_fn(|| self.rx.recv_timeout(debounce).ok());
+            let events: Vec<_> = std::iter::once(first).chain(rest).collect();
+            for event in events {
+                let event = event
+                    .map_err(|err| eco_format!("failed to watch directory ({err})"))?;
+
+                // Workaround for notify-rs' implicit unwatch on remove/rename
+                // (triggered by some editors when saving files) with the
+                // inotify backend. By keeping track of the potentially
+                // unwatched files, we can allow those we still depend on to be
+                // watched again later on.
+                if matches!(
+                    event.kind,
+                    notify::EventKind::Remove(notify::event::RemoveKind::File)
+                ) {
+                    // Mark the file as unwatched and remove the watch in case
+                    // it still exists.
+                    self.forget(&event.paths[0]);
+                }
+
+                if is_event_relevant(&event, outputs) {
+                    changed.extend(event.paths);
+                }
+            }
+
+            // The file stamps may not reflect quick successive edits, so
+            // explicitly re-read whatever the watcher reported.
+            if !changed.is_empty() {
+                world.invalidate(&changed);
+                return Ok(true);
+            }
+        }
     }
 
-    // Retrieve the dependencies of the last compilation and watch new paths
-    // that weren't watched yet. We can't watch paths that don't exist yet
-    // unfortunately, so we filter those out.
-    for path in world.dependencies().filter(|path| path.exists()) {
-        if !watched.contains_key(&path) {
-            watcher
-                .watch(&path, RecursiveMode::NonRecursive)
-                .map_err(|err| eco_format!("failed to watch {path:?} ({err})"))?;
+    /// Adjust the file watching to the world's current dependencies, falling
+    /// back to polling if native watching fails.
+    pub fn update(&mut self, world: &mut SystemWorld) -> StrResult<()> {
+        match self.watch_dependencies(world) {
+            Err(_) if !self.polling => {
+                self.inner = Box::new(poll_watcher(self.tx.clone())?);
+                self.polling = true;
+                self.watched.clear();
+                self.watch_dependencies(world)
+            }
+            result => result,
         }
+    }
 
-        // Mark the file as "seen" so that we don't unwatch it.
-        watched.insert(path, true);
+    /// Mark a path as unwatched, e.g. because it was removed.
+    fn forget(&mut self, path: &Path) {
+        self.watched.remove(path);
+        self.inner.unwatch(path).ok();
     }
 
-    // Unwatch old paths that don't need to be watched anymore.
-    watched.retain(|path, &mut seen| {
-        if !seen {
-            watcher.unwatch(path).ok();
+    /// Watches all new dependencies and unwatches all previously watched
+    /// files that are not relevant anymore.
+    fn watch_dependencies(&mut self, world: &mut SystemWorld) -> StrResult<()> {
+        // Mark all files as not "seen" so that we may unwatch them if they
+        // aren't in the dependency list.
+        for seen in self.watched.values_mut() {
+            *seen = false;
+        }
+
+        // Retrieve the dependencies of the last compilation and watch new
+        // paths that weren't watched yet. We can't watch paths that don't
+        // exist yet unfortunately, so we filter those out.
+        for path in world.dependencies().filter(|path| path.exists()) {
+            if !self.watched.contains_key(&path) {
+                self.inner
+                    .watch(&path, RecursiveMode::NonRecursive)
+                    .map_err(|err| eco_format!("failed to watch {path:?} ({err})"))?;
+            }
+
+            // Mark the file as "seen" so that we don't unwatch it.
+            self.watched.insert(path, true);
         }
-        seen
-    });
 
-    Ok(())
+        // Unwatch old paths that don't need to be watched anymore.
+        let inner = &mut self.inner;
+        self.watched.retain(|path, &mut seen| {
+            if !seen {
+                inner.unwatch(path).ok();
+            }
+            seen
+        });
+
+        Ok(())
+    }
+}
+
+/// Create a watcher that polls for changes.
+fn poll_watcher(tx: Sender<notify::Result<notify::Event>>) -> StrResult<PollWatcher> {
+    let config = notify::Config::default().with_poll_interval(POLL_INTERVAL);
+    PollWatcher::new(tx, config)
+        .map_err(|err| eco_format!("failed to setup file watching ({err})"))
 }
 
 /// Whether a watch event is relevant for compilation.
-fn is_event_relevant(event: &notify::Event, output: &Path) -> bool {
-    // Never recompile because the output file changed.
-    if event
-        .paths
-        .iter()
-        .all(|path| is_same_file(path, output).unwrap_or(false))
+fn is_event_relevant(event: &notify::Event, outputs: &[PathBuf]) -> bool {
+    // Never recompile because output files changed.
+    if !outputs.is_empty()
+        && event.paths.iter().all(|path| {
+            outputs
+                .iter()
+                .any(|output| is_same_file(path, output).unwrap_or(false))
+        })
     {
         return false;
     }
@@ -155,7 +270,7 @@ pub enum Status {
 impl Status {
     /// Clear the terminal and render the status message.
     pub fn print(&self, command: &CompileCommand) -> io::Result<()> {
-        let output = command.output();
+        let outputs = command.outputs();
         let timestamp = chrono::offset::Local::now().format("%H:%M:%S");
         let color = self.color();
 
@@ -174,7 +289,11 @@ impl Status {
         w.set_color(&color)?;
         write!(w, "writing to")?;
         w.reset()?;
-        writeln!(w, " {}", output.display())?;
+        for (i, output) in outputs.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(w, "{separator}{}", output.display())?;
+        }
+        writeln!(w)?;
 
         writeln!(w)?;
         writeln!(w, "[{timestamp}] {}", self.message())?;
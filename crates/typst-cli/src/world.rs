@@ -1,15 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
-use std::{fs, mem};
+use std::sync::{Arc, OnceLock};
 
-use chrono::{DateTime, Datelike, Local};
+use chrono::{DateTime, Datelike, Local, Timelike};
 use comemo::Prehashed;
-use ecow::eco_format;
+use ecow::{eco_format, EcoString};
 use parking_lot::Mutex;
-use typst::diag::{FileError, FileResult, StrResult};
-use typst::foundations::{Bytes, Datetime, Dict, IntoValue};
-use typst::syntax::{FileId, Source, VirtualPath};
+use typst::diag::{bail, FileResult, SourceDiagnostic, StrResult};
+use typst::foundations::{Bytes, Datetime, Dict, IntoValue, Timezone};
+use typst::syntax::{FileId, PackageSpec, Source, Span, VirtualPath};
 use typst::text::{Font, FontBook};
 use typst::{Library, World};
 use typst_timing::{timed, TimingScope};
@@ -17,68 +20,120 @@ use typst_timing::{timed, TimingScope};
 use crate::args::SharedArgs;
 use crate::compile::ExportCache;
 use crate::fonts::{FontSearcher, FontSlot};
-use crate::package::prepare_package;
+use crate::limits::stop_if_cancelled;
+use crate::lock::{Lockfile, LOCKFILE};
+use crate::package::{self, local_packages, stored_checksum};
+use crate::permissions::Permissions;
+use crate::prefetch::package_imports;
+use crate::resolve::{local_package_root, Resolvers, Stamp};
+
+/// The virtual path at which a source read from stdin is placed.
+const STDIN_PATH: &str = "<stdin>";
 
 /// A world that provides access to the operating system.
 pub struct SystemWorld {
     /// The working directory.
     workdir: Option<PathBuf>,
-    /// The canonical path to the input file.
-    input: PathBuf,
+    /// The canonical path to the input file, if it wasn't read from stdin.
+    input: Option<PathBuf>,
     /// The root relative to which absolute paths are resolved.
     root: PathBuf,
+    /// What the compilation may access on the system.
+    permissions: Permissions,
+    /// The local directories that packages resolve to.
+    package_paths: Vec<(String, PathBuf)>,
+    /// Whether only packages recorded in the lockfile are resolved.
+    locked: bool,
+    /// Whether the lockfile is created if it doesn't exist yet.
+    lock: bool,
+    /// The size from which project files are memory-mapped, if any.
+    mmap_threshold: Option<u64>,
+    /// Resolves file ids to files on the system. Shared with snapshots until
+    /// either side changes the overlays.
+    resolvers: Arc<Resolvers>,
     /// The input path.
     main: FileId,
     /// Typst's standard library.
-    library: Prehashed<Library>,
+    library: Arc<Prehashed<Library>>,
     /// Metadata about discovered fonts.
-    book: Prehashed<FontBook>,
+    book: Arc<Prehashed<FontBook>>,
     /// Locations of and storage for lazily loaded fonts.
-    fonts: Vec<FontSlot>,
-    /// Maps file ids to source files and buffers.
-    slots: Mutex<HashMap<FileId, FileSlot>>,
+    fonts: Arc<Vec<FontSlot>>,
+    /// Maps file ids to source files and buffers. Shared with snapshots until
+    /// either side modifies it.
+    slots: Mutex<Arc<HashMap<FileId, FileSlot>>>,
     /// The current datetime if requested. This is stored here to ensure it is
     /// always the same within one compilation. Reset between compilations.
     now: OnceLock<DateTime<Local>>,
+    /// A fixed datetime to use instead of the current one, if any.
+    timestamp: Option<DateTime<Local>>,
     /// The export cache, used for caching output files in `typst watch`
     /// sessions.
     export_cache: ExportCache,
+    /// The packages that are known without a request, listed on first use.
+    /// Reset between compilations.
+    packages: OnceLock<Vec<(PackageSpec, Option<EcoString>)>>,
 }
 
 impl SystemWorld {
     /// Create a new system world.
     pub fn new(command: &SharedArgs) -> StrResult<Self> {
+        Self::new_mapped(command, None)
+    }
+
+    /// Create a new system world that memory-maps project files of at least
+    /// `mmap_threshold` bytes instead of reading them into memory.
+    ///
+    /// Only for commands that compile once, see
+    /// [`ProjectResolver::new`](crate::resolve::ProjectResolver::new).
+    pub fn new_mapped(
+        command: &SharedArgs,
+        mmap_threshold: Option<u64>,
+    ) -> StrResult<Self> {
         let mut searcher = FontSearcher::new();
         searcher.search(&command.font_paths);
 
-        // Resolve the system-global input path.
-        let input = command.input.canonicalize().map_err(|_| {
-            eco_format!("input file not found (searched at {})", command.input.display())
-        })?;
-
-        // Resolve the system-global root directory.
-        let root = {
-            let path = command
-                .root
-                .as_deref()
-                .or_else(|| input.parent())
-                .unwrap_or(Path::new("."));
-            path.canonicalize().map_err(|_| {
-                eco_format!("root directory not found (searched at {})", path.display())
-            })?
-        };
+        // Resolve the input file, the project root, and the main file.
+        let (input, root, main) = locate(command)?;
+
+        // Serve the source from stdin as an overlay.
+        let permissions = Permissions::from_args(command);
+        let mut resolvers = Resolvers::new(
+            root.clone(),
+            permissions,
+            command.package_paths.clone(),
+            lockfile(command, &root)?,
+            mmap_threshold,
+        );
+        if command.reads_stdin() {
+            let mut buf = vec![];
+            std::io::stdin()
+                .read_to_end(&mut buf)
+                .map_err(|err| eco_format!("failed to read from stdin ({err})"))?;
+            resolvers.overlay(main, buf);
+        }
 
-        // Resolve the virtual path of the main file within the project root.
-        let main_path = VirtualPath::within_root(&input, &root)
-            .ok_or("source file must be contained in project root")?;
+        // Resolve the fixed creation timestamp, if any.
+        let timestamp = command
+            .creation_timestamp
+            .map(|secs| {
+                DateTime::from_timestamp(secs, 0)
+                    .map(|utc| utc.with_timezone(&Local))
+                    .ok_or_else(|| {
+                        eco_format!("creation timestamp {secs} is out of range")
+                    })
+            })
+            .transpose()?;
 
         let library = {
-            // Convert the input pairs to a dictionary.
-            let inputs: Dict = command
-                .inputs
-                .iter()
-                .map(|(k, v)| (k.as_str().into(), v.as_str().into_value()))
-                .collect();
+            // Load the input file and add the input pairs to the dictionary.
+            let mut inputs = match &command.input_file {
+                Some(path) => read_inputs(path)?,
+                None => Dict::new(),
+            };
+            for (k, v) in &command.inputs {
+                inputs.insert(k.as_str().into(), v.as_str().into_value());
+            }
 
             Library::builder().with_inputs(inputs).build()
         };
@@ -86,14 +141,22 @@ impl SystemWorld {
         Ok(Self {
             workdir: std::env::current_dir().ok(),
             input,
+            resolvers: Arc::new(resolvers),
             root,
-            main: FileId::new(None, main_path),
-            library: Prehashed::new(library),
-            book: Prehashed::new(searcher.book),
-            fonts: searcher.fonts,
-            slots: Mutex::new(HashMap::new()),
+            permissions,
+            package_paths: command.package_paths.clone(),
+            locked: command.is_locked(),
+            lock: command.lock,
+            mmap_threshold,
+            main,
+            library: Arc::new(Prehashed::new(library)),
+            book: Arc::new(Prehashed::new(searcher.book)),
+            fonts: Arc::new(searcher.fonts),
+            slots: Mutex::new(Arc::new(HashMap::new())),
             now: OnceLock::new(),
+            timestamp,
             export_cache: ExportCache::new(),
+            packages: OnceLock::new(),
         })
     }
 
@@ -112,26 +175,218 @@ impl SystemWorld {
         self.workdir.as_deref().unwrap_or(Path::new("."))
     }
 
+    /// Retarget the world to another input file, e.g. to compile multiple
+    /// files in one go.
+    ///
+    /// Fonts and loaded files are kept, unless the project root or the way
+    /// files are resolved changes.
+    pub fn set_input(&mut self, command: &SharedArgs) -> StrResult<()> {
+        let (input, root, main) = locate(command)?;
+        let permissions = Permissions::from_args(command);
+        if root != self.root
+            || permissions != self.permissions
+            || command.package_paths != self.package_paths
+            || command.is_locked() != self.locked
+        {
+            self.resolvers = Arc::new(Resolvers::new(
+                root.clone(),
+                permissions,
+                command.package_paths.clone(),
+                lockfile(command, &root)?,
+                self.mmap_threshold,
+            ));
+            *self.slots.get_mut() = Arc::new(HashMap::new());
+            self.root = root;
+            self.permissions = permissions;
+            self.package_paths = command.package_paths.clone();
+            self.locked = command.is_locked();
+        }
+
+        self.input = input;
+        self.main = main;
+        self.lock = command.lock;
+        self.export_cache = ExportCache::new();
+        self.reset();
+        Ok(())
+    }
+
     /// Return all paths the last compilation depended on.
     pub fn dependencies(&mut self) -> impl Iterator<Item = PathBuf> + '_ {
         self.slots
             .get_mut()
             .values()
             .filter(|slot| slot.accessed())
-            .filter_map(|slot| system_path(&self.root, slot.id).ok())
+            .filter_map(|slot| self.resolvers.path(slot.id).ok())
+    }
+
+    /// Return the ids of all files the last compilation depended on.
+    pub fn dependency_ids(&mut self) -> impl Iterator<Item = FileId> + '_ {
+        self.slots
+            .get_mut()
+            .values()
+            .filter(|slot| slot.accessed())
+            .map(|slot| slot.id)
+    }
+
+    /// Record the packages the last compilation used in the project's
+    /// lockfile.
+    ///
+    /// Nothing is recorded with `--locked`, or if the project has no lockfile
+    /// and `--lock` wasn't given. Packages that resolve to local directories
+    /// through `--package-path` are left out. Returns a warning if the
+    /// lockfile couldn't be updated.
+    pub fn record_packages(&mut self) -> Option<SourceDiagnostic> {
+        if self.locked {
+            return None;
+        }
+
+        let packages: Vec<_> = self
+            .dependency_ids()
+            .filter_map(|id| id.package().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter(|spec| local_package_root(&self.package_paths, spec).is_none())
+            .map(|spec| {
+                let checksum = stored_checksum(&spec);
+                (spec, checksum)
+            })
+            .collect();
+
+        Lockfile::record(&self.root, packages, self.lock).err().map(|err| {
+            SourceDiagnostic::warning(
+                Span::detached(),
+                eco_format!("failed to update {LOCKFILE}: {err}"),
+            )
+        })
+    }
+
+    /// Warn about imports of package versions that their registry marks as
+    /// yanked or deprecated.
+    ///
+    /// Every imported package is checked, using the index entry kept next to
+    /// it if the registry's index wasn't fetched, see [`package::advisory`].
+    pub fn package_warnings(&mut self) -> Vec<SourceDiagnostic> {
+        let ids: Vec<FileId> = self.dependency_ids().collect();
+        let mut advisories = HashMap::new();
+        for spec in ids.iter().filter_map(|id| id.package().cloned()) {
+            advisories.entry(spec).or_insert_with_key(package::advisory);
+        }
+
+        if advisories.values().all(Option::is_none) {
+            return vec![];
+        }
+
+        let mut warnings = vec![];
+        for id in ids {
+            if id
+                .vpath()
+                .as_rooted_path()
+                .extension()
+                .map_or(true, |ext| ext != "typ")
+            {
+                continue;
+            }
+            let Ok(source) = self.source(id) else { continue };
+            for (spec, span) in package_imports(source.root()) {
+                let Some(Some(advisory)) = advisories.get(&spec) else { continue };
+                let what = if advisory.yanked { "was yanked" } else { "is deprecated" };
+                let mut warning =
+                    SourceDiagnostic::warning(span, eco_format!("package {spec} {what}"));
+                if let Some(replacement) = &advisory.replacement {
+                    warning.hint(eco_format!("use {replacement} instead"));
+                }
+                warnings.push(warning);
+            }
+        }
+        warnings
+    }
+
+    /// Resolve the path of a file on the system.
+    pub fn path(&self, id: FileId) -> FileResult<PathBuf> {
+        self.resolvers.path(id)
+    }
+
+    /// Shadow a file with unsaved contents, e.g. from an editor buffer.
+    ///
+    /// The overlay takes effect with the next compilation. Like changes on
+    /// disk, it participates in fingerprinting, so an overlaid source is
+    /// reparsed incrementally.
+    pub fn overlay(&mut self, id: FileId, text: String) {
+        Arc::make_mut(&mut self.resolvers).overlay(id, text.into_bytes());
+    }
+
+    /// Remove the overlay of a file so that it is read from the system again.
+    ///
+    /// Returns whether the file was overlaid.
+    pub fn remove_overlay(&mut self, id: FileId) -> bool {
+        Arc::make_mut(&mut self.resolvers).remove_overlay(id)
+    }
+
+    /// Force the files at the given paths to be read again on next access,
+    /// even if their stamps look unchanged.
+    ///
+    /// Used with the paths a file watcher reported as changed, since a stamp
+    /// can miss edits that happen in quick succession.
+    pub fn invalidate(&mut self, paths: &HashSet<PathBuf>) {
+        let resolvers = &self.resolvers;
+        for slot in Arc::make_mut(self.slots.get_mut()).values_mut() {
+            if resolvers.path(slot.id).is_ok_and(|path| paths.contains(&path)) {
+                slot.invalidate();
+            }
+        }
     }
 
     /// Reset the compilation state in preparation of a new compilation.
     pub fn reset(&mut self) {
-        for slot in self.slots.get_mut().values_mut() {
+        for slot in Arc::make_mut(self.slots.get_mut()).values_mut() {
             slot.reset();
         }
         self.now.take();
+        self.packages.take();
+    }
+
+    /// Take a snapshot of the world's current state, e.g. to compile it on
+    /// another thread while this world picks up new edits.
+    ///
+    /// The snapshot shares fonts, resolvers, and loaded files with this world,
+    /// so taking it is cheap. The table of loaded files is only copied once
+    /// either side modifies it, so the two don't contend for one lock.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            workdir: self.workdir.clone(),
+            input: self.input.clone(),
+            root: self.root.clone(),
+            permissions: self.permissions,
+            package_paths: self.package_paths.clone(),
+            locked: self.locked,
+            lock: self.lock,
+            mmap_threshold: self.mmap_threshold,
+            resolvers: self.resolvers.clone(),
+            main: self.main,
+            library: self.library.clone(),
+            book: self.book.clone(),
+            fonts: self.fonts.clone(),
+            slots: Mutex::new(self.slots.lock().clone()),
+            now: OnceLock::new(),
+            timestamp: self.timestamp,
+            export_cache: ExportCache::new(),
+            packages: self.packages.clone(),
+        }
+    }
+
+    /// Take over the files that a snapshot of this world loaded, so that the
+    /// next snapshot reparses them incrementally instead of from scratch.
+    ///
+    /// Files that changed in the meantime are detected as usual when they
+    /// are accessed again.
+    pub fn adopt(&mut self, snapshot: Self) {
+        *self.slots.get_mut() = snapshot.slots.into_inner();
     }
 
-    /// Return the canonical path to the input file.
-    pub fn input(&self) -> &PathBuf {
-        &self.input
+    /// Return the canonical path to the input file, if it wasn't read from
+    /// stdin.
+    pub fn input(&self) -> Option<&Path> {
+        self.input.as_deref()
     }
 
     /// Lookup a source file by id.
@@ -148,10 +403,12 @@ impl SystemWorld {
 
 impl World for SystemWorld {
     fn library(&self) -> &Prehashed<Library> {
+        stop_if_cancelled();
         &self.library
     }
 
     fn book(&self) -> &Prehashed<FontBook> {
+        stop_if_cancelled();
         &self.book
     }
 
@@ -160,19 +417,26 @@ impl World for SystemWorld {
     }
 
     fn source(&self, id: FileId) -> FileResult<Source> {
-        self.slot(id, |slot| slot.source(&self.root))
+        stop_if_cancelled();
+        self.slot(id, |slot| slot.source(&self.resolvers))
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        self.slot(id, |slot| slot.file(&self.root))
+        stop_if_cancelled();
+        self.slot(id, |slot| slot.file(&self.resolvers))
     }
 
     fn font(&self, index: usize) -> Option<Font> {
+        stop_if_cancelled();
         self.fonts[index].get()
     }
 
+    fn packages(&self) -> &[(PackageSpec, Option<EcoString>)] {
+        self.packages.get_or_init(|| known_packages(&self.root))
+    }
+
     fn today(&self, offset: Option<i64>) -> Option<Datetime> {
-        let now = self.now.get_or_init(chrono::Local::now);
+        let now = self.current()?;
 
         let naive = match offset {
             None => now.naive_local(),
@@ -185,22 +449,142 @@ impl World for SystemWorld {
             naive.day().try_into().ok()?,
         )
     }
+
+    fn now(&self, timezone: Option<Timezone>) -> Option<Datetime> {
+        let now = self.current()?;
+
+        let naive = match timezone {
+            None => now.naive_local(),
+            Some(Timezone::Offset(o)) => now.naive_utc() + chrono::Duration::hours(o),
+            Some(Timezone::Named(name)) => {
+                let tz: chrono_tz::Tz = name.parse().ok()?;
+                now.with_timezone(&tz).naive_local()
+            }
+        };
+
+        Datetime::from_ymd_hms(
+            naive.year(),
+            naive.month().try_into().ok()?,
+            naive.day().try_into().ok()?,
+            naive.hour().try_into().ok()?,
+            naive.minute().try_into().ok()?,
+            naive.second().try_into().ok()?,
+        )
+    }
 }
 
 impl SystemWorld {
+    /// The current datetime, unless the permissions deny observing it.
+    ///
+    /// A fixed creation timestamp is always available since it doesn't reveal
+    /// anything about the environment.
+    fn current(&self) -> Option<&DateTime<Local>> {
+        if self.timestamp.is_none() && !self.permissions.env {
+            return None;
+        }
+
+        Some(self.now.get_or_init(|| self.timestamp.unwrap_or_else(Local::now)))
+    }
+
     /// Access the canonical slot for the given file id.
     fn slot<F, T>(&self, id: FileId, f: F) -> T
     where
         F: FnOnce(&mut FileSlot) -> T,
     {
         let mut map = self.slots.lock();
-        f(map.entry(id).or_insert_with(|| FileSlot::new(id)))
+        f(Arc::make_mut(&mut map).entry(id).or_insert_with(|| FileSlot::new(id)))
+    }
+}
+
+/// The packages that are known without a request, along with their
+/// descriptions if available: Those available locally, those recorded in the
+/// lockfile of the project at `root`, and those in the registry indices that
+/// were fetched already.
+fn known_packages(root: &Path) -> Vec<(PackageSpec, Option<EcoString>)> {
+    let mut packages = local_packages();
+    packages.extend(package::index_packages());
+    if let Ok(Some(lockfile)) = Lockfile::read(root) {
+        packages.extend(lockfile.packages().map(|spec| (spec, None)));
+    }
+
+    // Keep the first entry of each package, which has a description if any.
+    let mut seen = HashSet::new();
+    packages.retain(|(spec, _)| seen.insert(spec.clone()));
+    packages
+}
+
+/// The lockfile of the project at `root`, if packages must be recorded in it.
+fn lockfile(command: &SharedArgs, root: &Path) -> StrResult<Option<Lockfile>> {
+    if command.is_locked() {
+        Lockfile::require(root).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Resolve the canonical input path, the project root, and the id of the main
+/// file.
+fn locate(command: &SharedArgs) -> StrResult<(Option<PathBuf>, PathBuf, FileId)> {
+    // Resolve the system-global input path, unless reading from stdin.
+    let stdin = command.reads_stdin();
+    let input = if stdin {
+        None
+    } else {
+        Some(command.input.canonicalize().map_err(|_| {
+            eco_format!("input file not found (searched at {})", command.input.display())
+        })?)
+    };
+
+    // Resolve the system-global root directory.
+    let root = {
+        if stdin && command.root.is_none() {
+            bail!("a root directory must be specified with `--root` when reading from stdin");
+        }
+
+        let path = command
+            .root
+            .as_deref()
+            .or_else(|| input.as_deref()?.parent())
+            .unwrap_or(Path::new("."));
+        path.canonicalize().map_err(|_| {
+            eco_format!("root directory not found (searched at {})", path.display())
+        })?
+    };
+
+    // Resolve the virtual path of the main file within the project root.
+    // A source from stdin gets a synthetic path at the root so that relative
+    // imports are resolved against the root.
+    let main_path = match &input {
+        Some(input) => VirtualPath::within_root(input, &root)
+            .ok_or("source file must be contained in project root")?,
+        None => VirtualPath::new(STDIN_PATH),
+    };
+
+    Ok((input, root, FileId::new(None, main_path)))
+}
+
+/// Read a dictionary of inputs from a JSON, TOML, or YAML file.
+fn read_inputs(path: &Path) -> StrResult<Dict> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| eco_format!("failed to read input file ({err})"))?;
+
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+    match extension.to_lowercase().as_str() {
+        "json" => serde_json::from_str(&text)
+            .map_err(|err| eco_format!("failed to parse input file as JSON ({err})")),
+        "toml" => toml::from_str(&text).map_err(|err| {
+            eco_format!("failed to parse input file as TOML ({})", err.message())
+        }),
+        "yaml" | "yml" => serde_yaml::from_str(&text)
+            .map_err(|err| eco_format!("failed to parse input file as YAML ({err})")),
+        _ => bail!("input file must have a .json, .toml, or .yaml extension"),
     }
 }
 
 /// Holds the processed data for a file ID.
 ///
 /// Both fields can be populated if the file is both imported and read().
+#[derive(Clone)]
 struct FileSlot {
     /// The slot's file id.
     id: FileId,
@@ -228,10 +612,17 @@ impl FileSlot {
         self.file.reset();
     }
 
+    /// Forces the file to be read again on next access.
+    fn invalidate(&mut self) {
+        self.source.invalidate();
+        self.file.invalidate();
+    }
+
     /// Retrieve the source for this file.
-    fn source(&mut self, project_root: &Path) -> FileResult<Source> {
+    fn source(&mut self, resolvers: &Resolvers) -> FileResult<Source> {
         self.source.get_or_init(
-            || system_path(project_root, self.id),
+            || resolvers.stamp(self.id),
+            || resolvers.read(self.id),
             |data, prev| {
                 let name = if prev.is_some() { "reparsing file" } else { "parsing file" };
                 let _scope = TimingScope::new(name, None);
@@ -247,18 +638,24 @@ impl FileSlot {
     }
 
     /// Retrieve the file's bytes.
-    fn file(&mut self, project_root: &Path) -> FileResult<Bytes> {
-        self.file
-            .get_or_init(|| system_path(project_root, self.id), |data, _| Ok(data.into()))
+    fn file(&mut self, resolvers: &Resolvers) -> FileResult<Bytes> {
+        self.file.get_or_init(
+            || resolvers.stamp(self.id),
+            || resolvers.read(self.id),
+            |data, _| Ok(data),
+        )
     }
 }
 
 /// Lazily processes data for a file.
+#[derive(Clone)]
 struct SlotCell<T> {
     /// The processed data.
     data: Option<FileResult<T>>,
     /// A hash of the raw file contents / access error.
     fingerprint: u128,
+    /// The modification stamp of the file when it was last read.
+    stamp: Option<Stamp>,
     /// Whether the slot has been accessed in the current compilation.
     accessed: bool,
 }
@@ -266,7 +663,12 @@ struct SlotCell<T> {
 impl<T: Clone> SlotCell<T> {
     /// Creates a new, empty cell.
     fn new() -> Self {
-        Self { data: None, fingerprint: 0, accessed: false }
+        Self {
+            data: None,
+            fingerprint: 0,
+            stamp: None,
+            accessed: false,
+        }
     }
 
     /// Whether the cell was accessed in the ongoing compilation.
@@ -280,11 +682,18 @@ impl<T: Clone> SlotCell<T> {
         self.accessed = false;
     }
 
+    /// Forgets the stamp so that the file is read and hashed again on next
+    /// access. The processed data is still reused if the contents match.
+    fn invalidate(&mut self) {
+        self.stamp = None;
+    }
+
     /// Gets the contents of the cell or initialize them.
     fn get_or_init(
         &mut self,
-        path: impl FnOnce() -> FileResult<PathBuf>,
-        f: impl FnOnce(Vec<u8>, Option<T>) -> FileResult<T>,
+        stamp: impl FnOnce() -> Option<Stamp>,
+        load: impl FnOnce() -> FileResult<Bytes>,
+        f: impl FnOnce(Bytes, Option<T>) -> FileResult<T>,
     ) -> FileResult<T> {
         // If we accessed the file already in this compilation, retrieve it.
         if mem::replace(&mut self.accessed, true) {
@@ -293,8 +702,18 @@ impl<T: Clone> SlotCell<T> {
             }
         }
 
+        // If the file's stamp didn't change since we last read it and the
+        // file wasn't invalidated, it is very likely unchanged. Skip reading
+        // and hashing it again.
+        let stamp = stamp();
+        if mem::replace(&mut self.stamp, stamp) == stamp && stamp.is_some() {
+            if let Some(data) = &self.data {
+                return data.clone();
+            }
+        }
+
         // Read and hash the file.
-        let result = timed!("loading file", path().and_then(|p| read(&p)));
+        let result = timed!("loading file", load());
         let fingerprint = timed!("hashing file", typst::util::hash128(&result));
 
         // If the file contents didn't change, yield the old processed data.
@@ -312,33 +731,6 @@ impl<T: Clone> SlotCell<T> {
     }
 }
 
-/// Resolves the path of a file id on the system, downloading a package if
-/// necessary.
-fn system_path(project_root: &Path, id: FileId) -> FileResult<PathBuf> {
-    // Determine the root path relative to which the file path
-    // will be resolved.
-    let buf;
-    let mut root = project_root;
-    if let Some(spec) = id.package() {
-        buf = prepare_package(spec)?;
-        root = &buf;
-    }
-
-    // Join the path to the root. If it tries to escape, deny
-    // access. Note: It can still escape via symlinks.
-    id.vpath().resolve(root).ok_or(FileError::AccessDenied)
-}
-
-/// Read a file.
-fn read(path: &Path) -> FileResult<Vec<u8>> {
-    let f = |e| FileError::from_io(e, path);
-    if fs::metadata(path).map_err(f)?.is_dir() {
-        Err(FileError::IsDirectory)
-    } else {
-        fs::read(path).map_err(f)
-    }
-}
-
 /// Decode UTF-8 with an optional BOM.
 fn decode_utf8(buf: &[u8]) -> FileResult<&str> {
     // Remove UTF-8 BOM.
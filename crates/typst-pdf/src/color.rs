@@ -1,6 +1,8 @@
 use once_cell::sync::Lazy;
 use pdf_writer::types::DeviceNSubtype;
 use pdf_writer::{writers, Chunk, Dict, Filter, Name, Ref};
+use typst::diag::error;
+use typst::syntax::Span;
 use typst::visualize::{Color, ColorSpace, Paint};
 
 use crate::deflate;
@@ -144,6 +146,15 @@ impl ColorSpaces {
     }
 }
 
+/// Write the sRGB ICC profile for the output intent of a PDF/A file.
+pub fn write_output_profile(chunk: &mut Chunk, id: Ref) {
+    chunk
+        .icc_profile(id, &SRGB_ICC_DEFLATED)
+        .n(3)
+        .range([0.0, 1.0, 0.0, 1.0, 0.0, 1.0])
+        .filter(Filter::FlateDecode);
+}
+
 /// This function removes comments, line spaces and carriage returns from a
 /// PostScript program. This is necessary to optimize the size of the PDF file.
 fn minify(source: &str) -> String {
@@ -233,12 +244,13 @@ impl PaintEncode for Paint {
 
 impl PaintEncode for Color {
     fn set_as_fill(&self, ctx: &mut PageContext, _: bool, _: Transforms) {
-        match self {
+        let color = pdfa_color(ctx, *self);
+        match color {
             Color::Luma(_) => {
                 ctx.parent.colors.d65_gray(&mut ctx.parent.alloc);
                 ctx.set_fill_color_space(D65_GRAY);
 
-                let [l, _, _, _] = ColorSpace::D65Gray.encode(*self);
+                let [l, _, _, _] = ColorSpace::D65Gray.encode(color);
                 ctx.content.set_fill_color([l]);
             }
             // Oklch is converted to Oklab.
@@ -246,39 +258,40 @@ impl PaintEncode for Color {
                 ctx.parent.colors.oklab(&mut ctx.parent.alloc);
                 ctx.set_fill_color_space(OKLAB);
 
-                let [l, a, b, _] = ColorSpace::Oklab.encode(*self);
+                let [l, a, b, _] = ColorSpace::Oklab.encode(color);
                 ctx.content.set_fill_color([l, a, b]);
             }
             Color::LinearRgb(_) => {
                 ctx.parent.colors.linear_rgb();
                 ctx.set_fill_color_space(LINEAR_SRGB);
 
-                let [r, g, b, _] = ColorSpace::LinearRgb.encode(*self);
+                let [r, g, b, _] = ColorSpace::LinearRgb.encode(color);
                 ctx.content.set_fill_color([r, g, b]);
             }
             Color::Rgb(_) => {
                 ctx.parent.colors.srgb(&mut ctx.parent.alloc);
                 ctx.set_fill_color_space(SRGB);
 
-                let [r, g, b, _] = ColorSpace::Srgb.encode(*self);
+                let [r, g, b, _] = ColorSpace::Srgb.encode(color);
                 ctx.content.set_fill_color([r, g, b]);
             }
             Color::Cmyk(_) => {
                 ctx.reset_fill_color_space();
 
-                let [c, m, y, k] = ColorSpace::Cmyk.encode(*self);
+                let [c, m, y, k] = ColorSpace::Cmyk.encode(color);
                 ctx.content.set_fill_cmyk(c, m, y, k);
             }
         }
     }
 
     fn set_as_stroke(&self, ctx: &mut PageContext, _: bool, _: Transforms) {
-        match self {
+        let color = pdfa_color(ctx, *self);
+        match color {
             Color::Luma(_) => {
                 ctx.parent.colors.d65_gray(&mut ctx.parent.alloc);
                 ctx.set_stroke_color_space(D65_GRAY);
 
-                let [l, _, _, _] = ColorSpace::D65Gray.encode(*self);
+                let [l, _, _, _] = ColorSpace::D65Gray.encode(color);
                 ctx.content.set_stroke_color([l]);
             }
             // Oklch is converted to Oklab.
@@ -286,33 +299,59 @@ impl PaintEncode for Color {
                 ctx.parent.colors.oklab(&mut ctx.parent.alloc);
                 ctx.set_stroke_color_space(OKLAB);
 
-                let [l, a, b, _] = ColorSpace::Oklab.encode(*self);
+                let [l, a, b, _] = ColorSpace::Oklab.encode(color);
                 ctx.content.set_stroke_color([l, a, b]);
             }
             Color::LinearRgb(_) => {
                 ctx.parent.colors.linear_rgb();
                 ctx.set_stroke_color_space(LINEAR_SRGB);
 
-                let [r, g, b, _] = ColorSpace::LinearRgb.encode(*self);
+                let [r, g, b, _] = ColorSpace::LinearRgb.encode(color);
                 ctx.content.set_stroke_color([r, g, b]);
             }
             Color::Rgb(_) => {
                 ctx.parent.colors.srgb(&mut ctx.parent.alloc);
                 ctx.set_stroke_color_space(SRGB);
 
-                let [r, g, b, _] = ColorSpace::Srgb.encode(*self);
+                let [r, g, b, _] = ColorSpace::Srgb.encode(color);
                 ctx.content.set_stroke_color([r, g, b]);
             }
             Color::Cmyk(_) => {
                 ctx.reset_stroke_color_space();
 
-                let [c, m, y, k] = ColorSpace::Cmyk.encode(*self);
+                let [c, m, y, k] = ColorSpace::Cmyk.encode(color);
                 ctx.content.set_stroke_cmyk(c, m, y, k);
             }
         }
     }
 }
 
+/// Replace or reject a color that PDF/A forbids.
+///
+/// Colors based on Oklab would need a DeviceN color space with custom
+/// colorants, so they are converted to sRGB instead. CMYK colors can't be used
+/// with the sRGB output intent.
+fn pdfa_color(ctx: &mut PageContext, color: Color) -> Color {
+    if !ctx.parent.standard.is_pdfa() {
+        return color;
+    }
+
+    match color {
+        Color::Oklab(_) | Color::Oklch(_) | Color::Hsl(_) | Color::Hsv(_) => {
+            color.to_rgb()
+        }
+        Color::Cmyk(_) => {
+            let standard = ctx.parent.standard.name();
+            ctx.parent.reject(
+                error!(Span::detached(), "{standard} does not support CMYK colors")
+                    .with_hint("convert the color with `rgb(..)`"),
+            );
+            color
+        }
+        _ => color,
+    }
+}
+
 /// Extra color space functions.
 pub(super) trait ColorSpaceExt {
     /// Returns the range of the color space.
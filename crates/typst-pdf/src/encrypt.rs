@@ -0,0 +1,523 @@
+//! Encryption of finished PDF files with AES-256.
+//!
+//! Uses the standard security handler of PDF 2.0 (revision 6). Since
+//! `pdf-writer` has no notion of encryption, the file is written in full first
+//! and then rewritten: Every string and stream is encrypted, the encryption
+//! dictionary is added and the cross-reference table and trailer are written
+//! anew.
+
+use std::ops::Range;
+
+use aes::{Aes128, Aes256};
+use cbc::cipher::block_padding::{NoPadding, Pkcs7};
+use cbc::cipher::{BlockEncryptMut, KeyIvInit};
+use ecow::{eco_format, EcoString};
+use pdf_writer::{Chunk, Finish, Name, Ref, Str};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use typst::diag::StrResult;
+
+/// How an exported PDF file is encrypted.
+///
+/// Viewers open the file with the owner password with full access and with
+/// the user password with the permissions given here. The permissions are
+/// only honored by cooperating viewers, the encryption itself protects the
+/// contents from anyone without a password.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PdfEncryption {
+    /// The password that grants full access.
+    pub owner_password: String,
+    /// The password needed to open the file. If it is empty, anyone can open
+    /// the file, but only with the permissions below.
+    pub user_password: String,
+    /// Whether the document may be printed.
+    pub printing: bool,
+    /// Whether text and graphics may be copied.
+    pub copying: bool,
+}
+
+/// Encrypt a file that was just written by `pdf-writer`.
+pub(crate) fn encrypt(pdf: &[u8], encryption: &PdfEncryption) -> StrResult<Vec<u8>> {
+    let keys = Keys::new(encryption)?;
+
+    // Copy the header, which ends with an empty line.
+    let start = pdf.windows(2).position(|w| w == b"\n\n").ok_or_else(malformed)? + 2;
+    let mut out = Vec::with_capacity(pdf.len() + pdf.len() / 8);
+    out.extend_from_slice(&pdf[..start]);
+
+    // Rewrite the indirect objects.
+    let mut rewriter = Rewriter { pdf, pos: start, out, keys: &keys };
+    let mut offsets = vec![];
+    while !rewriter.rest().starts_with(b"xref\n") {
+        offsets.push(rewriter.object()?);
+    }
+
+    // Write the encryption dictionary.
+    let mut out = rewriter.out;
+    let encrypt_ref = Ref::new(offsets.iter().map(|&(id, _)| id).max().unwrap_or(0) + 1);
+    offsets.push((encrypt_ref.get(), out.len()));
+    out.extend_from_slice(keys.dictionary(encrypt_ref).as_bytes());
+
+    // Write the cross-reference table and the trailer, which are also
+    // rewritten as they are.
+    let xref_offset = out.len();
+    write_xref(&mut out, &mut offsets);
+    let trailer = find(pdf, b"trailer\n").ok_or_else(malformed)?;
+    let startxref = find(pdf, b"\nstartxref\n").ok_or_else(malformed)?;
+    let dict =
+        std::str::from_utf8(&pdf[trailer + 8..startxref]).map_err(|_| malformed())?;
+    let dict = dict.strip_suffix(">>").ok_or_else(malformed)?;
+    out.extend_from_slice(b"trailer\n");
+    for line in dict.lines() {
+        match line.trim().strip_prefix("/Size ") {
+            Some(_) => out.extend_from_slice(
+                format!("  /Size {}", encrypt_ref.get() + 1).as_bytes(),
+            ),
+            None => out.extend_from_slice(line.as_bytes()),
+        }
+        out.push(b'\n');
+    }
+    out.extend_from_slice(format!("  /Encrypt {} 0 R\n>>", encrypt_ref.get()).as_bytes());
+    out.extend_from_slice(format!("\nstartxref\n{xref_offset}\n%%EOF").as_bytes());
+    Ok(out)
+}
+
+/// The keys and password checks of an encrypted file.
+struct Keys {
+    /// The key that all strings and streams are encrypted with.
+    file: [u8; 32],
+    /// The owner password check with its validation and key salts.
+    owner: Vec<u8>,
+    /// The user password check with its validation and key salts.
+    user: Vec<u8>,
+    /// The file key, encrypted with a key derived from the owner password.
+    owner_key: Vec<u8>,
+    /// The file key, encrypted with a key derived from the user password.
+    user_key: Vec<u8>,
+    /// The permission flags.
+    permissions: i32,
+    /// The permission flags, encrypted with the file key.
+    encrypted_permissions: Vec<u8>,
+}
+
+impl Keys {
+    /// Derive the keys for the given passwords with fresh random salts
+    /// (Algorithms 8, 9, and 10 of ISO 32000-2).
+    fn new(encryption: &PdfEncryption) -> StrResult<Self> {
+        let file = random::<32>()?;
+        let salts = random::<32>()?;
+        let owner_password = password(&encryption.owner_password);
+        let user_password = password(&encryption.user_password);
+
+        let mut user = hash(user_password, &salts[..8], &[]).to_vec();
+        user.extend_from_slice(&salts[..16]);
+        let user_key = aes256_raw(&hash(user_password, &salts[8..16], &[]), &file);
+
+        let mut owner = hash(owner_password, &salts[16..24], &user).to_vec();
+        owner.extend_from_slice(&salts[16..]);
+        let owner_key = aes256_raw(&hash(owner_password, &salts[24..], &user), &file);
+
+        // Bits 1 and 2 must be zero, all others are permissions or must be
+        // one. Printing is bits 3 and 12 (high quality) and copying bit 5.
+        let mut flags = !0b11_u32;
+        if !encryption.printing {
+            flags &= !(1 << 2 | 1 << 11);
+        }
+        if !encryption.copying {
+            flags &= !(1 << 4);
+        }
+
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(&flags.to_le_bytes());
+        block[4..8].fill(0xff);
+        block[8..12].copy_from_slice(b"Tadb");
+        block[12..].copy_from_slice(&random::<4>()?);
+        let encrypted_permissions = aes256_raw(&file, &block);
+
+        Ok(Self {
+            file,
+            owner,
+            user,
+            owner_key,
+            user_key,
+            permissions: flags as i32,
+            encrypted_permissions,
+        })
+    }
+
+    /// Encrypt a string or stream, prefixed with its random initialization
+    /// vector.
+    fn encrypt(&self, data: &[u8]) -> StrResult<Vec<u8>> {
+        let iv = random::<16>()?;
+        let mut out = iv.to_vec();
+        out.extend(
+            cbc::Encryptor::<Aes256>::new(&self.file.into(), &iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(data),
+        );
+        Ok(out)
+    }
+
+    /// Write the encryption dictionary.
+    fn dictionary(&self, id: Ref) -> Chunk {
+        let mut chunk = Chunk::new();
+        let mut dict = chunk.indirect(id).dict();
+        dict.pair(Name(b"Filter"), Name(b"Standard"));
+        dict.pair(Name(b"V"), 5);
+        dict.pair(Name(b"R"), 6);
+        dict.pair(Name(b"Length"), 256);
+        dict.insert(Name(b"CF"))
+            .dict()
+            .insert(Name(b"StdCF"))
+            .dict()
+            .pair(Name(b"Type"), Name(b"CryptFilter"))
+            .pair(Name(b"CFM"), Name(b"AESV3"))
+            .pair(Name(b"AuthEvent"), Name(b"DocOpen"))
+            .pair(Name(b"Length"), 32);
+        dict.pair(Name(b"StmF"), Name(b"StdCF"));
+        dict.pair(Name(b"StrF"), Name(b"StdCF"));
+        dict.pair(Name(b"O"), Str(&self.owner));
+        dict.pair(Name(b"U"), Str(&self.user));
+        dict.pair(Name(b"OE"), Str(&self.owner_key));
+        dict.pair(Name(b"UE"), Str(&self.user_key));
+        dict.pair(Name(b"P"), self.permissions);
+        dict.pair(Name(b"Perms"), Str(&self.encrypted_permissions));
+        dict.finish();
+        chunk
+    }
+}
+
+/// Rewrites the indirect objects of a file with encrypted strings and
+/// streams.
+struct Rewriter<'a> {
+    /// The original file.
+    pdf: &'a [u8],
+    /// The position in the original file.
+    pos: usize,
+    /// The rewritten file.
+    out: Vec<u8>,
+    /// The keys to encrypt with.
+    keys: &'a Keys,
+}
+
+impl<'a> Rewriter<'a> {
+    /// The rest of the original file.
+    fn rest(&self) -> &'a [u8] {
+        &self.pdf[self.pos..]
+    }
+
+    /// Copy the given number of bytes.
+    fn copy(&mut self, len: usize) {
+        self.out.extend_from_slice(&self.pdf[self.pos..self.pos + len]);
+        self.pos += len;
+    }
+
+    /// Rewrite an indirect object and return its id along with its offset in
+    /// the rewritten file.
+    fn object(&mut self) -> StrResult<(i32, usize)> {
+        let offset = self.out.len();
+        let header = find(self.rest(), b" 0 obj\n").ok_or_else(malformed)?;
+        let id = std::str::from_utf8(&self.rest()[..header])
+            .ok()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(malformed)?;
+        self.copy(header + 7);
+
+        // Nesting of dictionaries and arrays, and where the value of the
+        // stream's `/Length` is in the rewritten file.
+        let mut depth = 0;
+        let mut length: Option<(Range<usize>, usize)> = None;
+        loop {
+            let rest = self.rest();
+            if depth == 0 && rest.starts_with(b"\nendobj\n\n") {
+                self.copy(9);
+                return Ok((id, offset));
+            } else if depth == 0 && rest.starts_with(b"\nstream\n") {
+                let (range, len) = length.ok_or_else(malformed)?;
+                let data = rest.get(8..8 + len).ok_or_else(malformed)?;
+                let data = self.keys.encrypt(data)?;
+                self.out.splice(range, data.len().to_string().into_bytes());
+                self.copy(8);
+                self.out.extend_from_slice(&data);
+                self.pos += len;
+                if !self.rest().starts_with(b"\nendstream\nendobj\n\n") {
+                    return Err(malformed());
+                }
+                self.copy(19);
+                return Ok((id, offset));
+            }
+
+            match rest {
+                [b'<', b'<', ..] | [b'[', ..] => {
+                    depth += 1;
+                    self.copy(if rest[0] == b'[' { 1 } else { 2 });
+                }
+                [b'>', b'>', ..] | [b']', ..] => {
+                    depth -= 1;
+                    self.copy(if rest[0] == b']' { 1 } else { 2 });
+                }
+                [b'(', ..] => {
+                    let (string, len) = literal_string(rest).ok_or_else(malformed)?;
+                    self.string(&string)?;
+                    self.pos += len;
+                }
+                [b'<', ..] => {
+                    let (string, len) = hex_string(rest).ok_or_else(malformed)?;
+                    self.string(&string)?;
+                    self.pos += len;
+                }
+                [b'/', ..] => {
+                    let len =
+                        1 + rest[1..].iter().take_while(|&&b| is_regular(b)).count();
+                    let name = &rest[..len];
+                    self.copy(len);
+                    if depth == 1 && name == b"/Length" {
+                        let space =
+                            self.rest().iter().take_while(|&&b| b == b' ').count();
+                        self.copy(space);
+                        let digits =
+                            self.rest().iter().take_while(|b| b.is_ascii_digit()).count();
+                        let value = std::str::from_utf8(&self.rest()[..digits])
+                            .ok()
+                            .and_then(|value| value.parse().ok())
+                            .ok_or_else(malformed)?;
+                        let start = self.out.len();
+                        length = Some((start..start + digits, value));
+                        self.copy(digits);
+                    }
+                }
+                [_, ..] => self.copy(1),
+                [] => return Err(malformed()),
+            }
+        }
+    }
+
+    /// Write an encrypted string.
+    fn string(&mut self, string: &[u8]) -> StrResult<()> {
+        let data = self.keys.encrypt(string)?;
+        self.out.push(b'<');
+        for byte in data {
+            self.out.extend_from_slice(format!("{byte:02X}").as_bytes());
+        }
+        self.out.push(b'>');
+        Ok(())
+    }
+}
+
+/// Decode a literal string at the start of `data` and return it along with
+/// its length in the file.
+fn literal_string(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut string = vec![];
+    let mut depth = 0;
+    let mut i = 1;
+    loop {
+        let byte = *data.get(i)?;
+        i += 1;
+        match byte {
+            b'(' => depth += 1,
+            b')' if depth == 0 => return Some((string, i)),
+            b')' => depth -= 1,
+            b'\\' => {
+                let escaped = *data.get(i)?;
+                i += 1;
+                match escaped {
+                    b'n' => string.push(b'\n'),
+                    b'r' => string.push(b'\r'),
+                    b't' => string.push(b'\t'),
+                    b'b' => string.push(b'\x08'),
+                    b'f' => string.push(b'\x0c'),
+                    b'0'..=b'7' => {
+                        let mut value = u32::from(escaped - b'0');
+                        for _ in 0..2 {
+                            match data.get(i) {
+                                Some(&digit @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(digit - b'0');
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        string.push(value as u8);
+                    }
+                    b'\r' if data.get(i) == Some(&b'\n') => i += 1,
+                    b'\r' | b'\n' => {}
+                    other => string.push(other),
+                }
+                continue;
+            }
+            _ => {}
+        }
+        string.push(byte);
+    }
+}
+
+/// Decode a hexadecimal string at the start of `data` and return it along with
+/// its length in the file.
+fn hex_string(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let end = data.iter().position(|&b| b == b'>')?;
+    let digits: Vec<u8> = data[1..end]
+        .iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    let string = digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect();
+    Some((string, end + 1))
+}
+
+/// Whether a byte is neither whitespace nor a delimiter.
+fn is_regular(byte: u8) -> bool {
+    !byte.is_ascii_whitespace()
+        && byte != 0
+        && !matches!(
+            byte,
+            b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+        )
+}
+
+/// Write a cross-reference table for the objects at the given offsets, linking
+/// all unused ids in the free list.
+fn write_xref(out: &mut Vec<u8>, offsets: &mut [(i32, usize)]) {
+    offsets.sort();
+    let len = offsets.last().map_or(0, |&(id, _)| id) + 1;
+    let mut entries = vec![None; len as usize];
+    for &(id, offset) in offsets.iter() {
+        entries[id as usize] = Some(offset);
+    }
+
+    out.extend_from_slice(format!("xref\n0 {len}\n").as_bytes());
+    for (id, entry) in entries.iter().enumerate() {
+        let line = match entry {
+            Some(offset) => format!("{offset:010} 00000 n\r\n"),
+            None => {
+                let next = (id + 1..entries.len())
+                    .find(|&next| entries[next].is_none())
+                    .unwrap_or(0);
+                let gen = if id == 0 { "65535" } else { "00000" };
+                format!("{next:010} {gen} f\r\n")
+            }
+        };
+        out.extend_from_slice(line.as_bytes());
+    }
+}
+
+/// The error for a file that can't be rewritten, which would be a bug in how
+/// it was written.
+fn malformed() -> EcoString {
+    "failed to encrypt PDF file (malformed file)".into()
+}
+
+/// Find the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Prepare a password for key derivation: Its UTF-8 bytes, truncated to 127
+/// bytes.
+fn password(password: &str) -> &[u8] {
+    let bytes = password.as_bytes();
+    &bytes[..bytes.len().min(127)]
+}
+
+/// Hash a password with a salt and, for the owner password, the user password
+/// check (Algorithm 2.B of ISO 32000-2).
+fn hash(password: &[u8], salt: &[u8], user: &[u8]) -> [u8; 32] {
+    let mut key = Sha256::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(user)
+        .finalize()
+        .to_vec();
+
+    let mut round = 0;
+    loop {
+        let mut block =
+            Vec::with_capacity(64 * (password.len() + key.len() + user.len()));
+        for _ in 0..64 {
+            block.extend_from_slice(password);
+            block.extend_from_slice(&key);
+            block.extend_from_slice(user);
+        }
+
+        let encrypted =
+            cbc::Encryptor::<Aes128>::new(key[..16].into(), key[16..32].into())
+                .encrypt_padded_vec_mut::<NoPadding>(&block);
+        let sum: u32 = encrypted[..16].iter().map(|&b| u32::from(b)).sum();
+        key = match sum % 3 {
+            0 => Sha256::digest(&encrypted).to_vec(),
+            1 => Sha384::digest(&encrypted).to_vec(),
+            _ => Sha512::digest(&encrypted).to_vec(),
+        };
+
+        round += 1;
+        let last = u32::from(*encrypted.last().unwrap());
+        if round >= 64 && last + 32 <= round {
+            break;
+        }
+    }
+
+    key[..32].try_into().unwrap()
+}
+
+/// Encrypt whole blocks with AES-256 without an initialization vector or
+/// padding.
+fn aes256_raw(key: &[u8; 32], data: &[u8]) -> Vec<u8> {
+    cbc::Encryptor::<Aes256>::new(key.into(), &[0; 16].into())
+        .encrypt_padded_vec_mut::<NoPadding>(data)
+}
+
+/// Generate random bytes for keys, salts, and initialization vectors.
+fn random<const N: usize>() -> StrResult<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| eco_format!("failed to generate encryption key ({err})"))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use cbc::cipher::BlockDecryptMut;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_strings() {
+        assert_eq!(literal_string(b"(a(b)c) x"), Some((b"a(b)c".to_vec(), 7)));
+        assert_eq!(literal_string(br"(\(\n\101\\)"), Some((b"(\nA\\".to_vec(), 12)));
+        assert_eq!(literal_string(b"(open"), None);
+        assert_eq!(hex_string(b"<4A 6b7>"), Some((b"Jkp".to_vec(), 8)));
+        assert_eq!(hex_string(b"<4X>"), None);
+    }
+
+    #[test]
+    fn test_recover_file_key() {
+        let encryption = PdfEncryption {
+            owner_password: "owner".into(),
+            user_password: String::new(),
+            printing: false,
+            copying: true,
+        };
+
+        // Both passwords unlock the file key, the way a viewer would do it.
+        let keys = Keys::new(&encryption).unwrap();
+        let decrypt = |key: [u8; 32], data: &[u8]| {
+            cbc::Decryptor::<Aes256>::new(&key.into(), &[0; 16].into())
+                .decrypt_padded_vec_mut::<NoPadding>(data)
+                .unwrap()
+        };
+
+        assert_eq!(&hash(b"", &keys.user[32..40], &[]), &keys.user[..32]);
+        let key = hash(b"", &keys.user[40..], &[]);
+        assert_eq!(decrypt(key, &keys.user_key), keys.file);
+
+        let owner = hash(b"owner", &keys.owner[32..40], &keys.user);
+        assert_eq!(&owner, &keys.owner[..32]);
+        let key = hash(b"owner", &keys.owner[40..], &keys.user);
+        assert_eq!(decrypt(key, &keys.owner_key), keys.file);
+
+        let permissions = decrypt(keys.file, &keys.encrypted_permissions);
+        assert_eq!(&permissions[..4], &keys.permissions.to_le_bytes());
+        assert_eq!(&permissions[8..12], b"Tadb");
+        assert_eq!(keys.permissions & 0b100, 0);
+    }
+}
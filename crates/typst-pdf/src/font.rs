@@ -4,6 +4,7 @@ use std::sync::Arc;
 use ecow::{eco_format, EcoString};
 use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap};
 use pdf_writer::{Filter, Finish, Name, Rect, Str};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use ttf_parser::{name_id, GlyphId, Tag};
 use typst::text::Font;
 use typst::util::SliceExt;
@@ -23,7 +24,18 @@ const SYSTEM_INFO: SystemInfo = SystemInfo {
 /// Embed all used fonts into the PDF.
 #[typst_macros::time(name = "write fonts")]
 pub(crate) fn write_fonts(ctx: &mut PdfContext) {
-    for font in ctx.font_map.items() {
+    // Subsetting is the most expensive part, so all fonts are subsetted in
+    // parallel up front.
+    let fonts: Vec<&Font> = ctx.font_map.items().collect();
+    let subsets: Vec<Arc<Vec<u8>>> = fonts
+        .into_par_iter()
+        .map(|font| {
+            let glyphs: Vec<_> = ctx.glyph_sets[font].keys().copied().collect();
+            subset_font(font, &glyphs)
+        })
+        .collect();
+
+    for (font, data) in ctx.font_map.items().zip(subsets) {
         let type0_ref = ctx.alloc.bump();
         let cid_ref = ctx.alloc.bump();
         let descriptor_ref = ctx.alloc.bump();
@@ -149,10 +161,7 @@ pub(crate) fn write_fonts(ctx: &mut PdfContext) {
         let cmap = create_cmap(ttf, glyph_set);
         ctx.pdf.cmap(cmap_ref, &cmap.finish());
 
-        // Subset and write the font's bytes.
-        let glyphs: Vec<_> = glyph_set.keys().copied().collect();
-        let data = subset_font(font, &glyphs);
-
+        // Write the font's subsetted bytes.
         let mut stream = ctx.pdf.stream(data_ref, &data);
         stream.filter(Filter::FlateDecode);
         if is_cff {
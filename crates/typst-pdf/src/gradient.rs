@@ -5,7 +5,9 @@ use ecow::eco_format;
 use pdf_writer::types::{ColorSpaceOperand, FunctionShadingType};
 use pdf_writer::writers::StreamShadingType;
 use pdf_writer::{Filter, Finish, Name, Ref};
+use typst::diag::error;
 use typst::layout::{Abs, Angle, Point, Quadrant, Ratio, Transform};
+use typst::syntax::Span;
 use typst::util::Numeric;
 use typst::visualize::{
     Color, ColorSpace, Gradient, RatioOrAngle, RelativeTo, WeightedColor,
@@ -244,6 +246,27 @@ impl PaintEncode for Gradient {
     }
 }
 
+/// Reject gradients that are interpolated in a color space PDF/A forbids.
+fn validate_pdfa(ctx: &mut PageContext, gradient: &Gradient) {
+    let space = match gradient.space() {
+        ColorSpace::Srgb | ColorSpace::D65Gray | ColorSpace::LinearRgb => return,
+        ColorSpace::Oklab => "Oklab",
+        ColorSpace::Oklch => "Oklch",
+        ColorSpace::Hsl => "HSL",
+        ColorSpace::Hsv => "HSV",
+        ColorSpace::Cmyk => "CMYK",
+    };
+
+    let standard = ctx.parent.standard.name();
+    ctx.parent.reject(
+        error!(
+            Span::detached(),
+            "{standard} does not support gradients in the {space} color space"
+        )
+        .with_hint("set `space: rgb` on the gradient"),
+    );
+}
+
 /// Deduplicates a gradient to a named PDF resource.
 fn register_gradient(
     ctx: &mut PageContext,
@@ -251,6 +274,10 @@ fn register_gradient(
     on_text: bool,
     mut transforms: Transforms,
 ) -> usize {
+    if ctx.parent.standard.is_pdfa() {
+        validate_pdfa(ctx, gradient);
+    }
+
     // Edge cases for strokes.
     if transforms.size.x.is_zero() {
         transforms.size.x = Abs::pt(1.0);
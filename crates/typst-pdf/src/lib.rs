@@ -1,6 +1,7 @@
 //! Exporting into PDF documents.
 
 mod color;
+mod encrypt;
 mod extg;
 mod font;
 mod gradient;
@@ -8,6 +9,7 @@ mod image;
 mod outline;
 mod page;
 mod pattern;
+mod tags;
 
 use std::cmp::Eq;
 use std::collections::{BTreeMap, HashMap};
@@ -16,22 +18,28 @@ use std::sync::Arc;
 
 use base64::Engine;
 use ecow::{eco_format, EcoString};
-use pdf_writer::types::Direction;
+use pdf_writer::types::{Direction, OutputIntentSubtype};
+use pdf_writer::writers::OutputIntent;
 use pdf_writer::{Finish, Name, Pdf, Ref, TextStr};
+use typst::diag::{error, At, SourceDiagnostic, SourceResult};
 use typst::foundations::Datetime;
 use typst::layout::{Abs, Dir, Em, Transform};
 use typst::model::Document;
+use typst::syntax::Span;
 use typst::text::{Font, Lang};
 use typst::util::Deferred;
 use typst::visualize::Image;
 use xmp_writer::{DateTime, LangId, RenditionClass, Timezone, XmpWriter};
 
+pub use crate::encrypt::PdfEncryption;
+
 use crate::color::ColorSpaces;
 use crate::extg::ExtGState;
 use crate::gradient::PdfGradient;
 use crate::image::EncodedImage;
 use crate::page::Page;
 use crate::pattern::PdfPattern;
+use crate::tags::Tags;
 
 /// Export a document into a PDF file.
 ///
@@ -47,14 +55,53 @@ use crate::pattern::PdfPattern;
 /// The `timestamp`, if given, is expected to be the creation date of the
 /// document as a UTC datetime. It will only be used if `set document(date: ..)`
 /// is `auto`.
-#[typst_macros::time(name = "pdf")]
 pub fn pdf(
     document: &Document,
     ident: Option<&str>,
     timestamp: Option<Datetime>,
 ) -> Vec<u8> {
-    let mut ctx = PdfContext::new(document);
-    page::construct_pages(&mut ctx, &document.pages);
+    let pages: Vec<usize> = (0..document.pages.len()).collect();
+    pdf_pages(document, ident, timestamp, &pages, PdfStandard::Pdf17, false, None)
+        .expect("plain PDF export cannot fail")
+}
+
+/// Export selected pages of a document into a PDF file.
+///
+/// The `pages` are indices into the document's pages, in the order in which
+/// they should appear. Links and outline entries pointing to pages that are
+/// not exported lose their destination. If not all pages are exported, pages
+/// without a custom page label are labelled with their number in the full
+/// document.
+///
+/// The file conforms to the given `standard`. If the document uses features
+/// that the standard forbids, the export fails with an error for each of them.
+///
+/// If `tagged` is true, the file records the logical structure of the document
+/// for assistive technology like screen readers: Its headings, figures, block
+/// equations, lists, tables and paragraphs.
+///
+/// If an `encryption` is given, the file is encrypted with AES-256 and can only
+/// be opened with one of its passwords. PDF/A forbids encryption.
+///
+/// See [`pdf`] for the other parameters.
+#[typst_macros::time(name = "pdf")]
+pub fn pdf_pages(
+    document: &Document,
+    ident: Option<&str>,
+    timestamp: Option<Datetime>,
+    pages: &[usize],
+    standard: PdfStandard,
+    tagged: bool,
+    encryption: Option<&PdfEncryption>,
+) -> SourceResult<Vec<u8>> {
+    let mut ctx = PdfContext::new(document, standard, tagged);
+    ctx.encrypted = encryption.is_some();
+    if standard.is_pdfa() && ctx.encrypted {
+        let standard = standard.name();
+        ctx.reject(error!(Span::detached(), "{standard} does not allow encryption"));
+    }
+
+    page::construct_pages(&mut ctx, pages);
     font::write_fonts(&mut ctx);
     image::write_images(&mut ctx);
     gradient::write_gradients(&mut ctx);
@@ -62,13 +109,60 @@ pub fn pdf(
     pattern::write_patterns(&mut ctx);
     page::write_page_tree(&mut ctx);
     write_catalog(&mut ctx, ident, timestamp);
-    ctx.pdf.finish()
+
+    if !ctx.errors.is_empty() {
+        return Err(ctx.errors.into());
+    }
+
+    let buffer = ctx.pdf.finish();
+    match encryption {
+        Some(encryption) => encrypt::encrypt(&buffer, encryption).at(Span::detached()),
+        None => Ok(buffer),
+    }
+}
+
+/// A standard that an exported PDF file conforms to.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PdfStandard {
+    /// Plain PDF 1.7.
+    #[default]
+    Pdf17,
+    /// PDF/A-2b, for long-term archival.
+    ///
+    /// Embeds an sRGB output intent and identifies the file as PDF/A in its
+    /// XMP metadata. CMYK colors, gradients interpolated in color spaces other
+    /// than RGB and luma, missing glyphs and fonts whose license forbids
+    /// embedding are rejected.
+    A2b,
+}
+
+impl PdfStandard {
+    /// The name of the standard.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pdf17 => "PDF 1.7",
+            Self::A2b => "PDF/A-2b",
+        }
+    }
+
+    /// Whether this is a PDF/A standard.
+    pub fn is_pdfa(self) -> bool {
+        matches!(self, Self::A2b)
+    }
 }
 
 /// Context for exporting a whole PDF document.
 struct PdfContext<'a> {
     /// The document that we're currently exporting.
     document: &'a Document,
+    /// The standard the file conforms to.
+    standard: PdfStandard,
+    /// Whether the file records the document's logical structure.
+    tagged: bool,
+    /// Whether the file is encrypted once written.
+    encrypted: bool,
+    /// The features of the document that the standard forbids.
+    errors: Vec<SourceDiagnostic>,
     /// The writer we are writing the PDF into.
     pdf: Pdf,
     /// Content of exported pages.
@@ -83,6 +177,8 @@ struct PdfContext<'a> {
     /// The number of glyphs for all referenced languages in the document.
     /// We keep track of this to determine the main document language.
     languages: HashMap<Lang, usize>,
+    /// The logical structure of the written pages.
+    tags: Tags,
 
     /// Allocator for indirect reference IDs.
     alloc: Ref,
@@ -90,6 +186,9 @@ struct PdfContext<'a> {
     page_tree_ref: Ref,
     /// The IDs of written pages.
     page_refs: Vec<Ref>,
+    /// For each of the document's pages, the index of the written page it
+    /// became, if it is exported.
+    page_indices: Vec<Option<usize>>,
     /// The IDs of written fonts.
     font_refs: Vec<Ref>,
     /// The IDs of written images.
@@ -118,18 +217,24 @@ struct PdfContext<'a> {
 }
 
 impl<'a> PdfContext<'a> {
-    fn new(document: &'a Document) -> Self {
+    fn new(document: &'a Document, standard: PdfStandard, tagged: bool) -> Self {
         let mut alloc = Ref::new(1);
         let page_tree_ref = alloc.bump();
         Self {
             document,
+            standard,
+            tagged,
+            encrypted: false,
+            errors: vec![],
             pdf: Pdf::new(),
             pages: vec![],
             glyph_sets: HashMap::new(),
             languages: HashMap::new(),
+            tags: Tags::default(),
             alloc,
             page_tree_ref,
             page_refs: vec![],
+            page_indices: vec![None; document.pages.len()],
             font_refs: vec![],
             image_refs: vec![],
             gradient_refs: vec![],
@@ -144,6 +249,13 @@ impl<'a> PdfContext<'a> {
             extg_map: Remapper::new(),
         }
     }
+
+    /// Reject a feature that the standard forbids.
+    fn reject(&mut self, error: SourceDiagnostic) {
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
 }
 
 /// Write the document catalog.
@@ -166,6 +278,9 @@ fn write_catalog(ctx: &mut PdfContext, ident: Option<&str>, timestamp: Option<Da
     // Write the page labels.
     let page_labels = page::write_page_labels(ctx);
 
+    // Write the structure tree.
+    let struct_tree_root = tags::write_structure_tree(ctx);
+
     // Write the document information.
     let mut info = ctx.pdf.document_info(ctx.alloc.bump());
     let mut xmp = XmpWriter::new();
@@ -221,7 +336,7 @@ fn write_catalog(ctx: &mut PdfContext, ident: Option<&str>, timestamp: Option<Da
     }
 
     info.finish();
-    xmp.num_pages(ctx.document.pages.len() as u32);
+    xmp.num_pages(ctx.pages.len() as u32);
     xmp.format("application/pdf");
     xmp.language(ctx.languages.keys().map(|lang| LangId(lang.as_str())));
 
@@ -245,6 +360,11 @@ fn write_catalog(ctx: &mut PdfContext, ident: Option<&str>, timestamp: Option<Da
     xmp.rendition_class(RenditionClass::Proof);
     xmp.pdf_version("1.7");
 
+    if ctx.standard == PdfStandard::A2b {
+        xmp.pdfa_part("2");
+        xmp.pdfa_conformance("B");
+    }
+
     let xmp_buf = xmp.finish(None);
     let meta_ref = ctx.alloc.bump();
     ctx.pdf
@@ -252,12 +372,43 @@ fn write_catalog(ctx: &mut PdfContext, ident: Option<&str>, timestamp: Option<Da
         .pair(Name(b"Type"), Name(b"Metadata"))
         .pair(Name(b"Subtype"), Name(b"XML"));
 
+    // PDF/A files declare the color space they are meant to be displayed in.
+    let output_profile = ctx.standard.is_pdfa().then(|| {
+        let profile = ctx.alloc.bump();
+        color::write_output_profile(&mut ctx.pdf, profile);
+        profile
+    });
+
     // Write the document catalog.
     let mut catalog = ctx.pdf.catalog(ctx.alloc.bump());
     catalog.pages(ctx.page_tree_ref);
     catalog.viewer_preferences().direction(dir);
     catalog.metadata(meta_ref);
 
+    // AES-256 encryption is part of PDF 2.0, which PDF 1.7 readers know as an
+    // Adobe extension.
+    if ctx.encrypted {
+        catalog
+            .insert(Name(b"Extensions"))
+            .dict()
+            .insert(Name(b"ADBE"))
+            .dict()
+            .pair(Name(b"BaseVersion"), Name(b"1.7"))
+            .pair(Name(b"ExtensionLevel"), 8);
+    }
+
+    if let Some(profile) = output_profile {
+        catalog
+            .insert(Name(b"OutputIntents"))
+            .array()
+            .push()
+            .start::<OutputIntent>()
+            .subtype(OutputIntentSubtype::PDFA)
+            .output_condition_identifier(TextStr("sRGB"))
+            .info(TextStr("sRGB IEC61966-2.1"))
+            .dest_output_profile(profile);
+    }
+
     // Insert the page labels.
     if !page_labels.is_empty() {
         let mut num_tree = catalog.page_labels();
@@ -271,6 +422,11 @@ fn write_catalog(ctx: &mut PdfContext, ident: Option<&str>, timestamp: Option<Da
         catalog.outlines(outline_root_id);
     }
 
+    if let Some(struct_tree_root) = struct_tree_root {
+        catalog.pair(Name(b"StructTreeRoot"), struct_tree_root);
+        catalog.mark_info().marked(true);
+    }
+
     if let Some(lang) = lang {
         catalog.lang(TextStr(lang.as_str()));
     }
@@ -420,3 +576,156 @@ fn transform_to_array(ts: Transform) -> [f32; 6] {
         ts.ty.to_f32(),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use typst::foundations::{Bytes, Content, NativeElement};
+    use typst::introspection::{Location, Meta};
+    use typst::layout::{Frame, FrameItem, Point, Size};
+    use typst::model::HeadingElem;
+    use typst::syntax::Span;
+    use typst::text::{Glyph, Lang, TextItem};
+    use typst::visualize::Color;
+
+    use super::*;
+
+    /// Export the pages with the given indices of a document with empty pages.
+    fn export(count: usize, pages: &[usize]) -> String {
+        let size = Size::splat(Abs::pt(100.0));
+        let document = Document {
+            pages: vec![Frame::hard(size); count],
+            ..Default::default()
+        };
+        let pdf =
+            pdf_pages(&document, None, None, pages, PdfStandard::Pdf17, false, None)
+                .unwrap();
+        String::from_utf8_lossy(&pdf).into_owned()
+    }
+
+    /// The page count and the numbers that page labels start at.
+    fn labels(pdf: &str) -> (usize, Vec<usize>) {
+        let number = |line: &str, key: &str| line.trim().strip_prefix(key)?.parse().ok();
+        let count = pdf.lines().find_map(|line| number(line, "/Count ")).unwrap();
+        let starts = pdf.lines().filter_map(|line| number(line, "/St ")).collect();
+        (count, starts)
+    }
+
+    #[test]
+    fn test_pdf_pages_full() {
+        let pdf = export(3, &[0, 1, 2]);
+        assert_eq!(labels(&pdf), (3, vec![]));
+        assert!(!pdf.contains("/PageLabels"));
+    }
+
+    #[test]
+    fn test_pdf_pages_partial() {
+        assert_eq!(labels(&export(4, &[1, 2])), (2, vec![2]));
+        assert_eq!(labels(&export(4, &[0, 2, 3])), (3, vec![1, 3]));
+        assert_eq!(labels(&export(4, &[3, 0])), (2, vec![4, 1]));
+        assert_eq!(labels(&export(2, &[1, 5])), (1, vec![2]));
+    }
+
+    /// A page with a heading and a paragraph of one word each.
+    ///
+    /// The heading's marker only spans its own line, like the marker of an
+    /// inlined frame. The heading's word is in a group of its own.
+    fn headed_page() -> Document {
+        let data = include_bytes!("../../../assets/fonts/LinLibertine_R.ttf");
+        let font = Font::new(Bytes::from_static(data), 0).unwrap();
+        let text = |text: &str| {
+            let glyphs = text
+                .char_indices()
+                .map(|(i, c)| {
+                    let id = font.ttf().glyph_index(c).unwrap().0;
+                    Glyph {
+                        id,
+                        x_advance: font.advance(id).unwrap(),
+                        x_offset: Em::zero(),
+                        range: i as u16..(i + c.len_utf8()) as u16,
+                        span: (Span::detached(), 0),
+                    }
+                })
+                .collect();
+            FrameItem::Text(TextItem {
+                font: font.clone(),
+                size: Abs::pt(10.0),
+                fill: Color::BLACK.into(),
+                stroke: None,
+                lang: Lang::ENGLISH,
+                text: text.into(),
+                glyphs,
+            })
+        };
+
+        let mut heading = HeadingElem::new(Content::empty()).pack();
+        heading.set_location(Location { hash: 1, disambiguator: 0, variant: 0 });
+        let line = Size::new(Abs::pt(100.0), Abs::pt(20.0));
+        let mut group = Frame::hard(line);
+        group.push(Point::with_y(Abs::pt(15.0)), text("Typst"));
+
+        let mut frame = Frame::soft(Size::new(Abs::pt(100.0), Abs::pt(40.0)));
+        frame.push(Point::zero(), FrameItem::Meta(Meta::Elem(heading), line));
+        frame.push_frame(Point::zero(), group);
+        frame.push(Point::with_y(Abs::pt(35.0)), text("PDF"));
+        Document { pages: vec![frame], ..Default::default() }
+    }
+
+    #[test]
+    fn test_pdf_tagged() {
+        let document = headed_page();
+        let pdf = pdf_pages(&document, None, None, &[0], PdfStandard::Pdf17, true, None);
+        let pdf = String::from_utf8_lossy(&pdf.unwrap()).into_owned();
+        assert!(pdf.contains("/StructTreeRoot"));
+        assert!(pdf.contains("/Marked true"));
+        assert_eq!(pdf.matches("/S /Document").count(), 1);
+        assert_eq!(pdf.matches("/S /H1").count(), 1);
+        assert_eq!(pdf.matches("/S /P").count(), 1);
+    }
+
+    #[test]
+    fn test_pdf_encrypted() {
+        let encryption = PdfEncryption {
+            owner_password: "owner".into(),
+            user_password: "user".into(),
+            printing: true,
+            copying: false,
+        };
+
+        let document = headed_page();
+        let export = |standard| {
+            pdf_pages(&document, None, None, &[0], standard, true, Some(&encryption))
+        };
+
+        let bytes = export(PdfStandard::Pdf17).unwrap();
+        let pdf = String::from_utf8_lossy(&bytes).into_owned();
+        assert!(pdf.contains("/CFM /AESV3"));
+        assert!(pdf.contains("/ExtensionLevel 8"));
+        assert!(pdf.contains("/P -20\n"));
+        assert!(!pdf.contains("Typst"));
+
+        // All objects are where the cross-reference table says they are.
+        let start = bytes.windows(6).rposition(|w| w == b"\nxref\n").unwrap() + 1;
+        let tail = std::str::from_utf8(&bytes[start..]).unwrap();
+        let (xref, trailer) = tail.split_once("trailer\n").unwrap();
+        let size = trailer.lines().find_map(|line| line.trim().strip_prefix("/Size "));
+        let entries: Vec<_> = xref.lines().skip(2).collect();
+        assert_eq!(entries.len(), size.unwrap().parse::<usize>().unwrap());
+        for (id, entry) in entries.iter().enumerate() {
+            if let Some(offset) = entry.strip_suffix(" 00000 n\r") {
+                let offset: usize = offset.parse().unwrap();
+                assert!(bytes[offset..].starts_with(format!("{id} 0 obj\n").as_bytes()));
+            }
+        }
+
+        assert!(trailer.contains(&format!("/Encrypt {} 0 R", entries.len() - 1)));
+        assert!(export(PdfStandard::A2b).is_err());
+    }
+
+    #[test]
+    fn test_pdf_untagged() {
+        let pdf = String::from_utf8_lossy(&pdf(&headed_page(), None, None)).into_owned();
+        assert!(!pdf.contains("/StructTreeRoot"));
+        assert!(!pdf.contains("/MarkInfo"));
+        assert!(!pdf.contains("/StructElem"));
+    }
+}
@@ -162,8 +162,8 @@ fn write_outline_item(
 
     let loc = node.element.location().unwrap();
     let pos = ctx.document.introspector.position(loc);
-    let index = pos.page.get() - 1;
-    if let Some(page) = ctx.pages.get(index) {
+    let index = ctx.page_indices.get(pos.page.get() - 1).copied().flatten();
+    if let Some((index, page)) = index.map(|index| (index, &ctx.pages[index])) {
         let y = (pos.point.y - Abs::pt(10.0)).max(Abs::zero());
         outline.dest().page(ctx.page_refs[index]).xyz(
             pos.point.x.to_f32(),
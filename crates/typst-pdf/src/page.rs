@@ -4,16 +4,19 @@ use std::num::NonZeroUsize;
 use ecow::{eco_format, EcoString};
 use pdf_writer::types::{
     ActionType, AnnotationFlags, AnnotationType, ColorSpaceOperand, LineCapStyle,
-    LineJoinStyle, NumberingStyle,
+    LineJoinStyle, NumberingStyle, TabOrder,
 };
 use pdf_writer::writers::PageLabel;
 use pdf_writer::{Content, Filter, Finish, Name, Rect, Ref, Str, TextStr};
+use ttf_parser::Permissions;
+use typst::diag::error;
 use typst::introspection::Meta;
 use typst::layout::{
     Abs, Em, Frame, FrameItem, GroupItem, PdfPageLabel, PdfPageLabelStyle, Point, Ratio,
     Size, Transform,
 };
 use typst::model::Destination;
+use typst::syntax::Span;
 use typst::text::{Font, TextItem};
 use typst::util::{Deferred, Numeric};
 use typst::visualize::{
@@ -27,22 +30,47 @@ use crate::{deflate_deferred, AbsExt, EmExt, PdfContext};
 
 /// Construct page objects.
 #[typst_macros::time(name = "construct pages")]
-pub(crate) fn construct_pages(ctx: &mut PdfContext, frames: &[Frame]) {
-    for frame in frames {
-        let (page_ref, page) = construct_page(ctx, frame);
+pub(crate) fn construct_pages(ctx: &mut PdfContext, pages: &[usize]) {
+    let document = ctx.document;
+    let partial = !pages.iter().copied().eq(0..document.pages.len());
+    for &i in pages {
+        let Some(frame) = document.pages.get(i) else { continue };
+        let (page_ref, mut page) = construct_page(ctx, frame, ctx.tagged);
+
+        // Keep the original page numbers visible in viewers.
+        if partial && page.label.is_none() {
+            page.label = Some(PdfPageLabel {
+                prefix: None,
+                style: Some(PdfPageLabelStyle::Arabic),
+                offset: NonZeroUsize::new(i + 1),
+            });
+        }
+
+        ctx.page_indices[i] = Some(ctx.pages.len());
         ctx.page_refs.push(page_ref);
         ctx.pages.push(page);
     }
 }
 
 /// Construct a page object.
+///
+/// If `tagged` is true, the content is added to the document's structure
+/// tree. This is the case for the document's pages if the file is tagged, but
+/// never for the contents of patterns.
 #[typst_macros::time(name = "construct page")]
-pub(crate) fn construct_page(ctx: &mut PdfContext, frame: &Frame) -> (Ref, Page) {
+pub(crate) fn construct_page(
+    ctx: &mut PdfContext,
+    frame: &Frame,
+    tagged: bool,
+) -> (Ref, Page) {
     let page_ref = ctx.alloc.bump();
+    let page_index = tagged.then_some(ctx.pages.len());
 
     let mut ctx = PageContext {
         parent: ctx,
         page_ref,
+        page_index,
+        marked: vec![],
         label: None,
         uses_opacities: false,
         content: Content::new(),
@@ -67,7 +95,7 @@ pub(crate) fn construct_page(ctx: &mut PdfContext, frame: &Frame) -> (Ref, Page)
     });
 
     // Encode the page into the content stream.
-    write_frame(&mut ctx, frame);
+    write_frame(&mut ctx, frame, &[]);
 
     let page = Page {
         size,
@@ -77,6 +105,7 @@ pub(crate) fn construct_page(ctx: &mut PdfContext, frame: &Frame) -> (Ref, Page)
         links: ctx.links,
         label: ctx.label,
         resources: ctx.resources,
+        marked: ctx.marked,
     };
 
     (page_ref, page)
@@ -153,6 +182,11 @@ fn write_page(ctx: &mut PdfContext, i: usize) {
     page_writer.media_box(Rect::new(0.0, 0.0, w, h));
     page_writer.contents(content_id);
 
+    if !page.marked.is_empty() {
+        page_writer.struct_parents(i as i32);
+        page_writer.tab_order(TabOrder::StructureOrder);
+    }
+
     if page.uses_opacities {
         page_writer
             .group()
@@ -181,7 +215,12 @@ fn write_page(ctx: &mut PdfContext, i: usize) {
             Destination::Location(loc) => ctx.document.introspector.position(*loc),
         };
 
-        let index = pos.page.get() - 1;
+        // Links to pages that are not exported have no destination.
+        let Some(index) = ctx.page_indices.get(pos.page.get() - 1).copied().flatten()
+        else {
+            continue;
+        };
+
         let y = (pos.point.y - Abs::pt(10.0)).max(Abs::zero());
         if let Some(page) = ctx.pages.get(index) {
             annotation
@@ -265,6 +304,9 @@ pub struct Page {
     pub label: Option<PdfPageLabel>,
     /// The page's used resources
     pub resources: HashMap<PageResource, usize>,
+    /// The structure element of each marked content sequence, indexed by its
+    /// identifier.
+    pub marked: Vec<usize>,
 }
 
 /// Represents a resource being used in a PDF page by its name.
@@ -326,6 +368,10 @@ impl PageResource {
 pub struct PageContext<'a, 'b> {
     pub(crate) parent: &'a mut PdfContext<'b>,
     page_ref: Ref,
+    /// The index of the written page, if the content is tagged.
+    page_index: Option<usize>,
+    /// The structure element of each marked content sequence.
+    marked: Vec<usize>,
     label: Option<PdfPageLabel>,
     pub content: Content,
     state: State,
@@ -396,6 +442,41 @@ pub(super) struct Transforms {
 }
 
 impl PageContext<'_, '_> {
+    /// Start a marked content sequence for an item and add it to the
+    /// structure tree.
+    ///
+    /// The `elems` are the located elements the item belongs to.
+    fn begin_tagged(
+        &mut self,
+        elems: &[&typst::foundations::Content],
+        image: Option<&Image>,
+    ) {
+        let Some(page) = self.page_index else { return };
+        let mcid = self.marked.len() as i32;
+        let index = self.parent.tags.add(elems, image, page, mcid);
+        self.marked.push(index);
+        self.content
+            .begin_marked_content_with_properties(self.parent.tags.name(index))
+            .properties()
+            .identify(mcid);
+    }
+
+    /// Start a marked content sequence for an item that is not part of the
+    /// document's logical structure, like a decorative shape.
+    fn begin_artifact(&mut self) {
+        if self.page_index.is_some() {
+            self.content.begin_marked_content(Name(b"Artifact"));
+        }
+    }
+
+    /// End a sequence started with [`begin_tagged`](Self::begin_tagged) or
+    /// [`begin_artifact`](Self::begin_artifact).
+    fn end_marked(&mut self) {
+        if self.page_index.is_some() {
+            self.content.end_marked_content();
+        }
+    }
+
     fn save_state(&mut self) {
         self.saves.push(self.state.clone());
         self.content.save_state();
@@ -553,18 +634,58 @@ impl PageContext<'_, '_> {
 }
 
 /// Encode a frame into the content stream.
-fn write_frame(ctx: &mut PageContext, frame: &Frame) {
+///
+/// The `elems` are the located elements that the whole frame belongs to.
+fn write_frame(
+    ctx: &mut PageContext,
+    frame: &Frame,
+    elems: &[&typst::foundations::Content],
+) {
+    // The markers of located elements seen so far and the areas they span.
+    // A marker precedes its element's content in the frame it was attached
+    // to. Because small frames are inlined into their parent, this frame may
+    // also contain unrelated content, so only the items within the area
+    // belong to the element. Markers without a size are placeholders that
+    // don't belong to any content.
+    let mut markers = vec![];
+
     for &(pos, ref item) in frame.items() {
         let x = pos.x.to_f32();
         let y = pos.y.to_f32();
 
+        let within: Vec<&typst::foundations::Content> = elems
+            .iter()
+            .copied()
+            .chain(
+                markers
+                    .iter()
+                    .filter(|&&(_, start, size)| contains(start, size, pos))
+                    .map(|&(elem, _, _)| elem),
+            )
+            .collect();
+
         match item {
-            FrameItem::Group(group) => write_group(ctx, pos, group),
-            FrameItem::Text(text) => write_text(ctx, pos, text),
-            FrameItem::Shape(shape, _) => write_shape(ctx, pos, shape),
-            FrameItem::Image(image, size, _) => write_image(ctx, x, y, image, *size),
+            FrameItem::Group(group) => write_group(ctx, pos, group, &within),
+            FrameItem::Text(text) => {
+                ctx.begin_tagged(&within, None);
+                write_text(ctx, pos, text);
+                ctx.end_marked();
+            }
+            FrameItem::Shape(shape, _) => {
+                ctx.begin_artifact();
+                write_shape(ctx, pos, shape);
+                ctx.end_marked();
+            }
+            FrameItem::Image(image, size, _) => {
+                ctx.begin_tagged(&within, Some(image));
+                write_image(ctx, x, y, image, *size);
+                ctx.end_marked();
+            }
             FrameItem::Meta(meta, size) => match meta {
                 Meta::Link(dest) => write_link(ctx, pos, dest, *size),
+                Meta::Elem(elem) if ctx.page_index.is_some() && !size.is_zero() => {
+                    markers.push((elem, pos, *size))
+                }
                 Meta::Elem(_) => {}
                 Meta::Hide => {}
                 Meta::PageNumbering(_) => {}
@@ -574,8 +695,22 @@ fn write_frame(ctx: &mut PageContext, frame: &Frame) {
     }
 }
 
+/// Whether a position lies within the area that starts at `start`.
+///
+/// The area excludes its right edge, where the next run of text on the same
+/// line starts.
+fn contains(start: Point, size: Size, pos: Point) -> bool {
+    (start.x..start.x + size.x).contains(&pos.x)
+        && (start.y..=start.y + size.y).contains(&pos.y)
+}
+
 /// Encode a group into the content stream.
-fn write_group(ctx: &mut PageContext, pos: Point, group: &GroupItem) {
+fn write_group(
+    ctx: &mut PageContext,
+    pos: Point,
+    group: &GroupItem,
+    elems: &[&typst::foundations::Content],
+) {
     let translation = Transform::translate(pos.x, pos.y);
 
     ctx.save_state();
@@ -598,7 +733,7 @@ fn write_group(ctx: &mut PageContext, pos: Point, group: &GroupItem) {
         ctx.content.end_path();
     }
 
-    write_frame(ctx, &group.frame);
+    write_frame(ctx, &group.frame, elems);
     ctx.restore_state();
 }
 
@@ -609,6 +744,10 @@ fn write_text(ctx: &mut PageContext, pos: Point, text: &TextItem) {
 
     *ctx.parent.languages.entry(text.lang).or_insert(0) += text.glyphs.len();
 
+    if ctx.parent.standard.is_pdfa() {
+        validate_pdfa(ctx.parent, text);
+    }
+
     let glyph_set = ctx.parent.glyph_sets.entry(text.font.clone()).or_default();
     for g in &text.glyphs {
         let segment = &text.text[g.range()];
@@ -667,6 +806,32 @@ fn write_text(ctx: &mut PageContext, pos: Point, text: &TextItem) {
     ctx.content.end_text();
 }
 
+/// Reject text that PDF/A forbids: PDF/A files must embed all fonts and must
+/// not show the `.notdef` glyph.
+fn validate_pdfa(ctx: &mut PdfContext, text: &TextItem) {
+    let standard = ctx.standard.name();
+    let family = &text.font.info().family;
+
+    if text.font.ttf().permissions() == Some(Permissions::Restricted) {
+        ctx.reject(
+            error!(
+                Span::detached(),
+                "{standard} requires embedding all fonts, \
+                 but the license of {family} forbids it"
+            )
+            .with_hint("choose a font that may be embedded"),
+        );
+    }
+
+    for glyph in text.glyphs.iter().filter(|glyph| glyph.id == 0) {
+        let segment = &text.text[glyph.range()];
+        ctx.reject(
+            error!(glyph.span.0, "{standard} does not allow missing glyphs")
+                .with_hint(eco_format!("{family} has no glyph for {segment:?}")),
+        );
+    }
+}
+
 /// Encode a geometrical shape into the content stream.
 fn write_shape(ctx: &mut PageContext, pos: Point, shape: &Shape) {
     let x = pos.x.to_f32();
@@ -114,7 +114,7 @@ fn register_pattern(
     };
 
     // Render the body.
-    let (_, content) = construct_page(ctx.parent, pattern.frame());
+    let (_, content) = construct_page(ctx.parent, pattern.frame(), false);
 
     let pdf_pattern = PdfPattern {
         transform,
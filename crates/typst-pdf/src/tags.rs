@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+
+use ecow::EcoString;
+use pdf_writer::types::StructRole;
+use pdf_writer::writers::StructTreeRoot;
+use pdf_writer::{Finish, Name, Ref, TextStr};
+use typst::foundations::{Content, StyleChain};
+use typst::introspection::Location;
+use typst::math::EquationElem;
+use typst::model::{
+    CellElem, EnumElem, FigureElem, HeadingElem, ListElem, TableElem, TermsElem,
+};
+use typst::visualize::Image;
+
+use crate::PdfContext;
+
+/// The logical structure of a document, which assistive technology like
+/// screen readers uses to navigate it.
+///
+/// Headings, figures, block equations, lists, tables and the cells of the
+/// latter two are located elements, so the content they produce can be
+/// recognized by the introspection markers around it. All other text is
+/// grouped into paragraphs between them.
+#[derive(Default)]
+pub struct Tags {
+    /// The structure elements in reading order.
+    elems: Vec<Tag>,
+    /// The structure element of each located element.
+    located: HashMap<Location, usize>,
+    /// The structure element of each table row and list item, by the location
+    /// of the table or list and the row's index.
+    rows: HashMap<(Location, usize), usize>,
+    /// The paragraph that untagged text is currently added to.
+    paragraph: Option<usize>,
+}
+
+impl Tags {
+    /// Whether no content was tagged.
+    pub fn is_empty(&self) -> bool {
+        self.elems.is_empty()
+    }
+
+    /// Add a marked content sequence on a page to the structure element of
+    /// the item it contains.
+    ///
+    /// The `elems` are the located elements the item belongs to, from the
+    /// outermost to the innermost one. The structure elements of those that
+    /// are tagged are nested into each other. If the item is an `image` that
+    /// doesn't belong to a figure, it becomes a figure of its own. Figures
+    /// without a description take the one of their image.
+    ///
+    /// Returns the index of the structure element.
+    pub fn add(
+        &mut self,
+        elems: &[&Content],
+        image: Option<&Image>,
+        page: usize,
+        mcid: i32,
+    ) -> usize {
+        let mut parent = None;
+        let mut container = None;
+        let mut figure = None;
+        let mut seen = vec![];
+
+        for elem in elems {
+            let Some(location) = elem.location() else { continue };
+            if seen.contains(&location) {
+                continue;
+            }
+            seen.push(location);
+
+            if let Some(cell) = elem.to::<CellElem>() {
+                let Some((of, role)) = container.take() else { continue };
+                let (row_role, cell_role) = match role {
+                    Role::List if cell.marker(StyleChain::default()) => {
+                        (Role::ListItem, Role::ListLabel)
+                    }
+                    Role::List => (Role::ListItem, Role::ListBody),
+                    _ => (Role::TableRow, Role::TableCell),
+                };
+
+                let row = match self.rows.get(&(of, *cell.row())) {
+                    Some(&row) => row,
+                    None => {
+                        let row = self.push(row_role, None, parent);
+                        self.rows.insert((of, *cell.row()), row);
+                        row
+                    }
+                };
+                parent = Some(self.open(location, cell_role, None, Some(row)));
+            } else if let Some(role) = Role::of(elem) {
+                let alt = elem
+                    .to::<FigureElem>()
+                    .and_then(|figure| figure.alt(StyleChain::default()));
+                let index = self.open(location, role, alt, parent);
+                if role == Role::Figure {
+                    figure = Some(index);
+                }
+                container =
+                    matches!(role, Role::List | Role::Table).then_some((location, role));
+                parent = Some(index);
+            }
+        }
+
+        if image.is_some() && figure.is_none() {
+            let index = self.push(Role::Figure, None, parent);
+            figure = Some(index);
+            parent = Some(index);
+        }
+
+        let index = match parent {
+            Some(index) if !self.elems[index].role.is_container() => index,
+            _ => match self.paragraph {
+                Some(paragraph) if self.elems[paragraph].parent == parent => paragraph,
+                _ => self.push(Role::Paragraph, None, parent),
+            },
+        };
+
+        self.paragraph = (self.elems[index].role == Role::Paragraph).then_some(index);
+
+        if let (Some(figure), Some(alt)) = (figure, image.and_then(Image::alt)) {
+            self.elems[figure].alt.get_or_insert_with(|| alt.into());
+        }
+
+        self.elems[index].kids.push(Kid::Marked(page, mcid));
+        index
+    }
+
+    /// The tag of marked content sequences for a structure element.
+    pub fn name(&self, index: usize) -> Name<'static> {
+        self.elems[index].role.name()
+    }
+
+    /// The structure element of a located element, which is created if it
+    /// doesn't exist yet.
+    fn open(
+        &mut self,
+        location: Location,
+        role: Role,
+        alt: Option<EcoString>,
+        parent: Option<usize>,
+    ) -> usize {
+        match self.located.get(&location) {
+            Some(&index) => index,
+            None => {
+                let index = self.push(role, alt, parent);
+                self.located.insert(location, index);
+                index
+            }
+        }
+    }
+
+    /// Create a structure element as the last child of its parent.
+    fn push(
+        &mut self,
+        role: Role,
+        alt: Option<EcoString>,
+        parent: Option<usize>,
+    ) -> usize {
+        let index = self.elems.len();
+        self.elems.push(Tag { role, alt, parent, kids: vec![] });
+        if let Some(parent) = parent {
+            self.elems[parent].kids.push(Kid::Elem(index));
+        }
+        index
+    }
+}
+
+/// A structure element.
+struct Tag {
+    /// What kind of element this is.
+    role: Role,
+    /// A description of the element, for figures.
+    alt: Option<EcoString>,
+    /// The structure element this one is nested into, if it isn't directly
+    /// part of the document.
+    parent: Option<usize>,
+    /// The nested structure elements and marked content sequences that make
+    /// up the element, in reading order.
+    kids: Vec<Kid>,
+}
+
+/// A part of a structure element.
+enum Kid {
+    /// A nested structure element.
+    Elem(usize),
+    /// A marked content sequence, as the written page index and its marked
+    /// content identifier.
+    Marked(usize, i32),
+}
+
+/// The kind of a structure element.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Role {
+    Paragraph,
+    Heading(NonZeroUsize),
+    Figure,
+    Formula,
+    List,
+    ListItem,
+    ListLabel,
+    ListBody,
+    Table,
+    TableRow,
+    TableCell,
+}
+
+impl Role {
+    /// The role of a located element, if it is tagged.
+    ///
+    /// Cells have no role of their own, it depends on their container.
+    fn of(elem: &Content) -> Option<Self> {
+        if let Some(heading) = elem.to::<HeadingElem>() {
+            Some(Self::Heading(heading.level(StyleChain::default())))
+        } else if elem.is::<FigureElem>() {
+            Some(Self::Figure)
+        } else if let Some(equation) = elem.to::<EquationElem>() {
+            equation.block(StyleChain::default()).then_some(Self::Formula)
+        } else if elem.is::<ListElem>() || elem.is::<EnumElem>() || elem.is::<TermsElem>()
+        {
+            Some(Self::List)
+        } else if elem.is::<TableElem>() {
+            Some(Self::Table)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the element only contains other structure elements, so that
+    /// text in it needs a paragraph.
+    fn is_container(self) -> bool {
+        matches!(self, Self::List | Self::ListItem | Self::Table | Self::TableRow)
+    }
+
+    /// The standard structure role.
+    fn to_struct_role(self) -> StructRole {
+        match self {
+            Self::Paragraph => StructRole::P,
+            Self::Heading(level) => match level.get() {
+                1 => StructRole::H1,
+                2 => StructRole::H2,
+                3 => StructRole::H3,
+                4 => StructRole::H4,
+                5 => StructRole::H5,
+                _ => StructRole::H6,
+            },
+            Self::Figure => StructRole::Figure,
+            Self::Formula => StructRole::Formula,
+            Self::List => StructRole::L,
+            Self::ListItem => StructRole::LI,
+            Self::ListLabel => StructRole::Lbl,
+            Self::ListBody => StructRole::LBody,
+            Self::Table => StructRole::Table,
+            Self::TableRow => StructRole::TR,
+            Self::TableCell => StructRole::TD,
+        }
+    }
+
+    /// The tag of marked content sequences with this role.
+    fn name(self) -> Name<'static> {
+        Name(match self {
+            Self::Paragraph => b"P",
+            Self::Heading(level) => match level.get() {
+                1 => b"H1",
+                2 => b"H2",
+                3 => b"H3",
+                4 => b"H4",
+                5 => b"H5",
+                _ => b"H6",
+            },
+            Self::Figure => b"Figure",
+            Self::Formula => b"Formula",
+            Self::List => b"L",
+            Self::ListItem => b"LI",
+            Self::ListLabel => b"Lbl",
+            Self::ListBody => b"LBody",
+            Self::Table => b"Table",
+            Self::TableRow => b"TR",
+            Self::TableCell => b"TD",
+        })
+    }
+}
+
+/// Write the structure tree.
+///
+/// Returns the reference to the structure tree root, if any content was
+/// tagged.
+pub(crate) fn write_structure_tree(ctx: &mut PdfContext) -> Option<Ref> {
+    if ctx.tags.is_empty() {
+        return None;
+    }
+
+    let root_ref = ctx.alloc.bump();
+    let document_ref = ctx.alloc.bump();
+    let elem_refs: Vec<Ref> = ctx.tags.elems.iter().map(|_| ctx.alloc.bump()).collect();
+
+    let mut document = ctx.pdf.struct_element(document_ref);
+    document.kind(StructRole::Document).parent(root_ref);
+    let mut children = document.children();
+    for (tag, &elem_ref) in ctx.tags.elems.iter().zip(&elem_refs) {
+        if tag.parent.is_none() {
+            children.struct_element(elem_ref);
+        }
+    }
+    children.finish();
+    document.finish();
+
+    for (tag, &elem_ref) in ctx.tags.elems.iter().zip(&elem_refs) {
+        let mut elem = ctx.pdf.struct_element(elem_ref);
+        elem.kind(tag.role.to_struct_role())
+            .parent(tag.parent.map_or(document_ref, |parent| elem_refs[parent]));
+        if let Some(alt) = &tag.alt {
+            elem.alt(TextStr(alt));
+        }
+
+        let mut children = elem.children();
+        for kid in &tag.kids {
+            match *kid {
+                Kid::Elem(index) => {
+                    children.struct_element(elem_refs[index]);
+                }
+                Kid::Marked(page, mcid) => {
+                    children
+                        .marked_content_ref()
+                        .page(ctx.page_refs[page])
+                        .marked_content_id(mcid);
+                }
+            }
+        }
+    }
+
+    // For each page, the structure elements of its marked content sequences,
+    // indexed by their identifiers.
+    let mut parents = vec![];
+    for (i, page) in ctx.pages.iter().enumerate() {
+        if page.marked.is_empty() {
+            continue;
+        }
+
+        let array_ref = ctx.alloc.bump();
+        ctx.pdf
+            .indirect(array_ref)
+            .array()
+            .items(page.marked.iter().map(|&index| elem_refs[index]));
+        parents.push((i as i32, array_ref));
+    }
+
+    let mut root = ctx.pdf.indirect(root_ref).start::<StructTreeRoot>();
+    root.child(document_ref);
+    let mut tree = root.parent_tree();
+    let mut nums = tree.nums();
+    for (key, array_ref) in parents {
+        nums.insert(key, array_ref);
+    }
+    nums.finish();
+    tree.finish();
+    root.parent_tree_next_key(ctx.pages.len() as i32);
+    root.finish();
+
+    Some(root_ref)
+}
+
+#[cfg(test)]
+mod tests {
+    use typst::foundations::NativeElement;
+
+    use super::*;
+
+    /// Give an element a location that is unique for `n`.
+    fn locate(elem: impl NativeElement, n: u128) -> Content {
+        let mut content = elem.pack();
+        content.set_location(Location { hash: n, disambiguator: 0, variant: 0 });
+        content
+    }
+
+    /// The role and parent of each structure element.
+    fn tree(tags: &Tags) -> Vec<(Role, Option<usize>)> {
+        tags.elems.iter().map(|tag| (tag.role, tag.parent)).collect()
+    }
+
+    #[test]
+    fn test_tags_paragraphs() {
+        let heading = locate(HeadingElem::new(Content::empty()), 1);
+        let mut tags = Tags::default();
+        assert_eq!(tags.add(&[], None, 0, 0), 0);
+        assert_eq!(tags.add(&[], None, 0, 1), 0);
+        assert_eq!(tags.add(&[&heading], None, 0, 2), 1);
+        assert_eq!(tags.add(&[&heading, &heading], None, 0, 3), 1);
+        assert_eq!(tags.add(&[], None, 1, 0), 2);
+        assert_eq!(
+            tree(&tags),
+            [
+                (Role::Paragraph, None),
+                (Role::Heading(NonZeroUsize::new(1).unwrap()), None),
+                (Role::Paragraph, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tags_list() {
+        let list = locate(ListElem::new(vec![]), 1);
+        let marker =
+            |row, n| locate(CellElem::new(row, Content::empty()).with_marker(true), n);
+        let body = |row, n| locate(CellElem::new(row, Content::empty()), n);
+        let (marker0, body0, marker1) = (marker(0, 2), body(0, 3), marker(1, 4));
+
+        let mut tags = Tags::default();
+        assert_eq!(tags.add(&[&list, &marker0], None, 0, 0), 2);
+        assert_eq!(tags.add(&[&list, &body0], None, 0, 1), 3);
+        assert_eq!(tags.add(&[&list, &body0], None, 0, 2), 3);
+        assert_eq!(tags.add(&[&list, &marker1], None, 0, 3), 5);
+        assert_eq!(tags.add(&[&list], None, 0, 4), 6);
+        assert_eq!(
+            tree(&tags),
+            [
+                (Role::List, None),
+                (Role::ListItem, Some(0)),
+                (Role::ListLabel, Some(1)),
+                (Role::ListBody, Some(1)),
+                (Role::ListItem, Some(0)),
+                (Role::ListLabel, Some(4)),
+                (Role::Paragraph, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tags_table() {
+        let figure = FigureElem::new(Content::empty()).with_alt(Some("Data".into()));
+        let figure = locate(figure, 1);
+        let table = locate(TableElem::new(vec![]), 2);
+        let cells: Vec<_> = (0..3)
+            .map(|i| locate(CellElem::new(i / 2, Content::empty()), 3 + i as u128))
+            .collect();
+
+        let mut tags = Tags::default();
+        for (i, cell) in cells.iter().enumerate() {
+            tags.add(&[&figure, &table, cell], None, 0, i as i32);
+        }
+        assert_eq!(tags.add(&[&figure], None, 0, 3), 0);
+        assert_eq!(tags.elems[0].alt.as_deref(), Some("Data"));
+        assert_eq!(
+            tree(&tags),
+            [
+                (Role::Figure, None),
+                (Role::Table, Some(0)),
+                (Role::TableRow, Some(1)),
+                (Role::TableCell, Some(2)),
+                (Role::TableCell, Some(2)),
+                (Role::TableRow, Some(1)),
+                (Role::TableCell, Some(5)),
+            ]
+        );
+    }
+}
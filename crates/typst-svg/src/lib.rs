@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+mod woff2;
+
+use std::collections::{BTreeSet, HashMap};
 use std::f32::consts::TAU;
 use std::fmt::{self, Display, Formatter, Write};
 use std::io::Read;
@@ -11,7 +13,8 @@ use typst::layout::{
     Abs, Angle, Axes, Frame, FrameItem, FrameKind, GroupItem, Point, Quadrant, Ratio,
     Size, Transform,
 };
-use typst::text::{Font, TextItem};
+use typst::model::Document;
+use typst::text::{Font, FontStyle, TextItem};
 use typst::util::hash128;
 use typst::visualize::{
     Color, FixedStroke, Geometry, Gradient, Image, ImageFormat, LineCap, LineJoin, Paint,
@@ -25,10 +28,39 @@ use xmlwriter::XmlWriter;
 const CONIC_SEGMENT: usize = 360;
 
 /// Export a frame into a SVG file.
-#[typst_macros::time(name = "svg")]
 pub fn svg(frame: &Frame) -> String {
+    svg_with_text(frame, TextMode::Paths)
+}
+
+/// Export a frame into a SVG file, representing text as specified.
+#[typst_macros::time(name = "svg")]
+pub fn svg_with_text(frame: &Frame, text_mode: TextMode) -> String {
+    render_page(frame, text_mode, None)
+}
+
+/// Export a page of a document into a SVG file, representing text as
+/// specified.
+///
+/// The document's title becomes the file's `<title>` and its authors and
+/// keywords are written as Dublin Core `<metadata>`.
+#[typst_macros::time(name = "svg page")]
+pub fn svg_page(document: &Document, page: usize, text_mode: TextMode) -> String {
+    render_page(&document.pages[page], text_mode, Some(document))
+}
+
+/// Render a frame into a SVG file, with the metadata of the document it
+/// belongs to, if any.
+fn render_page(
+    frame: &Frame,
+    text_mode: TextMode,
+    document: Option<&Document>,
+) -> String {
     let mut renderer = SVGRenderer::new();
+    renderer.text_mode = text_mode;
     renderer.write_header(frame.size());
+    if let Some(document) = document {
+        renderer.write_metadata(document);
+    }
 
     let state = State::new(frame.size(), Transform::identity());
     renderer.render_frame(state, Transform::identity(), frame);
@@ -58,10 +90,30 @@ pub fn svg_merged(frames: &[Frame], padding: Abs) -> String {
     renderer.finalize()
 }
 
+/// How text is represented in an SVG file.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TextMode {
+    /// Glyphs are converted to paths, which look the same in every viewer,
+    /// but the text can't be selected or searched.
+    #[default]
+    Paths,
+    /// Text is written as text, with subsets of the fonts embedded into the
+    /// file.
+    EmbedFonts,
+    /// Text is written as text that refers to fonts by their family name,
+    /// which must be installed wherever the file is viewed.
+    Plain,
+}
+
 /// Renders one or multiple frames to an SVG file.
 struct SVGRenderer {
     /// The internal XML writer.
     xml: XmlWriter,
+    /// How text is represented.
+    text_mode: TextMode,
+    /// The fonts used by text written as text, along with the glyphs that
+    /// must be kept when embedding them.
+    fonts: Vec<(Font, BTreeSet<u16>)>,
     /// Prepared glyphs.
     glyphs: Deduplicator<RenderedGlyph>,
     /// Clip paths are used to clip a group. A clip path is a path that defines
@@ -218,6 +270,8 @@ impl SVGRenderer {
     fn new() -> Self {
         SVGRenderer {
             xml: XmlWriter::new(xmlwriter::Options::default()),
+            text_mode: TextMode::Paths,
+            fonts: vec![],
             glyphs: Deduplicator::new('g'),
             clip_paths: Deduplicator::new('c'),
             gradient_refs: Deduplicator::new('g'),
@@ -245,6 +299,50 @@ impl SVGRenderer {
         self.xml.write_attribute("xmlns:h5", "http://www.w3.org/1999/xhtml");
     }
 
+    /// Write the document's title and other metadata.
+    fn write_metadata(&mut self, document: &Document) {
+        if let Some(title) = &document.title {
+            self.write_text_element("title", title);
+        }
+
+        if document.author.is_empty() && document.keywords.is_empty() {
+            return;
+        }
+
+        self.xml.start_element("metadata");
+        self.xml.start_element("rdf:RDF");
+        self.xml
+            .write_attribute("xmlns:rdf", "http://www.w3.org/1999/02/22-rdf-syntax-ns#");
+        self.xml
+            .write_attribute("xmlns:dc", "http://purl.org/dc/elements/1.1/");
+        self.xml.start_element("rdf:Description");
+        for author in &document.author {
+            self.write_text_element("dc:creator", author);
+        }
+        for keyword in &document.keywords {
+            self.write_text_element("dc:subject", keyword);
+        }
+        self.xml.end_element();
+        self.xml.end_element();
+        self.xml.end_element();
+    }
+
+    /// Write a text node.
+    fn write_text(&mut self, text: &str) {
+        // The writer only escapes `<` by itself.
+        self.xml.write_text(&text.replace('&', "&amp;"));
+    }
+
+    /// Write an element that only contains text.
+    fn write_text_element(&mut self, name: &str, text: &str) {
+        self.xml.start_element(name);
+        // Indentation would become part of the text.
+        self.xml.set_preserve_whitespaces(true);
+        self.write_text(text);
+        self.xml.end_element();
+        self.xml.set_preserve_whitespaces(false);
+    }
+
     /// Render a frame to a string.
     fn render_pattern_frame(
         &mut self,
@@ -325,6 +423,10 @@ impl SVGRenderer {
     /// try to render the text as SVG first, then bitmap, then outline. If none
     /// of them works, we will skip the text.
     fn render_text(&mut self, state: State, text: &TextItem) {
+        if self.text_mode != TextMode::Paths && self.render_text_as_text(text).is_some() {
+            return;
+        }
+
         let scale: f64 = text.size.to_pt() / text.font.units_per_em();
 
         self.xml.start_element("g");
@@ -356,6 +458,106 @@ impl SVGRenderer {
         self.xml.end_element();
     }
 
+    /// Render a text item as a `<text>` element, placing each character where
+    /// the layout put it.
+    ///
+    /// Returns `None` if the text can't be represented faithfully that way,
+    /// because it isn't filled with a solid color, is stroked, or uses color
+    /// glyphs.
+    fn render_text_as_text(&mut self, text: &TextItem) -> Option<()> {
+        if !matches!(text.fill, Paint::Solid(_))
+            || text.stroke.is_some()
+            || has_color_glyphs(&text.font)
+        {
+            return None;
+        }
+
+        // Glyphs that belong to the same cluster, like ligatures, share the
+        // cluster's advance among its characters.
+        let mut content = String::new();
+        let mut positions = String::new();
+        let mut x = 0.0;
+        let mut i = 0;
+        while i < text.glyphs.len() {
+            let first = &text.glyphs[i];
+            let range = first.range();
+            let mut advance = 0.0;
+            while i < text.glyphs.len() && text.glyphs[i].range() == range {
+                advance += text.glyphs[i].x_advance.at(text.size).to_pt();
+                i += 1;
+            }
+
+            let cluster = text.text.get(range)?;
+            let count = cluster.chars().count().max(1) as f64;
+            let start = x + first.x_offset.at(text.size).to_pt();
+            for (k, c) in cluster.chars().enumerate() {
+                content.push(c);
+                write!(positions, "{} ", start + k as f64 * advance / count).ok();
+            }
+
+            x += advance;
+        }
+
+        let index = match self.fonts.iter().position(|(font, _)| *font == text.font) {
+            Some(index) => index,
+            None => {
+                self.fonts.push((text.font.clone(), BTreeSet::from([0])));
+                self.fonts.len() - 1
+            }
+        };
+
+        // Keep the glyphs that viewers look up for the characters and those
+        // that the layout substituted for them, which viewers arrive at with
+        // the font's layout tables.
+        let ttf = text.font.ttf();
+        let glyphs = &mut self.fonts[index].1;
+        glyphs.extend(content.chars().filter_map(|c| ttf.glyph_index(c)).map(|id| id.0));
+        glyphs.extend(text.glyphs.iter().map(|glyph| glyph.id));
+
+        self.xml.start_element("text");
+        self.xml.write_attribute("class", "typst-text");
+        self.xml.write_attribute("x", positions.trim_end());
+        match self.text_mode {
+            TextMode::EmbedFonts => {
+                self.xml.write_attribute_fmt(
+                    "font-family",
+                    format_args!("typst-font-{index}"),
+                );
+            }
+            _ => {
+                let info = text.font.info();
+                self.xml.write_attribute_fmt(
+                    "font-family",
+                    format_args!("'{}'", info.family.replace('\'', "\\'")),
+                );
+                self.xml
+                    .write_attribute("font-weight", &info.variant.weight.to_number());
+                match info.variant.style {
+                    FontStyle::Normal => {}
+                    FontStyle::Italic => self.xml.write_attribute("font-style", "italic"),
+                    FontStyle::Oblique => {
+                        self.xml.write_attribute("font-style", "oblique")
+                    }
+                }
+            }
+        }
+        self.xml.write_attribute("font-size", &text.size.to_pt());
+        self.write_fill(&text.fill, Size::zero(), Transform::identity());
+
+        // The characters are in visual order already.
+        self.xml.write_attribute("direction", "ltr");
+        self.xml.write_attribute("unicode-bidi", "bidi-override");
+        self.xml.write_attribute("xml:space", "preserve");
+
+        // Indentation would become part of the text.
+        self.xml.set_preserve_whitespaces(true);
+        self.write_text(&content);
+        self.xml.end_element();
+        self.xml.set_preserve_whitespaces(false);
+
+        Some(())
+    }
+
     /// Render a glyph defined by an SVG.
     fn render_svg_glyph(
         &mut self,
@@ -699,6 +901,7 @@ impl SVGRenderer {
 
     /// Finalize the SVG file. This must be called after all rendering is done.
     fn finalize(mut self) -> String {
+        self.write_font_faces();
         self.write_glyph_defs();
         self.write_clip_path_defs();
         self.write_gradients();
@@ -709,6 +912,38 @@ impl SVGRenderer {
         self.xml.end_document()
     }
 
+    /// Embed the subsetted fonts used by text written as text.
+    fn write_font_faces(&mut self) {
+        if self.text_mode != TextMode::EmbedFonts || self.fonts.is_empty() {
+            return;
+        }
+
+        let mut css = String::new();
+        for (i, (font, glyphs)) in self.fonts.iter().enumerate() {
+            let glyphs: Vec<u16> = glyphs.iter().copied().collect();
+            let profile = subsetter::Profile::pdf(&glyphs);
+            let subsetted = subsetter::subset(font.data(), font.index(), profile);
+            let (data, index) = match &subsetted {
+                Ok(data) => (data.as_slice(), 0),
+                Err(_) => (font.data().as_slice(), font.index()),
+            };
+            let Some(woff) = woff2::woff2(data, index, font) else { continue };
+            let base64 = base64::engine::general_purpose::STANDARD.encode(woff);
+            write!(
+                css,
+                "@font-face {{ font-family: typst-font-{i}; \
+                 src: url(\"data:font/woff2;base64,{base64}\") format(\"woff2\"); }}",
+            )
+            .ok();
+        }
+
+        self.xml.start_element("defs");
+        self.xml.start_element("style");
+        self.xml.write_text(&css);
+        self.xml.end_element();
+        self.xml.end_element();
+    }
+
     /// Build the glyph definitions.
     fn write_glyph_defs(&mut self) {
         if self.glyphs.is_empty() {
@@ -1088,6 +1323,16 @@ impl SVGRenderer {
     }
 }
 
+/// Whether a font draws some glyphs in color, which the font subsets embedded
+/// into SVGs can't represent.
+fn has_color_glyphs(font: &Font) -> bool {
+    let tables = font.ttf().tables();
+    tables.colr.is_some()
+        || tables.sbix.is_some()
+        || tables.cbdt.is_some()
+        || tables.svg.is_some()
+}
+
 /// Convert an outline glyph to an SVG path.
 #[comemo::memoize]
 fn convert_outline_glyph_to_path(
@@ -1499,3 +1744,116 @@ impl ColorEncode for Color {
 fn correct_pattern_pos(x: f32) -> f32 {
     (x + 0.5) / 2.0
 }
+
+#[cfg(test)]
+mod tests {
+    use typst::foundations::Bytes;
+    use typst::layout::Em;
+    use typst::syntax::Span;
+    use typst::text::{Glyph, Lang};
+
+    use super::*;
+
+    /// A page with the given lines of text in Linux Libertine, each placed at
+    /// a baseline and shaped without substitutions.
+    fn page(lines: &[(f64, &str)]) -> Document {
+        let data = include_bytes!("../../../assets/fonts/LinLibertine_R.ttf");
+        let font = Font::new(Bytes::from_static(data), 0).unwrap();
+        let mut frame = Frame::soft(Size::new(Abs::pt(100.0), Abs::pt(40.0)));
+        for &(y, text) in lines {
+            let glyphs = text
+                .char_indices()
+                .map(|(i, c)| {
+                    let id = font.ttf().glyph_index(c).unwrap().0;
+                    Glyph {
+                        id,
+                        x_advance: font.advance(id).unwrap(),
+                        x_offset: Em::zero(),
+                        range: i as u16..(i + c.len_utf8()) as u16,
+                        span: (Span::detached(), 0),
+                    }
+                })
+                .collect();
+            let item = TextItem {
+                font: font.clone(),
+                size: Abs::pt(10.0),
+                fill: Color::BLACK.into(),
+                stroke: None,
+                lang: Lang::ENGLISH,
+                text: text.into(),
+                glyphs,
+            };
+            frame.push(Point::new(Abs::zero(), Abs::pt(y)), FrameItem::Text(item));
+        }
+        Document { pages: vec![frame], ..Default::default() }
+    }
+
+    #[test]
+    fn test_svg_page_paths() {
+        let svg = svg_page(&page(&[(15.0, "Typst")]), 0, TextMode::Paths);
+        assert!(!svg.contains("<text"));
+        assert!(!svg.contains("@font-face"));
+        assert_eq!(svg.matches("<use").count(), 5);
+    }
+
+    #[test]
+    fn test_svg_page_plain() {
+        let svg = svg_page(&page(&[(15.0, "Typst"), (30.0, "SVG")]), 0, TextMode::Plain);
+        assert!(!svg.contains("@font-face"));
+        assert!(svg.contains("font-family=\"'Linux Libertine'\""));
+        assert!(svg.contains(">Typst</text>"));
+        assert!(svg.contains(">SVG</text>"));
+
+        // Each line is positioned at its baseline and each character at the
+        // position that the layout gave it.
+        assert!(svg.contains("transform=\"translate(0 15)\""));
+        assert!(svg.contains("transform=\"translate(0 30)\""));
+        let x = svg.split(" x=\"").nth(1).unwrap().split('"').next().unwrap();
+        assert_eq!(x.split(' ').count(), 5);
+        assert!(x.starts_with("0 "));
+    }
+
+    #[test]
+    fn test_svg_with_text() {
+        let document = page(&[(15.0, "Typst")]);
+        let frame = &document.pages[0];
+        assert_eq!(svg(frame), svg_with_text(frame, TextMode::Paths));
+        for mode in [TextMode::Paths, TextMode::EmbedFonts, TextMode::Plain] {
+            assert_eq!(svg_with_text(frame, mode), svg_page(&document, 0, mode));
+        }
+    }
+
+    #[test]
+    fn test_svg_page_embed_fonts() {
+        let svg = svg_page(&page(&[(15.0, "Typst")]), 0, TextMode::EmbedFonts);
+        assert!(svg.contains(">Typst</text>"));
+        assert!(svg.contains("font-family=\"typst-font-0\""));
+
+        let (_, rest) = svg.split_once("data:font/woff2;base64,").unwrap();
+        let base64 = rest.split('"').next().unwrap();
+        let woff = base64::engine::general_purpose::STANDARD.decode(base64).unwrap();
+        assert_eq!(&woff[..4], b"wOF2");
+        assert_eq!(&woff[4..8], &[0, 1, 0, 0]);
+        let length = u32::from_be_bytes(woff[8..12].try_into().unwrap());
+        assert_eq!(length as usize, woff.len());
+        assert_eq!(woff.len() % 4, 0);
+
+        // The layout tables are kept.
+        let tables = u16::from_be_bytes([woff[12], woff[13]]) as usize;
+        let directory = &woff[48..];
+        let mut tags = vec![];
+        let mut at = 0;
+        for _ in 0..tables {
+            assert_eq!(directory[at] & 0x3f, 0x3f);
+            tags.push(std::str::from_utf8(&directory[at + 1..at + 5]).unwrap());
+            at += 5;
+            while directory[at] & 0x80 != 0 {
+                at += 1;
+            }
+            at += 1;
+        }
+        assert!(tags.contains(&"GSUB"));
+        assert!(tags.contains(&"GPOS"));
+        assert!(tags.contains(&"glyf"));
+    }
+}
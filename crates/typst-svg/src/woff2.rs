@@ -0,0 +1,151 @@
+//! Packaging of embedded fonts in the WOFF2 format.
+
+use brotli::enc::backward_references::BrotliEncoderMode;
+use brotli::enc::BrotliEncoderParams;
+use ttf_parser::{RawFace, Tag};
+use typst::text::Font;
+
+/// The tables that viewers shape text with. They are taken from the original
+/// font because subsetting drops them. Subsetting keeps the glyph ids intact,
+/// so they remain valid.
+const LAYOUT_TABLES: [&[u8; 4]; 3] = [b"GDEF", b"GPOS", b"GSUB"];
+
+/// Package a subset of a font as WOFF2, together with the layout tables of the
+/// original font.
+///
+/// The `data` and `index` locate the subset, which may also be the original
+/// font if it couldn't be subsetted. Returns `None` if it can't be parsed.
+pub fn woff2(data: &[u8], index: u32, font: &Font) -> Option<Vec<u8>> {
+    let face = RawFace::parse(data, index).ok()?;
+    let mut tables = vec![];
+    for record in face.table_records {
+        let start = usize::try_from(record.offset).ok()?;
+        let end = start.checked_add(usize::try_from(record.length).ok()?)?;
+        tables.push((record.tag, data.get(start..end)?));
+    }
+
+    let original = font.ttf().raw_face();
+    for tag in LAYOUT_TABLES.map(Tag::from_bytes) {
+        if tables.iter().all(|&(other, _)| other != tag) {
+            if let Some(table) = original.table(tag) {
+                tables.push((tag, table));
+            }
+        }
+    }
+    tables.sort_by_key(|&(tag, _)| tag);
+
+    let has = |name| tables.iter().any(|&(tag, _)| tag == Tag::from_bytes(name));
+    let flavor: &[u8; 4] =
+        if has(b"CFF ") || has(b"CFF2") { b"OTTO" } else { &[0, 1, 0, 0] };
+
+    // Each table is stored with its tag rather than an index into the known
+    // tags and without a transformation. For `glyf` and `loca`, the version
+    // of the null transform is 3 instead of 0.
+    let mut directory = vec![];
+    for &(tag, table) in &tables {
+        let version =
+            if tag == Tag::from_bytes(b"glyf") || tag == Tag::from_bytes(b"loca") {
+                3
+            } else {
+                0
+            };
+        directory.push(version << 6 | 0x3f);
+        directory.extend(tag.to_bytes());
+        write_base128(&mut directory, u32::try_from(table.len()).ok()?);
+    }
+
+    let stream: Vec<u8> = tables.iter().flat_map(|&(_, table)| table).copied().collect();
+    let compressed = compress(&stream)?;
+    let sfnt_size = 12
+        + 16 * tables.len()
+        + tables.iter().map(|&(_, table)| pad4(table.len())).sum::<usize>();
+    let length = pad4(48 + directory.len() + compressed.len());
+
+    let mut woff = Vec::with_capacity(length);
+    woff.extend(b"wOF2");
+    woff.extend(flavor);
+    woff.extend(u32::try_from(length).ok()?.to_be_bytes());
+    woff.extend(u16::try_from(tables.len()).ok()?.to_be_bytes());
+    woff.extend([0; 2]);
+    woff.extend(u32::try_from(sfnt_size).ok()?.to_be_bytes());
+    woff.extend(u32::try_from(compressed.len()).ok()?.to_be_bytes());
+    woff.extend([0, 1, 0, 0]);
+
+    // There is no metadata or private data.
+    woff.extend([0; 20]);
+    woff.extend(directory);
+    woff.extend(compressed);
+    woff.resize(length, 0);
+    Some(woff)
+}
+
+/// Round a length up to a multiple of four.
+fn pad4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Write a number in WOFF2's variable-length `UIntBase128` encoding.
+fn write_base128(out: &mut Vec<u8>, value: u32) {
+    let mut started = false;
+    for shift in (0..5).rev() {
+        let digit = (value >> (7 * shift)) as u8 & 0x7f;
+        if digit != 0 || started || shift == 0 {
+            started = true;
+            out.push(if shift > 0 { digit | 0x80 } else { digit });
+        }
+    }
+}
+
+/// Compress the font data with Brotli, as WOFF2 requires.
+///
+/// Quality 9 compresses nearly as well as the maximum of 11, but is much
+/// faster for fonts that couldn't be subsetted.
+fn compress(data: &[u8]) -> Option<Vec<u8>> {
+    let params = BrotliEncoderParams {
+        mode: BrotliEncoderMode::BROTLI_MODE_FONT,
+        quality: 9,
+        lgwin: 22,
+        size_hint: data.len(),
+        ..Default::default()
+    };
+
+    let mut compressed = vec![];
+    brotli::BrotliCompress(&mut &*data, &mut compressed, &params).ok()?;
+    Some(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_write_base128() {
+        #[track_caller]
+        fn test(value: u32, bytes: &[u8]) {
+            let mut out = vec![];
+            write_base128(&mut out, value);
+            assert_eq!(out, bytes);
+        }
+
+        test(0, &[0x00]);
+        test(63, &[0x3f]);
+        test(128, &[0x81, 0x00]);
+        test(16384, &[0x81, 0x80, 0x00]);
+        test(u32::MAX, &[0x8f, 0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn test_compress() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len() / 10);
+
+        let mut decompressed = vec![];
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -1,9 +1,13 @@
 //! File and package management.
 
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::RwLock;
 
 use ecow::{eco_format, EcoString};
@@ -13,23 +17,90 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use crate::is_ident;
 
 /// The global package-path interner.
-static INTERNER: Lazy<RwLock<Interner>> =
-    Lazy::new(|| RwLock::new(Interner { to_id: HashMap::new(), from_id: Vec::new() }));
+static INTERNER: Lazy<Interner> = Lazy::new(Interner::new);
+
+/// The number of shards of the interner's pair-to-id map.
+const SHARDS: usize = 16;
+
+thread_local! {
+    /// A per-thread copy of the interner's id-to-pair list. Lookups are served
+    /// from here so that threads don't contend on the global lock.
+    static CACHE: RefCell<Pairs> = const { RefCell::new(Pairs::new()) };
+}
 
 /// A package-path interner.
 struct Interner {
-    to_id: HashMap<Pair, FileId>,
-    from_id: Vec<Pair>,
+    /// Maps pairs to ids, sharded by hash so that threads interning different
+    /// pairs rarely contend.
+    to_id: [RwLock<HashMap<Pair, FileId>>; SHARDS],
+    /// Maps ids to pairs. Only appended to, except when resetting.
+    from_id: RwLock<Pairs>,
+    /// The id of the first pair in `from_id`, mirrored to quickly check
+    /// whether a per-thread cache is still current.
+    offset: AtomicU32,
+    /// Hashes pairs to select their shard.
+    hasher: RandomState,
 }
 
 /// An interned pair of a package specification and a path.
 type Pair = &'static (Option<PackageSpec>, VirtualPath);
 
+/// A list of interned pairs, indexed by id.
+///
+/// Ids are never reused, not even after a reset. Instead, a reset moves the
+/// start of the list past all ids handed out so far.
+struct Pairs {
+    /// The id of the first pair in the list.
+    offset: u32,
+    /// The pairs, in order of their ids.
+    list: Vec<Pair>,
+}
+
+impl Pairs {
+    /// Create a new, empty list.
+    const fn new() -> Self {
+        Self { offset: 0, list: Vec::new() }
+    }
+
+    /// The id the next pair will get, if there are ids left.
+    fn next(&self) -> Option<u32> {
+        u32::try_from(self.list.len()).ok()?.checked_add(self.offset)
+    }
+
+    /// The pair with the given id, if it is in the list.
+    fn get(&self, id: FileId) -> Option<Pair> {
+        let index = id.0.checked_sub(self.offset)?;
+        self.list.get(index as usize).copied()
+    }
+}
+
+impl Interner {
+    /// Create a new, empty interner.
+    fn new() -> Self {
+        Self {
+            to_id: std::array::from_fn(|_| RwLock::new(HashMap::new())),
+            from_id: RwLock::new(Pairs::new()),
+            offset: AtomicU32::new(0),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The shard of the pair-to-id map that is responsible for a pair.
+    fn shard(
+        &self,
+        pair: &(Option<PackageSpec>, VirtualPath),
+    ) -> &RwLock<HashMap<Pair, FileId>> {
+        let mut state = self.hasher.build_hasher();
+        pair.hash(&mut state);
+        &self.to_id[state.finish() as usize % SHARDS]
+    }
+}
+
 /// Identifies a file in a project or package.
 ///
 /// This type is globally interned and thus cheap to copy, compare, and hash.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub struct FileId(u16);
+pub struct FileId(u32);
 
 impl FileId {
     /// Create a new interned file specification.
@@ -40,23 +111,50 @@ impl FileId {
     pub fn new(package: Option<PackageSpec>, path: VirtualPath) -> Self {
         // Try to find an existing entry that we can reuse.
         let pair = (package, path);
-        if let Some(&id) = INTERNER.read().unwrap().to_id.get(&pair) {
+        let shard = INTERNER.shard(&pair);
+        if let Some(&id) = shard.read().unwrap().get(&pair) {
             return id;
         }
 
-        let mut interner = INTERNER.write().unwrap();
-        let num = interner.from_id.len().try_into().expect("out of file ids");
+        let mut to_id = shard.write().unwrap();
+        if let Some(&id) = to_id.get(&pair) {
+            return id;
+        }
 
-        // Create a new entry forever by leaking the pair. We can't leak more
-        // than 2^16 pair (and typically will leak a lot less), so its not a
-        // big deal.
-        let id = FileId(num);
+        // Create a new entry forever by leaking the pair. References to it
+        // are handed out with a `'static` lifetime, so it must outlive even a
+        // reset. Projects rarely use more than a few thousand files, so this
+        // is not a big deal.
         let leaked = Box::leak(Box::new(pair));
-        interner.to_id.insert(leaked, id);
-        interner.from_id.push(leaked);
+        let mut from_id = INTERNER.from_id.write().unwrap();
+        let id = FileId(from_id.next().expect("out of file ids"));
+        from_id.list.push(leaked);
+        to_id.insert(leaked, id);
         id
     }
 
+    /// Forget all interned file ids, freeing the interner's lookup tables.
+    ///
+    /// This is meant for long-running embedders that would otherwise
+    /// accumulate ids forever. The interned pairs themselves stay alive, since
+    /// references to them may still be held.
+    ///
+    /// Ids are never reused, so an id created before the reset can't refer to
+    /// another file afterwards. It must not be used anymore though: Resolving
+    /// it panics. Since memoized results can contain ids, comemo's caches must
+    /// be cleared along with the interner (e.g. with `comemo::evict(0)`).
+    pub fn reset() {
+        let mut shards: Vec<_> =
+            INTERNER.to_id.iter().map(|shard| shard.write().unwrap()).collect();
+        let mut from_id = INTERNER.from_id.write().unwrap();
+        for shard in &mut shards {
+            shard.clear();
+        }
+        from_id.offset = from_id.next().expect("out of file ids");
+        from_id.list.clear();
+        INTERNER.offset.store(from_id.offset, Ordering::Release);
+    }
+
     /// The package the file resides in, if any.
     pub fn package(&self) -> Option<&'static PackageSpec> {
         self.pair().0.as_ref()
@@ -74,18 +172,37 @@ impl FileId {
     }
 
     /// Construct from a raw number.
-    pub(crate) const fn from_raw(v: u16) -> Self {
+    pub(crate) const fn from_raw(v: u32) -> Self {
         Self(v)
     }
 
     /// Extract the raw underlying number.
-    pub(crate) const fn into_raw(self) -> u16 {
+    pub(crate) const fn into_raw(self) -> u32 {
         self.0
     }
 
     /// Get the static pair.
+    ///
+    /// The pair is looked up in the current thread's cache, which is only
+    /// refreshed from the global interner if the id is newer than the cache or
+    /// the interner was reset.
+    #[track_caller]
     fn pair(&self) -> Pair {
-        INTERNER.read().unwrap().from_id[usize::from(self.0)]
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            let current = INTERNER.offset.load(Ordering::Acquire);
+            if cache.offset != current || cache.get(*self).is_none() {
+                let from_id = INTERNER.from_id.read().unwrap();
+                if cache.offset != from_id.offset {
+                    cache.offset = from_id.offset;
+                    cache.list.clear();
+                }
+                let len = cache.list.len();
+                cache.list.extend_from_slice(&from_id.list[len..]);
+            }
+
+            cache.get(*self).expect("file id is stale")
+        })
     }
 }
 
@@ -175,6 +292,19 @@ impl VirtualPath {
         Some(out)
     }
 
+    /// Resolve the virtual path relative to an actual file system root like
+    /// [`resolve`](Self::resolve), but also follow symlinks.
+    ///
+    /// Returns `None` if the path escapes the root lexically or through a
+    /// symlink. Returns the canonical path if it exists and the lexically
+    /// resolved one otherwise.
+    pub fn resolve_confined(&self, root: &Path) -> Option<PathBuf> {
+        let path = self.resolve(root)?;
+        let Ok(canonical) = path.canonicalize() else { return Some(path) };
+        let root = root.canonicalize().ok()?;
+        canonical.starts_with(root).then_some(canonical)
+    }
+
     /// Resolve a path relative to this virtual path.
     pub fn join(&self, path: impl AsRef<Path>) -> Self {
         if let Some(parent) = self.0.parent() {
@@ -325,3 +455,145 @@ impl<'de> Deserialize<'de> for PackageVersion {
         string.parse().map_err(serde::de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::sync::{Barrier, Mutex, PoisonError};
+    use std::thread;
+
+    use super::*;
+
+    /// Held by tests that use ids across a reset or reset the interner, so
+    /// that they don't invalidate each other's ids.
+    static RESET: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_file_id_reset() {
+        let _guard = RESET.lock().unwrap_or_else(PoisonError::into_inner);
+        let spec: PackageSpec = "@test/reset:1.0.0".parse().unwrap();
+        let id = FileId::new(Some(spec.clone()), VirtualPath::new("lib.typ"));
+        let package = id.package();
+        let vpath = id.vpath();
+
+        FileId::reset();
+        assert_eq!(package, Some(&spec));
+        assert_eq!(vpath, &VirtualPath::new("lib.typ"));
+
+        let id = FileId::new(Some(spec.clone()), VirtualPath::new("lib.typ"));
+        assert_eq!(id.package(), Some(&spec));
+        assert_eq!(id.vpath(), &VirtualPath::new("lib.typ"));
+        assert_eq!(id.join("util.typ").vpath(), &VirtualPath::new("util.typ"));
+        assert_eq!(id, FileId::new(Some(spec), VirtualPath::new("/lib.typ")));
+    }
+
+    #[test]
+    fn test_file_id_threads() {
+        let _guard = RESET.lock().unwrap_or_else(PoisonError::into_inner);
+        let paths: Vec<_> = (0..100)
+            .map(|i| VirtualPath::new(format!("threads/{i}.typ")))
+            .collect();
+
+        let ids: Vec<Vec<FileId>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        let ids: Vec<_> = paths
+                            .iter()
+                            .map(|path| FileId::new(None, path.clone()))
+                            .collect();
+                        for (id, path) in ids.iter().zip(&paths) {
+                            assert_eq!(id.vpath(), path);
+                            assert_eq!(id.package(), None);
+                        }
+                        ids
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        for other in &ids[1..] {
+            assert_eq!(other, &ids[0]);
+        }
+
+        // Ids interned on other threads resolve here, too.
+        for (id, path) in ids[0].iter().zip(&paths) {
+            assert_eq!(id.vpath(), path);
+        }
+    }
+
+    #[test]
+    fn test_file_id_stale_after_reset() {
+        let _guard = RESET.lock().unwrap_or_else(PoisonError::into_inner);
+        let stale = FileId::new(None, VirtualPath::new("stale/before.typ"));
+        assert_eq!(stale.vpath(), &VirtualPath::new("stale/before.typ"));
+
+        // Another thread resolves the id before the reset, so that its cache
+        // holds the id, and once more afterwards.
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            let other = s.spawn(|| {
+                assert_eq!(stale.vpath(), &VirtualPath::new("stale/before.typ"));
+                barrier.wait();
+                barrier.wait();
+                panic::catch_unwind(|| stale.vpath()).is_err()
+            });
+
+            barrier.wait();
+            FileId::reset();
+            let fresh = FileId::new(None, VirtualPath::new("stale/after.typ"));
+            assert_ne!(fresh, stale);
+            assert_eq!(fresh.vpath(), &VirtualPath::new("stale/after.typ"));
+            barrier.wait();
+
+            assert!(other.join().unwrap());
+            assert!(panic::catch_unwind(|| stale.vpath()).is_err());
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_confined() {
+        use std::fs;
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let root = dir.join("root");
+        let outside = dir.join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(root.join("sub/main.typ"), "").unwrap();
+        fs::write(outside.join("secret.typ"), "").unwrap();
+
+        let resolve = |path: &str| VirtualPath::new(path).resolve_confined(&root);
+
+        // Regular files and files that don't exist yet.
+        assert_eq!(resolve("sub/main.typ"), Some(root.join("sub/main.typ")));
+        assert_eq!(resolve("new.typ"), Some(root.join("new.typ")));
+
+        // Lexical escapes.
+        assert_eq!(resolve("sub/../sub/main.typ"), Some(root.join("sub/main.typ")));
+        assert_eq!(resolve("../outside/secret.typ"), None);
+        assert_eq!(resolve("sub/../../outside/secret.typ"), None);
+
+        // Relative symlinks, out of the root and within it.
+        symlink("../outside", root.join("out")).unwrap();
+        symlink("sub", root.join("in")).unwrap();
+        assert_eq!(resolve("out/secret.typ"), None);
+        assert_eq!(resolve("in/main.typ"), Some(root.join("sub/main.typ")));
+
+        // Absolute symlinks, out of the root and within it.
+        symlink(outside.join("secret.typ"), root.join("abs-out.typ")).unwrap();
+        symlink(root.join("sub/main.typ"), root.join("abs-in.typ")).unwrap();
+        assert_eq!(resolve("abs-out.typ"), None);
+        assert_eq!(resolve("abs-in.typ"), Some(root.join("sub/main.typ")));
+
+        // Without confinement, symlinks are only resolved lexically.
+        assert_eq!(
+            VirtualPath::new("out/secret.typ").resolve(&root),
+            Some(root.join("out/secret.typ"))
+        );
+    }
+}
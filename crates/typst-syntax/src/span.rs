@@ -36,8 +36,8 @@ impl Span {
     const DETACHED: u64 = 1;
 
     /// Data layout:
-    /// | 16 bits source id | 48 bits number |
-    const BITS: usize = 48;
+    /// | 32 bits source id | 32 bits number |
+    const BITS: usize = 32;
 
     /// Create a new span from a source id and a unique number.
     ///
@@ -74,7 +74,7 @@ impl Span {
         if self.is_detached() {
             return None;
         }
-        let bits = (self.0.get() >> Self::BITS) as u16;
+        let bits = (self.0.get() >> Self::BITS) as u32;
         Some(FileId::from_raw(bits))
     }
 
@@ -137,5 +137,11 @@ mod tests {
         let span = Span::new(id, 10).unwrap();
         assert_eq!(span.id(), Some(id));
         assert_eq!(span.number(), 10);
+
+        let id = FileId::from_raw(u32::MAX);
+        let span = Span::new(id, Span::FULL.end - 1).unwrap();
+        assert_eq!(span.id(), Some(id));
+        assert_eq!(span.number(), Span::FULL.end - 1);
+        assert_eq!(Span::new(id, Span::FULL.end), None);
     }
 }
@@ -1,5 +1,6 @@
 //! Performance timing for Typst.
 
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::io::Write;
 use std::thread::ThreadId;
@@ -165,6 +166,58 @@ macro_rules! timed {
     }};
 }
 
+/// A scope that was recorded from start to end.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    /// The name of the scope.
+    pub name: &'static str,
+    /// The span of code that the scope was recorded in.
+    pub span: Option<Span>,
+    /// The thread the scope was recorded on.
+    pub thread_id: ThreadId,
+    /// When the scope started.
+    pub start: SystemTime,
+    /// When the scope ended.
+    pub end: SystemTime,
+}
+
+impl Measurement {
+    /// How long the scope took.
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// The scopes that were recorded since the events were last cleared, in the
+/// order they started.
+///
+/// Scopes that haven't ended yet are left out.
+pub fn measurements() -> Vec<Measurement> {
+    let recorder = RECORDER.lock();
+    let mut measurements: Vec<Option<Measurement>> = vec![];
+    let mut open = HashMap::new();
+    for event in &recorder.events {
+        match event.kind {
+            EventKind::Start => {
+                open.insert(event.id, (measurements.len(), event.timestamp));
+                measurements.push(None);
+            }
+            EventKind::End => {
+                let Some((index, start)) = open.remove(&event.id) else { continue };
+                measurements[index] = Some(Measurement {
+                    name: event.name,
+                    span: event.span,
+                    thread_id: event.thread_id,
+                    start,
+                    end: event.timestamp,
+                });
+            }
+        }
+    }
+
+    measurements.into_iter().flatten().collect()
+}
+
 /// Export data as JSON for Chrome's tracing tool.
 ///
 /// The `source` function is called for each span to get the source code
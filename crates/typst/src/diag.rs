@@ -148,6 +148,24 @@ pub enum Severity {
     Warning,
 }
 
+/// A group of related warnings that users can treat differently from others,
+/// for example by failing the compilation when one of them is emitted.
+///
+/// Warnings are assigned to a category when they are added to the
+/// [`Tracer`](crate::eval::Tracer). Warnings without a category can only be
+/// treated like all warnings.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WarningCategory {
+    /// Markup that has no effect, like empty strong or emphasized text.
+    Markup,
+    /// Unnecessary renames in imports.
+    Imports,
+    /// Layout that did not converge.
+    Layout,
+    /// Use of something that is deprecated, like a yanked package version.
+    Deprecated,
+}
+
 impl SourceDiagnostic {
     /// Create a new, bare error.
     pub fn error(span: Span, message: impl Into<EcoString>) -> Self {
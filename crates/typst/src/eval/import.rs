@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::diag::{
     bail, error, warning, At, FileError, SourceResult, StrResult, Trace, Tracepoint,
+    WarningCategory,
 };
 use crate::eval::{eval, Eval, Vm};
 use crate::foundations::{Content, Module, Value};
@@ -37,10 +38,13 @@ impl Eval for ast::ModuleImport<'_> {
             if let ast::Expr::Ident(ident) = self.source() {
                 if ident.as_str() == new_name.as_str() {
                     // Warn on `import x as x`
-                    vm.engine.tracer.warn(warning!(
-                        new_name.span(),
-                        "unnecessary import rename to same name",
-                    ));
+                    vm.engine.tracer.warn_as(
+                        WarningCategory::Imports,
+                        warning!(
+                            new_name.span(),
+                            "unnecessary import rename to same name",
+                        ),
+                    );
                 }
             }
 
@@ -72,10 +76,13 @@ impl Eval for ast::ModuleImport<'_> {
                             if renamed_item.original_name().as_str()
                                 == renamed_item.new_name().as_str()
                             {
-                                vm.engine.tracer.warn(warning!(
-                                    renamed_item.new_name().span(),
-                                    "unnecessary import rename to same name",
-                                ));
+                                vm.engine.tracer.warn_as(
+                                    WarningCategory::Imports,
+                                    warning!(
+                                        renamed_item.new_name().span(),
+                                        "unnecessary import rename to same name",
+                                    ),
+                                );
                             }
                         }
 
@@ -1,4 +1,4 @@
-use crate::diag::{warning, SourceResult};
+use crate::diag::{warning, SourceResult, WarningCategory};
 use crate::eval::{Eval, Vm};
 use crate::foundations::{Content, Label, NativeElement, Smart, Unlabellable, Value};
 use crate::math::EquationElem;
@@ -133,7 +133,7 @@ impl Eval for ast::Strong<'_> {
         if body.exprs().next().is_none() {
             vm.engine
                 .tracer
-                .warn(warning!(
+                .warn_as(WarningCategory::Markup, warning!(
                     self.span(), "no text within stars";
                     hint: "using multiple consecutive stars (e.g. **) has no additional effect",
                 ));
@@ -151,7 +151,7 @@ impl Eval for ast::Emph<'_> {
         if body.exprs().next().is_none() {
             vm.engine
                 .tracer
-                .warn(warning!(
+                .warn_as(WarningCategory::Markup, warning!(
                     self.span(), "no text within underscores";
                     hint: "using multiple consecutive underscores (e.g. __) has no additional effect"
                 ));
@@ -2,7 +2,7 @@ use std::collections::HashSet;
 
 use ecow::EcoVec;
 
-use crate::diag::SourceDiagnostic;
+use crate::diag::{SourceDiagnostic, WarningCategory};
 use crate::foundations::Value;
 use crate::syntax::{FileId, Span};
 use crate::util::hash128;
@@ -12,6 +12,7 @@ use crate::util::hash128;
 pub struct Tracer {
     inspected: Option<Span>,
     warnings: EcoVec<SourceDiagnostic>,
+    categories: EcoVec<Option<WarningCategory>>,
     warnings_set: HashSet<u128>,
     delayed: EcoVec<SourceDiagnostic>,
     values: EcoVec<Value>,
@@ -36,6 +37,26 @@ impl Tracer {
         self.warnings
     }
 
+    /// Get the stored warnings along with their categories.
+    pub fn categorized_warnings(
+        self,
+    ) -> impl Iterator<Item = (SourceDiagnostic, Option<WarningCategory>)> {
+        self.warnings.into_iter().zip(self.categories)
+    }
+
+    /// Add a warning unless it is a duplicate.
+    fn push_warning(
+        &mut self,
+        warning: SourceDiagnostic,
+        category: Option<WarningCategory>,
+    ) {
+        let hash = hash128(&(&warning.span, &warning.message));
+        if self.warnings_set.insert(hash) {
+            self.warnings.push(warning);
+            self.categories.push(category);
+        }
+    }
+
     /// Mark a span as inspected. All values observed for this span can be
     /// retrieved via `values` later.
     pub fn inspect(&mut self, span: Span) {
@@ -57,11 +78,12 @@ impl Tracer {
 
     /// Add a warning.
     pub fn warn(&mut self, warning: SourceDiagnostic) {
-        // Check if warning is a duplicate.
-        let hash = hash128(&(&warning.span, &warning.message));
-        if self.warnings_set.insert(hash) {
-            self.warnings.push(warning);
-        }
+        self.push_warning(warning, None);
+    }
+
+    /// Add a warning that belongs to a category.
+    pub fn warn_as(&mut self, category: WarningCategory, warning: SourceDiagnostic) {
+        self.push_warning(warning, Some(category));
     }
 
     /// The inspected span if it is part of the given source file.
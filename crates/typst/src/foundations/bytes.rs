@@ -1,5 +1,5 @@
-use std::borrow::Cow;
 use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
 use std::ops::{Add, AddAssign, Deref};
 use std::sync::Arc;
 
@@ -39,12 +39,21 @@ use crate::foundations::{cast, func, scope, ty, Array, Reflect, Repr, Str, Value
 /// ```
 #[ty(scope)]
 #[derive(Clone, Hash, Eq, PartialEq)]
-pub struct Bytes(Arc<Prehashed<Cow<'static, [u8]>>>);
+pub struct Bytes(Arc<Prehashed<Inner>>);
 
 impl Bytes {
     /// Create a buffer from a static byte slice.
     pub fn from_static(slice: &'static [u8]) -> Self {
-        Self(Arc::new(Prehashed::new(Cow::Borrowed(slice))))
+        Self(Arc::new(Prehashed::new(Inner::Static(slice))))
+    }
+
+    /// Create a buffer from data that is owned elsewhere, e.g. a memory-mapped
+    /// file.
+    pub fn from_shared<T>(data: T) -> Self
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        Self(Arc::new(Prehashed::new(Inner::Shared(Arc::new(data)))))
     }
 
     /// Return `true` if the length is 0.
@@ -181,13 +190,13 @@ impl AsRef<[u8]> for Bytes {
 
 impl From<&[u8]> for Bytes {
     fn from(slice: &[u8]) -> Self {
-        Self(Arc::new(Prehashed::new(slice.to_vec().into())))
+        Self(Arc::new(Prehashed::new(Inner::Owned(slice.to_vec()))))
     }
 }
 
 impl From<Vec<u8>> for Bytes {
     fn from(vec: Vec<u8>) -> Self {
-        Self(Arc::new(Prehashed::new(vec.into())))
+        Self(Arc::new(Prehashed::new(Inner::Owned(vec))))
     }
 }
 
@@ -206,9 +215,11 @@ impl AddAssign for Bytes {
             // Nothing to do
         } else if self.is_empty() {
             *self = rhs;
-        } else if Arc::strong_count(&self.0) == 1 && matches!(**self.0, Cow::Owned(_)) {
-            Arc::make_mut(&mut self.0).update(|cow| {
-                cow.to_mut().extend_from_slice(&rhs);
+        } else if Arc::strong_count(&self.0) == 1 && matches!(**self.0, Inner::Owned(_)) {
+            Arc::make_mut(&mut self.0).update(|inner| {
+                if let Inner::Owned(vec) = inner {
+                    vec.extend_from_slice(&rhs);
+                }
             })
         } else {
             *self = Self::from([self.as_slice(), rhs.as_slice()].concat());
@@ -229,6 +240,35 @@ impl Serialize for Bytes {
     }
 }
 
+/// The storage behind a byte buffer.
+#[derive(Clone)]
+enum Inner {
+    /// Bytes that live for the whole program, e.g. embedded assets.
+    Static(&'static [u8]),
+    /// Bytes owned by the buffer.
+    Owned(Vec<u8>),
+    /// Bytes owned by something else.
+    Shared(Arc<dyn AsRef<[u8]> + Send + Sync>),
+}
+
+impl Deref for Inner {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Static(slice) => slice,
+            Self::Owned(vec) => vec,
+            Self::Shared(data) => (**data).as_ref(),
+        }
+    }
+}
+
+impl Hash for Inner {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
 /// A value that can be cast to bytes.
 pub struct ToBytes(Bytes);
 
@@ -310,6 +310,32 @@ impl Datetime {
             .ok_or("unable to get the current date")?)
     }
 
+    /// Returns the current date and time.
+    ///
+    /// Within one compilation, this always returns the same point in time.
+    ///
+    /// ```example
+    /// Compiled at
+    /// #datetime.now().display().
+    /// ```
+    #[func]
+    pub fn now(
+        /// The engine.
+        engine: &mut Engine,
+        /// The timezone in which to determine the current time. Either an
+        /// offset in hours from UTC or the name of a timezone from the IANA
+        /// database, like `{"Europe/Berlin"}`. If set to `{auto}`, the local
+        /// timezone will be used.
+        #[named]
+        #[default]
+        timezone: Smart<Timezone>,
+    ) -> StrResult<Datetime> {
+        Ok(engine
+            .world
+            .now(timezone.as_custom())
+            .ok_or("unable to get the current date and time")?)
+    }
+
     /// Displays the datetime in a specified format.
     ///
     /// Depending on whether you have defined just a date, a time or both, the
@@ -491,6 +517,25 @@ impl Sub for Datetime {
     }
 }
 
+/// A timezone in which the current date and time can be requested.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Timezone {
+    /// A fixed offset from UTC in hours.
+    Offset(i64),
+    /// The name of a timezone from the IANA database, like `Europe/Berlin`.
+    Named(EcoString),
+}
+
+cast! {
+    Timezone,
+    self => match self {
+        Self::Offset(v) => v.into_value(),
+        Self::Named(v) => v.into_value(),
+    },
+    v: i64 => Self::Offset(v),
+    v: EcoString => Self::Named(v),
+}
+
 /// A format in which a datetime can be displayed.
 pub struct DisplayPattern(Str, format_description::OwnedFormatItem);
 
@@ -474,25 +474,32 @@ fn collect<'a>(
                     region,
                     SmartQuoteElem::alternative_in(styles),
                 );
-                let peeked = iter.peek().and_then(|child| {
-                    let child = if let Some((child, _)) = child.to_styled() {
-                        child
-                    } else {
-                        child
-                    };
-                    if let Some(elem) = child.to::<TextElem>() {
-                        elem.text().chars().next()
-                    } else if child.is::<SmartQuoteElem>() {
-                        Some('"')
-                    } else if child.is::<SpaceElem>()
-                        || child.is::<HElem>()
-                        || child.is::<LinebreakElem>()
-                    {
-                        Some(SPACING_REPLACE)
-                    } else {
-                        Some(OBJ_REPLACE)
-                    }
-                });
+                // Markers of located elements produce no text, so the quote
+                // is decided by what follows them.
+                let peeked = iter
+                    .clone()
+                    .map(|child| {
+                        if let Some((child, _)) = child.to_styled() {
+                            child
+                        } else {
+                            child
+                        }
+                    })
+                    .find(|child| !child.is::<MetaElem>())
+                    .and_then(|child| {
+                        if let Some(elem) = child.to::<TextElem>() {
+                            elem.text().chars().next()
+                        } else if child.is::<SmartQuoteElem>() {
+                            Some('"')
+                        } else if child.is::<SpaceElem>()
+                            || child.is::<HElem>()
+                            || child.is::<LinebreakElem>()
+                        {
+                            Some(SPACING_REPLACE)
+                        } else {
+                            Some(OBJ_REPLACE)
+                        }
+                    });
 
                 full.push_str(quoter.quote(&quotes, elem.double(styles), peeked));
             } else {
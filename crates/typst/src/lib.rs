@@ -63,11 +63,11 @@ use comemo::{Prehashed, Track, Tracked, Validate};
 use ecow::{EcoString, EcoVec};
 use typst_timing::{timed, TimingScope};
 
-use crate::diag::{warning, FileResult, SourceDiagnostic, SourceResult};
+use crate::diag::{warning, FileResult, SourceDiagnostic, SourceResult, WarningCategory};
 use crate::engine::{Engine, Route};
 use crate::eval::Tracer;
 use crate::foundations::{
-    Array, Bytes, Content, Datetime, Dict, Module, Scope, StyleChain, Styles,
+    Array, Bytes, Content, Datetime, Dict, Module, Scope, StyleChain, Styles, Timezone,
 };
 use crate::introspection::{Introspector, Locator};
 use crate::layout::{Align, Dir, LayoutRoot};
@@ -146,10 +146,13 @@ fn typeset(
         }
 
         if iter >= 5 {
-            tracer.warn(warning!(
-                Span::detached(), "layout did not converge within 5 attempts";
-                hint: "check if any states or queries are updating themselves"
-            ));
+            tracer.warn_as(
+                WarningCategory::Layout,
+                warning!(
+                    Span::detached(), "layout did not converge within 5 attempts";
+                    hint: "check if any states or queries are updating themselves"
+                ),
+            );
             break;
         }
     }
@@ -219,6 +222,20 @@ pub trait World {
     /// return an error.
     fn today(&self, offset: Option<i64>) -> Option<Datetime>;
 
+    /// Get the current date and time.
+    ///
+    /// If no timezone is specified, the local time should be chosen.
+    /// Otherwise, the time in the given timezone should be chosen. Like
+    /// [`today`](Self::today), this should return the same point in time
+    /// throughout one compilation.
+    ///
+    /// This function is optional to implement. If it returns `None`, Typst's
+    /// `datetime.now` function will return an error.
+    fn now(&self, timezone: Option<Timezone>) -> Option<Datetime> {
+        let _ = timezone;
+        None
+    }
+
     /// A list of all available packages and optionally descriptions for them.
     ///
     /// This function is optional to implement. It enhances the user experience
@@ -5,11 +5,12 @@ use crate::engine::Engine;
 use crate::foundations::{
     cast, elem, scope, Array, Content, Fold, NativeElement, Smart, StyleChain,
 };
+use crate::introspection::Locatable;
 use crate::layout::{
     Align, Axes, BlockElem, Celled, Em, Fragment, GridLayouter, HAlign, Layout, Length,
     Regions, Sizing, Spacing, VAlign,
 };
-use crate::model::{Numbering, NumberingPattern, ParElem};
+use crate::model::{CellElem, Numbering, NumberingPattern, ParElem};
 use crate::text::TextElem;
 
 /// A numbered list.
@@ -68,7 +69,7 @@ use crate::text::TextElem;
 /// Enumeration items can contain multiple paragraphs and other block-level
 /// content. All content that is indented more than an item's marker becomes
 /// part of that item.
-#[elem(scope, title = "Numbered List", Layout)]
+#[elem(scope, title = "Numbered List", Locatable, Layout)]
 pub struct EnumElem {
     /// If this is `{false}`, the items are spaced apart with
     /// [enum spacing]($enum.spacing). If it is `{true}`, they use normal
@@ -237,7 +238,7 @@ impl Layout for EnumElem {
         // relation to the item it refers to.
         let number_align = self.number_align(styles);
 
-        for item in self.children() {
+        for (i, item) in self.children().iter().enumerate() {
             number = item.number(styles).unwrap_or(number);
 
             let resolved = if full {
@@ -260,9 +261,15 @@ impl Layout for EnumElem {
                 resolved.aligned(number_align).styled(TextElem::set_overhang(false));
 
             cells.push(Content::empty());
-            cells.push(resolved);
+            cells.push(CellElem::new(i, resolved).with_marker(true).pack());
             cells.push(Content::empty());
-            cells.push(item.body().clone().styled(Self::set_parents(Parent(number))));
+            cells.push(
+                CellElem::new(
+                    i,
+                    item.body().clone().styled(Self::set_parents(Parent(number))),
+                )
+                .pack(),
+            );
             number = number.saturating_add(1);
         }
 
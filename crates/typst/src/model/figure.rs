@@ -134,6 +134,20 @@ pub struct FigureElem {
     /// The figure's caption.
     pub caption: Option<FigureCaption>,
 
+    /// A text describing the figure for readers who can't see it.
+    ///
+    /// Exported PDFs hand this description to assistive technology like
+    /// screen readers.
+    ///
+    /// ```example
+    /// #figure(
+    ///   circle(radius: 10pt),
+    ///   caption: [A circle],
+    ///   alt: "A small black disk",
+    /// )
+    /// ```
+    pub alt: Option<EcoString>,
+
     /// The kind of figure this is.
     ///
     /// All figures of the same kind share a common counter.
@@ -289,6 +303,9 @@ impl Synthesize for FigureElem {
 
         self.push_placement(self.placement(styles));
         self.push_caption(caption);
+        if let Some(alt) = self.alt(styles) {
+            self.push_alt(Some(alt));
+        }
         self.push_kind(Smart::Custom(kind));
         self.push_supplement(Smart::Custom(supplement.map(Supplement::Content)));
         self.push_numbering(numbering);
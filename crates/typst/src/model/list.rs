@@ -4,11 +4,12 @@ use crate::foundations::{
     cast, elem, scope, Array, Content, Fold, Func, NativeElement, Smart, StyleChain,
     Value,
 };
+use crate::introspection::Locatable;
 use crate::layout::{
     Axes, BlockElem, Celled, Em, Fragment, GridLayouter, HAlign, Layout, Length, Regions,
     Sizing, Spacing, VAlign,
 };
-use crate::model::ParElem;
+use crate::model::{CellElem, ParElem};
 use crate::text::TextElem;
 
 /// A bullet list.
@@ -42,7 +43,7 @@ use crate::text::TextElem;
 /// followed by a space to create a list item. A list item can contain multiple
 /// paragraphs and other block-level content. All content that is indented
 /// more than an item's marker becomes part of that item.
-#[elem(scope, title = "Bullet List", Layout)]
+#[elem(scope, title = "Bullet List", Locatable, Layout)]
 pub struct ListElem {
     /// If this is `{false}`, the items are spaced apart with
     /// [list spacing]($list.spacing). If it is `{true}`, they use normal
@@ -159,11 +160,14 @@ impl Layout for ListElem {
             .aligned(HAlign::Start + VAlign::Top);
 
         let mut cells = vec![];
-        for item in self.children() {
+        for (i, item) in self.children().iter().enumerate() {
             cells.push(Content::empty());
-            cells.push(marker.clone());
+            cells.push(CellElem::new(i, marker.clone()).with_marker(true).pack());
             cells.push(Content::empty());
-            cells.push(item.body().clone().styled(Self::set_depth(Depth)));
+            cells.push(
+                CellElem::new(i, item.body().clone().styled(Self::set_depth(Depth)))
+                    .pack(),
+            );
         }
 
         let fill = Celled::Value(None);
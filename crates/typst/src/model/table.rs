@@ -1,6 +1,7 @@
 use crate::diag::SourceResult;
 use crate::engine::Engine;
-use crate::foundations::{elem, Content, NativeElement, Smart, StyleChain};
+use crate::foundations::{elem, Content, NativeElement, Show, Smart, StyleChain};
+use crate::introspection::Locatable;
 use crate::layout::{
     apply_align_inset_to_cells, Abs, Align, Axes, Celled, Fragment, GridLayouter, Layout,
     Length, Regions, Rel, Sides, TrackSizings,
@@ -39,7 +40,7 @@ use crate::visualize::{Paint, Stroke};
 ///   [$a$: edge length]
 /// )
 /// ```
-#[elem(Layout, LocalName, Figurable)]
+#[elem(Locatable, Layout, LocalName, Figurable)]
 pub struct TableElem {
     /// The column sizes. See the [grid documentation]($grid) for more
     /// information on track sizing.
@@ -171,8 +172,14 @@ impl Layout for TableElem {
 
         let tracks = Axes::new(columns.0.as_slice(), rows.0.as_slice());
         let gutter = Axes::new(column_gutter.0.as_slice(), row_gutter.0.as_slice());
-        let cells =
-            apply_align_inset_to_cells(engine, &tracks, self.children(), align, inset)?;
+        let cols = tracks.x.len().max(1);
+        let children: Vec<_> = self
+            .children()
+            .iter()
+            .enumerate()
+            .map(|(i, child)| CellElem::new(i / cols, child.clone()).pack())
+            .collect();
+        let cells = apply_align_inset_to_cells(engine, &tracks, &children, align, inset)?;
 
         // Prepare grid layout by unifying content and gutter tracks.
         let layouter = GridLayouter::new(
@@ -227,3 +234,28 @@ impl LocalName for TableElem {
 }
 
 impl Figurable for TableElem {}
+
+/// A cell of a table or a part of a list item.
+///
+/// Tables and lists wrap their cells into this element, so that exporters can
+/// recognize the rows that the content of their cells belongs to.
+#[elem(Locatable, Show)]
+pub struct CellElem {
+    /// The row of the table or the index of the list item.
+    #[required]
+    pub row: usize,
+
+    /// The cell's content.
+    #[required]
+    pub body: Content,
+
+    /// Whether this is the marker or term of a list item rather than its body.
+    #[default(false)]
+    pub marker: bool,
+}
+
+impl Show for CellElem {
+    fn show(&self, _: &mut Engine, _: StyleChain) -> SourceResult<Content> {
+        Ok(self.body().clone())
+    }
+}
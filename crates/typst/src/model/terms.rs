@@ -3,10 +3,11 @@ use crate::engine::Engine;
 use crate::foundations::{
     cast, elem, scope, Array, Content, NativeElement, Smart, StyleChain,
 };
+use crate::introspection::Locatable;
 use crate::layout::{
     BlockElem, Em, Fragment, HElem, Layout, Length, Regions, Spacing, VElem,
 };
-use crate::model::ParElem;
+use crate::model::{CellElem, ParElem};
 use crate::util::Numeric;
 
 /// A list of terms and their descriptions.
@@ -25,7 +26,7 @@ use crate::util::Numeric;
 /// # Syntax
 /// This function also has dedicated syntax: Starting a line with a slash,
 /// followed by a term, a colon and a description creates a term list item.
-#[elem(scope, title = "Term List", Layout)]
+#[elem(scope, title = "Term List", Locatable, Layout)]
 pub struct TermsElem {
     /// If this is `{false}`, the items are spaced apart with
     /// [term list spacing]($terms.spacing). If it is `{true}`, they use normal
@@ -133,9 +134,13 @@ impl Layout for TermsElem {
             if !indent.is_zero() {
                 seq.push(HElem::new(indent.into()).pack());
             }
-            seq.push(child.term().clone().strong());
+            seq.push(
+                CellElem::new(i, child.term().clone().strong())
+                    .with_marker(true)
+                    .pack(),
+            );
             seq.push((*separator).clone());
-            seq.push(child.description().clone());
+            seq.push(CellElem::new(i, child.description().clone()).pack());
         }
 
         Content::sequence(seq)
@@ -283,6 +283,12 @@
 #test(datetime.today(offset: auto).display(), "1970-01-01")
 #test(datetime.today(offset: 2).display(), "1970-01-01")
 
+// Test now
+#test(datetime.now().display(), "1970-01-01 00:00:00")
+#test(datetime.now(timezone: auto).hour(), 0)
+#test(datetime.now(timezone: 2).minute(), 0)
+#test(datetime.now(timezone: "Europe/Berlin").second(), 0)
+
 ---
 // Error: 10-12 at least one of date or time must be fully specified
 #datetime()